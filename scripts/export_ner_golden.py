@@ -0,0 +1,29 @@
+"""Writes the golden logits `test_ner` compares the ONNX NER model against.
+
+The logits come from the PyTorch model `resources/roberta-ner.onnx` was exported from,
+for the same texts as the test, padded to the longest one like `encode` does.
+
+    pip install torch transformers numpy
+    python scripts/export_ner_golden.py
+"""
+
+import numpy as np
+import torch
+from transformers import AutoModelForTokenClassification, AutoTokenizer
+
+MODEL = "xlm-roberta-large-finetuned-conll03-english"
+TEXTS = [
+    "HuggingFace is a company based in Paris and New York",
+    "I'm Waner and work for Microsoft from Brazil",
+]
+OUTPUT = "resources/roberta-ner.logits.npy"
+
+tokenizer = AutoTokenizer.from_pretrained(MODEL)
+model = AutoModelForTokenClassification.from_pretrained(MODEL).eval()
+
+inputs = tokenizer(TEXTS, padding=True, return_tensors="pt")
+with torch.no_grad():
+    logits = model(**inputs).logits
+
+np.save(OUTPUT, logits.numpy().astype(np.float32))
+print(f"wrote {OUTPUT} {tuple(logits.shape)}")
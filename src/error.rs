@@ -0,0 +1,203 @@
+use std::path::{Path, PathBuf};
+
+use onnxruntime::OrtError;
+use rust_bert::RustBertError;
+use rust_tokenizers::error::TokenizerError;
+
+/// Errors raised while loading or running the models of this crate.
+///
+/// Every variant names the model file or tokenizer it was working with.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("onnx runtime failed for model `{}`: {source}", path.display())]
+    Ort {
+        path: PathBuf,
+        #[source]
+        source: OrtError,
+    },
+    #[error("model `{}` did not return a {expected} output", path.display())]
+    MissingOutput { path: PathBuf, expected: String },
+    #[error("tokenizer `{name}` failed: {source}")]
+    Tokenizer {
+        name: String,
+        #[source]
+        source: tokenizers::Error,
+    },
+    #[error("tokenizer `{name}` is not available offline, looked in {searched:?}")]
+    TokenizerNotFound {
+        name: String,
+        searched: Vec<PathBuf>,
+    },
+    #[error("rust_tokenizers tokenizer `{name}` failed: {source}")]
+    RustTokenizer {
+        name: String,
+        #[source]
+        source: TokenizerError,
+    },
+    #[error("rust-bert model `{name}` failed: {source}")]
+    RustBert {
+        name: String,
+        #[source]
+        source: RustBertError,
+    },
+    #[error("could not fetch `{resource}`: {source}")]
+    Download {
+        resource: String,
+        #[source]
+        source: cached_path::Error,
+    },
+    #[error("no home directory to cache `{resource}` into")]
+    NoCacheDirectory { resource: String },
+    #[error("invalid model manifest `{}`: {source}", path.display())]
+    Manifest {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("model `{name}` is not declared in the manifest")]
+    UnknownModel { name: String },
+    #[error("model `{model}` does not declare a {field}")]
+    MissingConfig { model: String, field: String },
+    #[error("model `{}` has an input `{input}` that cannot be fed from the tokenizer", path.display())]
+    UnknownInput { path: PathBuf, input: String },
+    #[error("model `{}` expects {found} for input `{input}`, the tokenizer produces Int64", path.display())]
+    InputType {
+        path: PathBuf,
+        input: String,
+        found: String,
+    },
+    #[error("model `{}` expects input `{input}` with shape {expected:?}, got {found:?}", path.display())]
+    InputShape {
+        path: PathBuf,
+        input: String,
+        expected: Vec<Option<u32>>,
+        found: Vec<usize>,
+    },
+    #[error("invalid label map in `{}`: {reason}", path.display())]
+    InvalidLabels { path: PathBuf, reason: String },
+    #[error("model `{}` has {labels} labels but returns {classes} classes", path.display())]
+    LabelCount {
+        path: PathBuf,
+        labels: usize,
+        classes: usize,
+    },
+    #[error("invalid index file `{}`: {reason}", path.display())]
+    InvalidIndex { path: PathBuf, reason: String },
+    #[error("invalid npy data in `{}`: {reason}", path.display())]
+    InvalidNpy { path: PathBuf, reason: String },
+    #[error("cannot train a quantizer: {reason}")]
+    Quantizer { reason: String },
+    #[error("the temperature must be positive, not {temperature}")]
+    Temperature { temperature: f32 },
+    #[error("expected {expected} values, found {found}")]
+    Dimension { expected: usize, found: usize },
+    #[error("expected a tensor of rank {expected}, found shape {found:?}")]
+    Rank { expected: usize, found: Vec<usize> },
+    #[error("could not read `{}`: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub(crate) fn ort(path: &Path) -> impl FnOnce(OrtError) -> Error + '_ {
+        move |source| Error::Ort {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    pub(crate) fn tokenizer(name: &str) -> impl FnOnce(tokenizers::Error) -> Error + '_ {
+        move |source| Error::Tokenizer {
+            name: name.to_string(),
+            source,
+        }
+    }
+
+    pub(crate) fn rust_tokenizer(name: &str) -> impl FnOnce(TokenizerError) -> Error + '_ {
+        move |source| Error::RustTokenizer {
+            name: name.to_string(),
+            source,
+        }
+    }
+
+    pub(crate) fn rust_bert(name: &str) -> impl FnOnce(RustBertError) -> Error + '_ {
+        move |source| Error::RustBert {
+            name: name.to_string(),
+            source,
+        }
+    }
+
+    pub(crate) fn download(resource: &str) -> impl FnOnce(cached_path::Error) -> Error + '_ {
+        move |source| Error::Download {
+            resource: resource.to_string(),
+            source,
+        }
+    }
+
+    pub(crate) fn io(path: &Path) -> impl FnOnce(std::io::Error) -> Error + '_ {
+        move |source| Error::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    pub(crate) fn manifest(path: &Path) -> impl FnOnce(serde_json::Error) -> Error + '_ {
+        move |source| Error::Manifest {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    pub(crate) fn missing_config(model: &str, field: &str) -> Error {
+        Error::MissingConfig {
+            model: model.to_string(),
+            field: field.to_string(),
+        }
+    }
+
+    pub(crate) fn invalid_index(path: &Path, reason: &str) -> Error {
+        Error::InvalidIndex {
+            path: path.to_path_buf(),
+            reason: reason.to_string(),
+        }
+    }
+
+    pub(crate) fn invalid_npy(path: &Path, reason: &str) -> Error {
+        Error::InvalidNpy {
+            path: path.to_path_buf(),
+            reason: reason.to_string(),
+        }
+    }
+
+    pub(crate) fn missing_output(path: &Path, expected: &str) -> Error {
+        Error::MissingOutput {
+            path: path.to_path_buf(),
+            expected: expected.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_names_the_model() {
+        let path = Path::new("resources/missing.onnx");
+        let error = std::fs::metadata(path)
+            .map_err(Error::io(path))
+            .unwrap_err();
+        assert!(error.to_string().contains("resources/missing.onnx"));
+
+        let error = Error::missing_output(path, "float");
+        assert_eq!(
+            error.to_string(),
+            "model `resources/missing.onnx` did not return a float output"
+        );
+    }
+}
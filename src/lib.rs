@@ -1,3 +1,6 @@
+pub mod error;
 pub mod models;
 pub mod tokens;
 pub mod utilities;
+
+pub use error::{Error, Result};
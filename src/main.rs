@@ -1,18 +1,12 @@
-mod utilities;
 use colored::Colorize;
 
-mod models;
-
-mod tokens;
-
-use sandbox_rust::models::{
-    xlm_roberta_onnx::{self, predict_sentiment},
-    xlm_roberta_rustbert,
-};
+use sandbox_rust::models::ner::{NerBackend, NerBackendKind, NerConfig, NerPipeline};
+use sandbox_rust::models::parity::check_parity;
+use sandbox_rust::models::xlm_roberta_onnx::SentimentClassifier;
+use sandbox_rust::timeit;
+use sandbox_rust::utilities::tokens::{bench_tonizers, generate_random_tokens};
+use sandbox_rust::utilities::vec_array::ParityTolerance;
 use terminal_menu::mut_menu;
-use utilities::tokens::{bench_tonizers, generate_random_tokens};
-
-use crate::tokens::bert_rustbert;
 
 fn main() {
     use terminal_menu::{button, label, menu, run};
@@ -22,14 +16,16 @@ fn main() {
         label("---------------"),
         button("ner_models"),
         button("others"),
+        button("parity"),
         button("exit"),
     ]);
     run(&menu);
     let mm = mut_menu(&menu);
 
     match mm.selected_item_name() {
-        "onnx" => ner_models(),
+        "ner_models" => ner_models(),
         "others" => other_models(),
+        "parity" => parity(),
         i => println!("Menu item {i} not found."),
     }
 }
@@ -42,49 +38,41 @@ fn ner_models() {
         "My name is Mario and I live in Canada.",
     ];
 
-    {
-        println!("{}", "NER Using BERT".bold().blue());
-
-        let token_classification_model = bert_rustbert::build_model().unwrap();
-
-        timeit!(token_classification_model.predict(&input));
-
-        let token_outputs = token_classification_model.predict(&input);
-        for token in token_outputs {
-            println!("{token:?}");
-        }
-    }
+    let backends = [
+        ("NER Using BERT", NerBackendKind::BertRustBert),
+        (
+            "NER Using Roberta rust_bert",
+            NerBackendKind::XlmRobertaRustBert,
+        ),
+        ("NER Using Roberta onnx", NerBackendKind::XlmRobertaOnnx),
+    ];
 
-    {
-        println!("{}", "NER Using Roberta rust_bert".bold().blue());
+    for (title, backend) in backends {
+        println!("{}", title.bold().blue());
 
-        let token_classification_model = xlm_roberta_rustbert::build_model().unwrap();
+        let token_classification_model = NerPipeline::load(&NerConfig::new(backend)).unwrap();
 
-        timeit!(token_classification_model.predict(&input));
+        timeit!(token_classification_model.extract(&input).unwrap());
 
-        let token_outputs = token_classification_model.predict(&input);
+        let token_outputs = token_classification_model.extract(&input).unwrap();
 
         for token in token_outputs {
             println!("{token:?}");
         }
     }
+}
 
-    {
-        println!("{}", "NER Using Roberta onnx".bold().blue());
-
-        let token_classification_model = xlm_roberta_onnx::build_model();
-
-        timeit!(xlm_roberta_onnx::predict(
-            &input,
-            &token_classification_model
-        ));
-
-        let token_outputs = xlm_roberta_onnx::predict(&input, &token_classification_model);
+fn parity() {
+    let input = [
+        "My name is Amélie. I live in Москва.",
+        "Chongqing is a city in China.",
+        "Meu nome é Waner e moro no Brasil.",
+        "My name is Mario and I live in Canada.",
+    ];
 
-        for token in token_outputs {
-            println!("{token:?}");
-        }
-    }
+    println!("{}", "Roberta rust_bert vs onnx logits".bold().blue());
+    let report = check_parity(&input, ParityTolerance::default()).unwrap();
+    println!("{report}");
 }
 
 fn other_models() {
@@ -100,15 +88,17 @@ fn other_models() {
     {
         let texts = vec!["You are awesome", "You are bad"];
 
-        timeit!(predict_sentiment(&texts));
+        let classifier = SentimentClassifier::build_model().unwrap();
+
+        timeit!(classifier.predict(&texts).unwrap());
 
-        let responses = predict_sentiment(&texts);
-        let res_positive = responses.get(0).unwrap();
-        let res_negative = responses.get(1).unwrap();
+        let responses = classifier.predict(&texts).unwrap();
+        let res_positive = responses[0];
+        let res_negative = responses[1];
 
-        assert!(res_positive[0] < res_positive[1]);
+        assert!(res_positive.negative < res_positive.positive);
         println!("{} {:?}", texts[0], res_positive);
-        assert!(res_negative[0] > res_negative[1]);
+        assert!(res_negative.negative > res_negative.positive);
         println!("{} {:?}", texts[1], res_negative);
     }
 }
@@ -0,0 +1,133 @@
+use tokenizers::Encoding;
+
+use crate::models::config::Batching;
+use crate::tokens::bert_roberta_tokenizers::{to_embeddings, Embeddings};
+use crate::{Error, Result};
+
+/// It groups inputs of similar token length into batches, shortest first
+///
+/// A batch is closed when it reaches `max_batch_size` inputs or when its padded size,
+/// the number of inputs times the longest one, would exceed `max_tokens`. An input longer
+/// than the token budget on its own gets a batch of its own.
+///
+/// Arguments:
+///
+/// * `lengths`: The token length of every input.
+/// * `batching`: The maximum batch size and token budget.
+///
+/// Returns:
+///
+/// The input indices of every batch
+#[must_use]
+pub fn plan_batches(lengths: &[usize], batching: Batching) -> Vec<Vec<usize>> {
+    let mut order: Vec<usize> = (0..lengths.len()).collect();
+    order.sort_by_key(|&index| lengths[index]);
+
+    let mut batches: Vec<Vec<usize>> = Vec::new();
+    let mut current: Vec<usize> = Vec::new();
+
+    for index in order {
+        // Inputs are sorted, so the new one is the longest of the batch
+        let padded = (current.len() + 1) * lengths[index];
+        if !current.is_empty()
+            && (current.len() == batching.max_batch_size.get() || padded > batching.max_tokens)
+        {
+            batches.push(std::mem::take(&mut current));
+        }
+        current.push(index);
+    }
+
+    if !current.is_empty() {
+        batches.push(current);
+    }
+    batches
+}
+
+/// It runs encoded inputs through a model in length-bucketed batches
+///
+/// Arguments:
+///
+/// * `encodings`: The unpadded encodings of the inputs.
+/// * `batching`: The maximum batch size and token budget.
+/// * `run`: Runs one padded batch and returns one result per row.
+///
+/// Returns:
+///
+/// One result per encoding, in the original order, or a `Dimension` error when `run`
+/// returns another number of rows than its batch holds
+pub fn run_batches<T, F>(encodings: &[Encoding], batching: Batching, mut run: F) -> Result<Vec<T>>
+where
+    F: FnMut(Embeddings) -> Result<Vec<T>>,
+{
+    let lengths: Vec<usize> = encodings
+        .iter()
+        .map(|encoding| encoding.get_ids().len())
+        .collect();
+
+    let mut results: Vec<Option<T>> = (0..encodings.len()).map(|_| None).collect();
+
+    for batch in plan_batches(&lengths, batching) {
+        let inputs: Vec<&Encoding> = batch.iter().map(|&index| &encodings[index]).collect();
+        let rows = run(to_embeddings(&inputs))?;
+        if rows.len() != batch.len() {
+            return Err(Error::Dimension {
+                expected: batch.len(),
+                found: rows.len(),
+            });
+        }
+        for (index, result) in batch.into_iter().zip(rows) {
+            results[index] = Some(result);
+        }
+    }
+
+    // Every input belongs to exactly one batch, so every slot is filled
+    Ok(results.into_iter().flatten().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroUsize;
+
+    use super::*;
+
+    fn batching(max_batch_size: usize, max_tokens: usize) -> Batching {
+        Batching {
+            max_batch_size: NonZeroUsize::new(max_batch_size).unwrap(),
+            max_tokens,
+        }
+    }
+
+    #[test]
+    fn test_plan_batches_by_length() {
+        let lengths = [40, 3, 5, 4, 38, 6];
+        let batching = batching(3, 80);
+
+        assert_eq!(
+            plan_batches(&lengths, batching),
+            [vec![1, 3, 2], vec![5, 4], vec![0]]
+        );
+    }
+
+    #[test]
+    fn test_long_input_gets_its_own_batch() {
+        let batching = batching(8, 10);
+
+        assert_eq!(plan_batches(&[2, 50, 3], batching), [vec![0, 2], vec![1]]);
+        assert!(plan_batches(&[], batching).is_empty());
+    }
+
+    #[test]
+    fn test_short_model_output_is_an_error() {
+        let encodings = vec![Encoding::default(); 3];
+        let result: Result<Vec<usize>> = run_batches(&encodings, batching(2, 100), |inputs| {
+            Ok(vec![0; inputs.0.nrows() - 1])
+        });
+        assert!(matches!(
+            result,
+            Err(Error::Dimension {
+                expected: 2,
+                found: 1
+            })
+        ));
+    }
+}
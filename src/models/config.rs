@@ -0,0 +1,312 @@
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Result};
+
+/// The manifest shipped with the crate, describing the models under `resources/`.
+const BUILTIN_MANIFEST: &str = include_str!("../../models.json");
+
+/// A list of models that can be loaded without recompiling the crate.
+///
+/// ```
+/// use sandbox_rust::models::config::ModelManifest;
+/// let manifest = ModelManifest::builtin().unwrap();
+/// assert!(manifest.get("roberta-ner").is_ok());
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelManifest {
+    pub models: Vec<ModelConfig>,
+}
+
+/// Everything needed to build the session and tokenizer of one model.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelConfig {
+    pub name: String,
+    pub task: ModelTask,
+    pub backend: ModelBackend,
+    /// Path of the model file, for backends that load one from disk
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+    #[serde(default)]
+    pub tokenizer: Option<TokenizerSource>,
+    /// The label of each output index. The ONNX metadata, or a `<model>.config.json` or
+    /// `config.json` next to the model file, override it when they declare one.
+    #[serde(default)]
+    pub labels: Option<Vec<String>>,
+    #[serde(default)]
+    pub session: SessionOptions,
+    /// Splits long inputs into overlapping windows instead of feeding them whole
+    #[serde(default)]
+    pub window: Option<Windowing>,
+    #[serde(default)]
+    pub batching: Batching,
+    #[serde(default)]
+    pub embedding: EmbeddingOptions,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModelTask {
+    SequenceClassification,
+    TokenClassification,
+    /// Sentence embeddings, pooled from the hidden states of the model
+    FeatureExtraction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModelBackend {
+    Onnx,
+    /// A rust-bert pipeline, downloading its pretrained weights for the given architecture
+    RustBert(RustBertArchitecture),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RustBertArchitecture {
+    Bert,
+    XlmRoberta,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenizerSource {
+    /// A tokenizer name on the HF Hub, such as `bert-base-uncased`
+    Hub(String),
+    /// A local `tokenizer.json` file
+    File(PathBuf),
+}
+
+impl TokenizerSource {
+    /// The name used for this tokenizer in error messages
+    #[must_use]
+    pub fn name(&self) -> String {
+        match self {
+            TokenizerSource::Hub(name) => name.clone(),
+            TokenizerSource::File(path) => path.display().to_string(),
+        }
+    }
+}
+
+/// The sliding windows long inputs are split into before they reach the model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Windowing {
+    /// The number of tokens of a window, special tokens included
+    pub max_length: usize,
+    /// The number of tokens shared by two consecutive windows
+    pub stride: usize,
+    /// How classifiers merge the scores of the windows of a text
+    #[serde(default)]
+    pub aggregation: WindowAggregation,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WindowAggregation {
+    /// The scores of the windows are averaged
+    #[default]
+    Mean,
+    /// The highest score of each class across the windows is kept
+    Max,
+}
+
+/// How many inputs go through the model at once. Inputs are sorted by token length first,
+/// so each batch only pads to its own longest input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Batching {
+    /// At least one input per batch, a manifest declaring 0 is rejected
+    pub max_batch_size: NonZeroUsize,
+    /// The most tokens a batch may hold once padded
+    pub max_tokens: usize,
+}
+
+impl Default for Batching {
+    fn default() -> Self {
+        Batching {
+            max_batch_size: NonZeroUsize::new(32).unwrap(),
+            max_tokens: 8192,
+        }
+    }
+}
+
+/// How a feature extraction model turns token hidden states into one vector per text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EmbeddingOptions {
+    pub pooling: Pooling,
+    /// Scales every embedding to unit length, so the dot product equals the cosine
+    pub normalize: bool,
+}
+
+impl Default for EmbeddingOptions {
+    fn default() -> Self {
+        EmbeddingOptions {
+            pooling: Pooling::Mean,
+            normalize: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Pooling {
+    /// The average of the tokens the attention mask keeps
+    #[default]
+    Mean,
+    /// The hidden state of the first token
+    Cls,
+    /// The highest value of every dimension across the tokens the attention mask keeps
+    Max,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionOptions {
+    pub environment_name: String,
+    pub optimization_level: OptimizationLevel,
+    pub intra_threads: Option<i16>,
+}
+
+impl Default for SessionOptions {
+    fn default() -> Self {
+        SessionOptions {
+            environment_name: "sandbox-rust".to_string(),
+            optimization_level: OptimizationLevel::Basic,
+            intra_threads: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OptimizationLevel {
+    DisableAll,
+    Basic,
+    Extended,
+    All,
+}
+
+impl ModelManifest {
+    /// It parses the manifest bundled with the crate
+    pub fn builtin() -> Result<ModelManifest> {
+        serde_json::from_str(BUILTIN_MANIFEST).map_err(Error::manifest(Path::new("models.json")))
+    }
+
+    /// It reads a manifest from a JSON file
+    ///
+    /// Arguments:
+    ///
+    /// * `path`: The path of the JSON manifest.
+    ///
+    /// Returns:
+    ///
+    /// A `ModelManifest`
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<ModelManifest> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path).map_err(Error::io(path))?;
+        serde_json::from_reader(std::io::BufReader::new(file)).map_err(Error::manifest(path))
+    }
+
+    /// It looks up a model by name
+    pub fn get(&self, name: &str) -> Result<&ModelConfig> {
+        self.models
+            .iter()
+            .find(|model| model.name == name)
+            .ok_or_else(|| Error::UnknownModel {
+                name: name.to_string(),
+            })
+    }
+}
+
+impl ModelConfig {
+    /// It looks up a model in the manifest bundled with the crate
+    pub fn builtin(name: &str) -> Result<ModelConfig> {
+        ModelManifest::builtin()?.get(name).cloned()
+    }
+
+    /// The model file, or an error naming the model when none is declared
+    pub fn model_path(&self) -> Result<&Path> {
+        self.path
+            .as_deref()
+            .ok_or_else(|| Error::missing_config(&self.name, "path"))
+    }
+
+    /// The tokenizer, or an error naming the model when none is declared
+    pub fn tokenizer_source(&self) -> Result<&TokenizerSource> {
+        self.tokenizer
+            .as_ref()
+            .ok_or_else(|| Error::missing_config(&self.name, "tokenizer"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_manifest() {
+        let manifest = ModelManifest::builtin().unwrap();
+
+        let ner = manifest.get("roberta-ner").unwrap();
+        assert_eq!(ner.task, ModelTask::TokenClassification);
+        assert_eq!(ner.backend, ModelBackend::Onnx);
+        assert_eq!(
+            ner.model_path().unwrap(),
+            Path::new("resources/roberta-ner.onnx")
+        );
+        assert_eq!(ner.labels.as_ref().map(Vec::len), Some(8));
+        assert_eq!(
+            ner.window,
+            Some(Windowing {
+                max_length: 512,
+                stride: 128,
+                aggregation: WindowAggregation::Mean,
+            })
+        );
+
+        let bert = manifest.get("bert-ner").unwrap();
+        assert_eq!(
+            bert.backend,
+            ModelBackend::RustBert(RustBertArchitecture::Bert)
+        );
+        assert!(bert.model_path().is_err());
+        assert!(bert.window.is_none());
+
+        assert!(manifest.get("missing").is_err());
+    }
+
+    #[test]
+    fn test_session_options_default() {
+        let model: ModelConfig = serde_json::from_str(
+            r#"{
+                "name": "classifier",
+                "task": "sequence_classification",
+                "backend": "onnx",
+                "path": "resources/classifier.onnx",
+                "tokenizer": { "file": "resources/tokenizer.json" },
+                "session": { "intra_threads": 2 }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(model.session.optimization_level, OptimizationLevel::Basic);
+        assert_eq!(model.session.intra_threads, Some(2));
+        assert_eq!(model.window, None);
+        assert_eq!(model.batching, Batching::default());
+        assert_eq!(
+            model.tokenizer,
+            Some(TokenizerSource::File("resources/tokenizer.json".into()))
+        );
+    }
+
+    #[test]
+    fn test_zero_batch_size_is_rejected() {
+        let batching: Batching = serde_json::from_str(r#"{ "max_tokens": 512 }"#).unwrap();
+        assert_eq!(batching.max_batch_size.get(), 32);
+
+        assert!(serde_json::from_str::<Batching>(r#"{ "max_batch_size": 0 }"#).is_err());
+    }
+}
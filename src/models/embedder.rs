@@ -0,0 +1,220 @@
+use ndarray::{Array2, ArrayView2, ArrayView3, Axis};
+
+use crate::models::config::{ModelConfig, Pooling};
+use crate::models::onnx::OnnxModel;
+use crate::models::windows::{aggregate_windows, group_windows};
+use crate::tokens::bert_roberta_tokenizers::encode_windows;
+use crate::utilities::retrieval::l2_normalize;
+use crate::utilities::vec_array::{array2_to_vec, view2, view3};
+use crate::{Error, Result};
+
+/// A sentence embedding model that keeps its ONNX session and tokenizer loaded between calls.
+///
+/// It runs a sentence-transformer export and pools its token hidden states into one vector
+/// per text, as declared in the `embedding` options of the manifest entry. Exports that
+/// already return pooled sentence embeddings are used as they are.
+pub struct Embedder {
+    model: OnnxModel,
+}
+
+impl Embedder {
+    /// It loads the `sentence-embed` model declared in the bundled `models.json`
+    ///
+    /// Returns:
+    ///
+    /// An `Embedder`
+    pub fn build_model() -> Result<Embedder> {
+        Embedder::from_config(&ModelConfig::builtin("sentence-embed")?)
+    }
+
+    /// It loads an embedding model from its manifest entry
+    ///
+    /// Arguments:
+    ///
+    /// * `config`: The manifest entry of a feature extraction model.
+    ///
+    /// Returns:
+    ///
+    /// An `Embedder`
+    pub fn from_config(config: &ModelConfig) -> Result<Embedder> {
+        Ok(Embedder {
+            model: OnnxModel::load(config)?,
+        })
+    }
+
+    /// It embeds a batch of texts
+    ///
+    /// Texts longer than the model's window are split, and the embeddings of their windows
+    /// merged with the window aggregation of the manifest entry before normalization.
+    ///
+    /// Arguments:
+    ///
+    /// * `text`: The texts to embed.
+    ///
+    /// Returns:
+    ///
+    /// One row per input text, in the same order
+    pub fn embed<S>(&self, text: &[S]) -> Result<Array2<f32>>
+    where
+        S: AsRef<str>,
+    {
+        let config = self.model.config();
+        let windows = encode_windows(text, self.model.tokenizer())
+            .map_err(|source| self.model.tokenizer_error(source))?;
+
+        let rows =
+            self.model
+                .run_batches(&windows.encodings, |output, attention_mask| {
+                    match output.ndim() {
+                        3 => Ok(array2_to_vec(&pool(
+                            view3(&output)?,
+                            attention_mask.view(),
+                            config.embedding.pooling,
+                        ))),
+                        2 => Ok(array2_to_vec(&view2(&output)?)),
+                        _ => Err(Error::missing_output(
+                            config.model_path()?,
+                            "hidden state or sentence embedding",
+                        )),
+                    }
+                })?;
+
+        let aggregation = config
+            .window
+            .map(|window| window.aggregation)
+            .unwrap_or_default();
+        let rows: Vec<Vec<f32>> = group_windows(&windows.documents, rows)
+            .iter()
+            .map(|windows| aggregate_windows(windows, aggregation))
+            .collect();
+
+        let dimension = rows.first().map_or(0, Vec::len);
+        if let Some(row) = rows.iter().find(|row| row.len() != dimension) {
+            return Err(Error::Dimension {
+                expected: dimension,
+                found: row.len(),
+            });
+        }
+        let mut embeddings =
+            Array2::from_shape_fn((rows.len(), dimension), |(row, column)| rows[row][column]);
+
+        if config.embedding.normalize {
+            l2_normalize(&mut embeddings);
+        }
+        Ok(embeddings)
+    }
+
+    /// It embeds a batch of texts into vectors that can go straight into `retrieval`
+    pub fn embed_vec<S>(&self, text: &[S]) -> Result<Vec<Vec<f32>>>
+    where
+        S: AsRef<str>,
+    {
+        Ok(array2_to_vec(&self.embed(text)?))
+    }
+}
+
+/// It pools the token hidden states of a batch into one vector per input
+///
+/// Arguments:
+///
+/// * `hidden`: The hidden states, shaped `(batch, tokens, dimension)`.
+/// * `attention_mask`: The attention mask of the batch, shaped `(batch, tokens)`.
+/// * `pooling`: How the tokens of an input are combined.
+///
+/// Returns:
+///
+/// The pooled vectors, shaped `(batch, dimension)`
+#[must_use]
+pub fn pool(
+    hidden: ArrayView3<f32>,
+    attention_mask: ArrayView2<i64>,
+    pooling: Pooling,
+) -> Array2<f32> {
+    let (batch, _, dimension) = hidden.dim();
+    let mut pooled = Array2::zeros((batch, dimension));
+
+    for ((tokens, mask), mut row) in hidden
+        .outer_iter()
+        .zip(attention_mask.outer_iter())
+        .zip(pooled.outer_iter_mut())
+    {
+        let kept = tokens
+            .outer_iter()
+            .zip(mask)
+            .filter(|(_, &mask)| mask != 0)
+            .map(|(token, _)| token);
+
+        match pooling {
+            Pooling::Cls => row.assign(&tokens.index_axis(Axis(0), 0)),
+            Pooling::Mean => {
+                let mut count = 0.0;
+                for token in kept {
+                    row += &token;
+                    count += 1.0;
+                }
+                if count > 0.0 {
+                    row /= count;
+                }
+            }
+            Pooling::Max => {
+                let mut first = true;
+                for token in kept {
+                    if first {
+                        row.assign(&token);
+                        first = false;
+                    } else {
+                        row.zip_mut_with(&token, |value, &token| *value = value.max(token));
+                    }
+                }
+            }
+        }
+    }
+
+    pooled
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::array;
+
+    use super::*;
+    use crate::utilities::retrieval::cosine_similarity;
+
+    #[test]
+    fn test_pooling_respects_attention_mask() {
+        let hidden = array![
+            [[1.0, 4.0], [3.0, 2.0], [100.0, 100.0]],
+            [[2.0, 2.0], [0.0, 0.0], [0.0, 0.0]]
+        ];
+        let mask = array![[1, 1, 0], [1, 0, 0]];
+
+        assert_eq!(
+            pool(hidden.view(), mask.view(), Pooling::Mean),
+            array![[2.0, 3.0], [2.0, 2.0]]
+        );
+        assert_eq!(
+            pool(hidden.view(), mask.view(), Pooling::Max),
+            array![[3.0, 4.0], [2.0, 2.0]]
+        );
+        assert_eq!(
+            pool(hidden.view(), mask.view(), Pooling::Cls),
+            array![[1.0, 4.0], [2.0, 2.0]]
+        );
+    }
+
+    #[test]
+    fn test_embedder() {
+        let embedder = Embedder::build_model().unwrap();
+        let embeddings = embedder
+            .embed_vec(&[
+                "The cat sits on the mat",
+                "A cat is sitting on a mat",
+                "Stock markets fell sharply today",
+            ])
+            .unwrap();
+
+        let similar = cosine_similarity(&embeddings[0], &embeddings[1]);
+        let different = cosine_similarity(&embeddings[0], &embeddings[2]);
+        assert!(similar > different);
+    }
+}
@@ -0,0 +1,356 @@
+use ndarray::{Array1, ArrayView2, Axis};
+use tokenizers::tokenizer::Encoding;
+
+use crate::utilities::postprocess::{argmax, compare_scores, softmax};
+
+/// A named entity decoded from token classification logits.
+///
+/// Mirrors the shape of rust-bert's `Entity`, with `start` and `end` given as
+/// character offsets into the input text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entity {
+    pub word: String,
+    pub label: String,
+    pub score: f64,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// How the sub-token predictions of a word are reduced to a single label,
+/// following rust-bert's `LabelAggregationOption`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LabelAggregation {
+    /// The label of the first sub token is assigned to the entire word
+    #[default]
+    First,
+    /// The most frequent sub token label is assigned to the entire word
+    Mode,
+    /// The label with the highest probability averaged over the sub tokens is assigned
+    Average,
+    /// The label of the single most confident sub token is assigned
+    Max,
+}
+
+/// The tokenizer output needed to map token predictions back onto the input text.
+///
+/// `offsets` are byte offsets into the input text, as returned by the `tokenizers` crate.
+pub struct TokenAlignment<'a> {
+    pub offsets: &'a [(usize, usize)],
+    pub special_tokens_mask: &'a [u32],
+    pub word_ids: &'a [Option<u32>],
+}
+
+impl<'a> From<&'a Encoding> for TokenAlignment<'a> {
+    fn from(encoding: &'a Encoding) -> Self {
+        TokenAlignment {
+            offsets: encoding.get_offsets(),
+            special_tokens_mask: encoding.get_special_tokens_mask(),
+            word_ids: encoding.get_word_ids(),
+        }
+    }
+}
+
+/// A whole word with the label its sub tokens were aggregated to.
+struct WordPrediction {
+    label: usize,
+    score: f32,
+    start: usize,
+    end: usize,
+}
+
+/// It turns the logits of one input text into entities, merging sub tokens into words
+/// and B-/I- runs of words into entities
+///
+/// Arguments:
+///
+/// * `text`: The input text the logits were computed for.
+/// * `logits`: One row of label logits per token.
+/// * `alignment`: The offsets, special tokens and word ids of the tokenized text.
+/// * `id_labels`: The label of each logit index.
+/// * `aggregation`: How sub token predictions are reduced to a word label.
+///
+/// Returns:
+///
+/// A vector of `Entity`, in the order they appear in the text.
+pub fn decode_entities<L>(
+    text: &str,
+    logits: ArrayView2<f32>,
+    alignment: &TokenAlignment,
+    id_labels: &[L],
+    aggregation: LabelAggregation,
+) -> Vec<Entity>
+where
+    L: AsRef<str>,
+{
+    let words = group_words(logits, alignment, aggregation);
+
+    let mut entities: Vec<Entity> = Vec::new();
+    let mut current: Option<(String, Vec<&WordPrediction>)> = None;
+
+    for word in &words {
+        let (prefix, entity_type) = split_label(id_labels[word.label].as_ref());
+
+        let continues = match (&current, prefix) {
+            // Labels without a BIO prefix behave like `I-` labels.
+            (Some((current_type, _)), Some('I') | None) => current_type == entity_type,
+            _ => false,
+        };
+
+        if continues {
+            if let Some((_, run)) = current.as_mut() {
+                run.push(word);
+            }
+            continue;
+        }
+
+        if let Some((entity_type, run)) = current.take() {
+            entities.push(build_entity(text, entity_type, &run));
+        }
+        if entity_type != "O" {
+            current = Some((entity_type.to_string(), vec![word]));
+        }
+    }
+
+    if let Some((entity_type, run)) = current.take() {
+        entities.push(build_entity(text, entity_type, &run));
+    }
+
+    entities
+}
+
+fn group_words(
+    logits: ArrayView2<f32>,
+    alignment: &TokenAlignment,
+    aggregation: LabelAggregation,
+) -> Vec<WordPrediction> {
+    let mut words = Vec::new();
+    let mut pieces: Vec<(Array1<f32>, (usize, usize))> = Vec::new();
+    let mut last_word_id = None;
+
+    for (index, token_logits) in logits.outer_iter().enumerate() {
+        let Some(&offset) = alignment.offsets.get(index) else {
+            break;
+        };
+        let is_special = alignment.special_tokens_mask.get(index) == Some(&1);
+        if is_special || offset.0 == offset.1 {
+            continue;
+        }
+
+        let word_id = alignment.word_ids.get(index).copied().flatten();
+        if word_id.is_none() || word_id != last_word_id {
+            if !pieces.is_empty() {
+                words.extend(aggregate_word(&pieces, aggregation));
+                pieces.clear();
+            }
+            last_word_id = word_id;
+        }
+        pieces.push((softmax(&token_logits, Axis(0)), offset));
+    }
+
+    if !pieces.is_empty() {
+        words.extend(aggregate_word(&pieces, aggregation));
+    }
+
+    words
+}
+
+/// It reduces the sub tokens of a word to one label, `None` when their logits held NaN
+fn aggregate_word(
+    pieces: &[(Array1<f32>, (usize, usize))],
+    aggregation: LabelAggregation,
+) -> Option<WordPrediction> {
+    let start = pieces[0].1 .0;
+    let end = pieces[pieces.len() - 1].1 .1;
+
+    let (label, score) = match aggregation {
+        LabelAggregation::First => argmax(pieces[0].0.view())?,
+        LabelAggregation::Max => pieces
+            .iter()
+            .filter_map(|(probabilities, _)| argmax(probabilities.view()))
+            .max_by(|(_, a), (_, b)| compare_scores(*a, *b))?,
+        LabelAggregation::Average => {
+            let mut mean = Array1::zeros(pieces[0].0.len());
+            for (probabilities, _) in pieces {
+                mean.scaled_add(1.0 / pieces.len() as f32, probabilities);
+            }
+            argmax(mean.view())?
+        }
+        LabelAggregation::Mode => {
+            // Votes are kept in first-seen order so ties go to the earliest sub token.
+            let mut votes: Vec<(usize, usize, f32)> = Vec::new();
+            for (label, score) in pieces
+                .iter()
+                .filter_map(|(probabilities, _)| argmax(probabilities.view()))
+            {
+                match votes.iter_mut().find(|(voted, _, _)| *voted == label) {
+                    Some(vote) => {
+                        vote.1 += 1;
+                        vote.2 += score;
+                    }
+                    None => votes.push((label, 1, score)),
+                }
+            }
+            let (label, count, total) =
+                votes
+                    .into_iter()
+                    .reduce(|best, vote| if vote.1 > best.1 { vote } else { best })?;
+            (label, total / count as f32)
+        }
+    };
+
+    Some(WordPrediction {
+        label,
+        score,
+        start,
+        end,
+    })
+}
+
+fn build_entity(text: &str, label: String, run: &[&WordPrediction]) -> Entity {
+    let start = run[0].start;
+    let end = run[run.len() - 1].end;
+    let score = run.iter().map(|word| f64::from(word.score)).sum::<f64>() / run.len() as f64;
+
+    Entity {
+        word: text[start..end].to_string(),
+        label,
+        score,
+        start: text[..start].chars().count(),
+        end: text[..end].chars().count(),
+    }
+}
+
+/// It splits a BIO label such as `B-LOC` into its prefix and entity type
+fn split_label(label: &str) -> (Option<char>, &str) {
+    match label.split_once('-') {
+        Some((prefix, entity_type)) if prefix == "B" || prefix == "I" => {
+            (prefix.chars().next(), entity_type)
+        }
+        _ => (None, label),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::{array, Array2};
+
+    const LABELS: [&str; 5] = ["O", "B-PER", "I-PER", "B-LOC", "I-LOC"];
+
+    fn one_hot(labels: &[usize]) -> Array2<f32> {
+        let mut logits = Array2::zeros((labels.len(), LABELS.len()));
+        for (token, &label) in labels.iter().enumerate() {
+            logits[[token, label]] = 10.0;
+        }
+        logits
+    }
+
+    #[test]
+    fn test_decode_merges_bio_runs_and_sub_tokens() {
+        // <s> My name is Wa ##ner from New York </s>
+        let text = "My name is Waner from New York";
+        let offsets = [
+            (0, 0),
+            (0, 2),
+            (3, 7),
+            (8, 10),
+            (11, 13),
+            (13, 16),
+            (17, 21),
+            (22, 25),
+            (26, 30),
+            (0, 0),
+        ];
+        let special_tokens_mask = [1, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+        let word_ids = [
+            None,
+            Some(0),
+            Some(1),
+            Some(2),
+            Some(3),
+            Some(3),
+            Some(4),
+            Some(5),
+            Some(6),
+            None,
+        ];
+        let logits = one_hot(&[0, 0, 0, 0, 1, 2, 0, 3, 4, 0]);
+        let alignment = TokenAlignment {
+            offsets: &offsets,
+            special_tokens_mask: &special_tokens_mask,
+            word_ids: &word_ids,
+        };
+
+        let entities = decode_entities(
+            text,
+            logits.view(),
+            &alignment,
+            &LABELS,
+            LabelAggregation::First,
+        );
+
+        assert_eq!(entities.len(), 2);
+        assert_eq!(entities[0].word, "Waner");
+        assert_eq!(entities[0].label, "PER");
+        assert_eq!((entities[0].start, entities[0].end), (11, 16));
+        assert_eq!(entities[1].word, "New York");
+        assert_eq!(entities[1].label, "LOC");
+        assert_eq!((entities[1].start, entities[1].end), (22, 30));
+        assert!(entities[1].score > 0.99);
+    }
+
+    #[test]
+    fn test_decode_uses_character_offsets() {
+        let text = "Amélie Москва";
+        let offsets = [(0, 7), (8, 20)];
+        let special_tokens_mask = [0, 0];
+        let word_ids = [Some(0), Some(1)];
+        let logits = one_hot(&[1, 3]);
+        let alignment = TokenAlignment {
+            offsets: &offsets,
+            special_tokens_mask: &special_tokens_mask,
+            word_ids: &word_ids,
+        };
+
+        let entities = decode_entities(
+            text,
+            logits.view(),
+            &alignment,
+            &LABELS,
+            LabelAggregation::First,
+        );
+
+        assert_eq!(entities[0].word, "Amélie");
+        assert_eq!((entities[0].start, entities[0].end), (0, 6));
+        assert_eq!(entities[1].word, "Москва");
+        assert_eq!((entities[1].start, entities[1].end), (7, 13));
+    }
+
+    #[test]
+    fn test_aggregation_strategies() {
+        let text = "Waner";
+        let offsets = [(0, 2), (2, 4), (4, 5)];
+        let special_tokens_mask = [0, 0, 0];
+        let word_ids = [Some(0), Some(0), Some(0)];
+        let logits = array![
+            [0.0, 1.0, 0.0, 0.0, 0.0],
+            [0.0, 0.0, 0.0, 9.0, 0.0],
+            [0.0, 0.0, 0.0, 2.0, 0.0],
+        ];
+        let alignment = TokenAlignment {
+            offsets: &offsets,
+            special_tokens_mask: &special_tokens_mask,
+            word_ids: &word_ids,
+        };
+        let label = |aggregation| {
+            decode_entities(text, logits.view(), &alignment, &LABELS, aggregation)[0]
+                .label
+                .clone()
+        };
+
+        assert_eq!(label(LabelAggregation::First), "PER");
+        assert_eq!(label(LabelAggregation::Mode), "LOC");
+        assert_eq!(label(LabelAggregation::Average), "LOC");
+        assert_eq!(label(LabelAggregation::Max), "LOC");
+    }
+}
@@ -0,0 +1,372 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use onnxruntime::session::Session;
+use serde::Deserialize;
+
+use crate::models::config::ModelConfig;
+use crate::{Error, Result};
+
+/// The metadata key holding the label map, stored as a JSON object like `config.json`'s `id2label`
+pub const ID2LABEL_METADATA_KEY: &str = "id2label";
+
+/// Field number of `metadata_props` in the ONNX `ModelProto` message
+const METADATA_PROPS: u64 = 14;
+/// Field numbers of `key` and `value` in the ONNX `StringStringEntryProto` message
+const ENTRY_KEY: u64 = 1;
+const ENTRY_VALUE: u64 = 2;
+
+/// The part of a Hugging Face `config.json` holding the label of each output index.
+#[derive(Debug, Deserialize)]
+struct PretrainedConfig {
+    #[serde(default)]
+    id2label: HashMap<String, String>,
+}
+
+/// It finds the label of each output index of a classifier and checks it against the model output
+///
+/// The `id2label` entry of the ONNX metadata wins, then the `id2label` of the
+/// `<model>.config.json` or `config.json` stored next to the model file, then the labels
+/// declared in the manifest.
+///
+/// Arguments:
+///
+/// * `config`: The manifest entry of the model.
+/// * `session`: The session built from the model file.
+///
+/// Returns:
+///
+/// The labels, ordered by output index
+pub fn load_labels(config: &ModelConfig, session: &Session) -> Result<Vec<String>> {
+    let path = config.model_path()?;
+
+    let labels = match labels_from_files(path)? {
+        Some(labels) => labels,
+        None => config
+            .labels
+            .clone()
+            .ok_or_else(|| Error::missing_config(&config.name, "label map"))?,
+    };
+
+    let classes = session
+        .outputs
+        .first()
+        .and_then(|output| output.dimensions.last().copied().flatten());
+
+    // Dynamic dimensions are checked again against the logits when the model runs
+    if let Some(classes) = classes {
+        check_label_count(path, &labels, classes as usize)?;
+    }
+
+    Ok(labels)
+}
+
+/// It reads the label map from the ONNX metadata of `model`, falling back to the
+/// Hugging Face `config.json` exported next to it. A `<model>.config.json` overrides that
+/// `config.json`, so that several models can share a directory
+///
+/// Arguments:
+///
+/// * `model`: The path of the ONNX model.
+///
+/// Returns:
+///
+/// The labels ordered by output index, or `None` when no place declares them
+pub fn labels_from_files(model: &Path) -> Result<Option<Vec<String>>> {
+    if let Some(id2label) = read_metadata(model)?.remove(ID2LABEL_METADATA_KEY) {
+        let id2label = serde_json::from_str(&id2label).map_err(|source| Error::InvalidLabels {
+            path: model.to_path_buf(),
+            reason: source.to_string(),
+        })?;
+        return ordered_labels(model, id2label).map(Some);
+    }
+
+    for config in config_paths(model) {
+        if let Some(labels) = labels_from_config(&config)? {
+            return Ok(Some(labels));
+        }
+    }
+    Ok(None)
+}
+
+/// The Hugging Face configs of a model, by priority: `resources/roberta-ner.config.json`
+/// then `resources/config.json` for `resources/roberta-ner.onnx`
+fn config_paths(model: &Path) -> [PathBuf; 2] {
+    let mut name = model.file_stem().unwrap_or_default().to_os_string();
+    name.push(".config.json");
+    [
+        model.with_file_name(name),
+        model.with_file_name("config.json"),
+    ]
+}
+
+/// It reads the `id2label` of a Hugging Face config, `None` when the file is missing or
+/// declares no labels
+fn labels_from_config(config: &Path) -> Result<Option<Vec<String>>> {
+    if !config.exists() {
+        return Ok(None);
+    }
+
+    let file = File::open(config).map_err(Error::io(config))?;
+    let pretrained: PretrainedConfig =
+        serde_json::from_reader(BufReader::new(file)).map_err(|source| Error::InvalidLabels {
+            path: config.to_path_buf(),
+            reason: source.to_string(),
+        })?;
+
+    if pretrained.id2label.is_empty() {
+        Ok(None)
+    } else {
+        ordered_labels(config, pretrained.id2label).map(Some)
+    }
+}
+
+/// It fails when a classifier returns a different number of classes than it has labels
+pub(crate) fn check_label_count(path: &Path, labels: &[String], classes: usize) -> Result<()> {
+    if labels.len() == classes {
+        Ok(())
+    } else {
+        Err(Error::LabelCount {
+            path: path.to_path_buf(),
+            labels: labels.len(),
+            classes,
+        })
+    }
+}
+
+/// It turns an `id2label` map into a list, requiring the ids to be exactly `0..n`
+fn ordered_labels(path: &Path, id2label: HashMap<String, String>) -> Result<Vec<String>> {
+    let invalid = |reason: String| Error::InvalidLabels {
+        path: path.to_path_buf(),
+        reason,
+    };
+
+    let mut labels = vec![None; id2label.len()];
+    for (id, label) in id2label {
+        let index = id
+            .parse::<usize>()
+            .map_err(|_| invalid(format!("`{id}` is not a label index")))?;
+        let slot = labels
+            .get_mut(index)
+            .ok_or_else(|| invalid(format!("label index {index} is out of range")))?;
+        *slot = Some(label);
+    }
+
+    labels
+        .into_iter()
+        .enumerate()
+        .map(|(index, label)| label.ok_or_else(|| invalid(format!("label {index} is missing"))))
+        .collect()
+}
+
+/// It reads the `metadata_props` of an ONNX model, skipping over the graph and weights
+fn read_metadata(model: &Path) -> Result<HashMap<String, String>> {
+    let file = File::open(model).map_err(Error::io(model))?;
+    read_metadata_props(&mut BufReader::new(file)).map_err(Error::io(model))
+}
+
+fn read_metadata_props<R: Read + Seek>(reader: &mut R) -> io::Result<HashMap<String, String>> {
+    let mut metadata = HashMap::new();
+
+    for (_, entry) in read_fields(reader, &[METADATA_PROPS])? {
+        let mut key = String::new();
+        let mut value = String::new();
+        for (field, bytes) in read_fields(&mut Cursor::new(entry), &[ENTRY_KEY, ENTRY_VALUE])? {
+            let text =
+                String::from_utf8(bytes).map_err(|source| invalid_data(source.to_string()))?;
+            if field == ENTRY_KEY {
+                key = text;
+            } else {
+                value = text;
+            }
+        }
+        metadata.insert(key, value);
+    }
+
+    Ok(metadata)
+}
+
+/// It walks the fields of a protobuf message, returning the bytes of the length delimited
+/// fields listed in `wanted` and seeking past everything else
+fn read_fields<R: Read + Seek>(reader: &mut R, wanted: &[u64]) -> io::Result<Vec<(u64, Vec<u8>)>> {
+    let mut fields = Vec::new();
+
+    while let Some(tag) = read_varint(reader)? {
+        let field = tag >> 3;
+        match tag & 0x7 {
+            0 => {
+                read_varint(reader)?.ok_or_else(truncated)?;
+            }
+            1 => skip(reader, 8)?,
+            5 => skip(reader, 4)?,
+            2 => {
+                let len = read_varint(reader)?.ok_or_else(truncated)?;
+                if wanted.contains(&field) {
+                    let mut bytes = Vec::new();
+                    reader.by_ref().take(len).read_to_end(&mut bytes)?;
+                    if bytes.len() as u64 != len {
+                        return Err(truncated());
+                    }
+                    fields.push((field, bytes));
+                } else {
+                    skip(reader, len)?;
+                }
+            }
+            wire_type => {
+                return Err(invalid_data(format!(
+                    "unsupported protobuf wire type {wire_type}"
+                )))
+            }
+        }
+    }
+
+    Ok(fields)
+}
+
+/// It reads a protobuf varint, or `None` at the end of the message
+fn read_varint<R: Read>(reader: &mut R) -> io::Result<Option<u64>> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        if reader.read(&mut byte)? == 0 {
+            return if shift == 0 {
+                Ok(None)
+            } else {
+                Err(truncated())
+            };
+        }
+        value |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+    }
+    Err(invalid_data("protobuf varint is too long".to_string()))
+}
+
+fn skip<R: Seek>(reader: &mut R, len: u64) -> io::Result<()> {
+    let len = i64::try_from(len).map_err(|_| truncated())?;
+    reader.seek(SeekFrom::Current(len)).map(|_| ())
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "truncated protobuf message")
+}
+
+fn invalid_data(reason: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(number: u64, bytes: &[u8]) -> Vec<u8> {
+        let mut encoded = vec![(number << 3 | 2) as u8, bytes.len() as u8];
+        encoded.extend_from_slice(bytes);
+        encoded
+    }
+
+    fn model_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("sandbox-rust-labels-{name}"));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_labels_from_onnx_metadata() {
+        let id2label = r#"{"0": "O", "1": "B-PER", "2": "I-PER"}"#;
+        let entry = [
+            field(ENTRY_KEY, ID2LABEL_METADATA_KEY.as_bytes()),
+            field(ENTRY_VALUE, id2label.as_bytes()),
+        ]
+        .concat();
+        // ir_version, a graph to skip over and the metadata entry
+        let model = [
+            vec![0x08, 0x07],
+            field(7, &[0; 32]),
+            field(METADATA_PROPS, &entry),
+        ]
+        .concat();
+
+        let path = model_dir("metadata").join("model.onnx");
+        std::fs::write(&path, model).unwrap();
+
+        let labels = labels_from_files(&path).unwrap().unwrap();
+        assert_eq!(labels, ["O", "B-PER", "I-PER"]);
+    }
+
+    #[test]
+    fn test_labels_from_config_json() {
+        let dir = model_dir("config");
+        let path = dir.join("model.onnx");
+        std::fs::write(&path, [0x08, 0x07]).unwrap();
+        std::fs::write(
+            dir.join("model.config.json"),
+            r#"{"model_type": "bert", "id2label": {"1": "POSITIVE", "0": "NEGATIVE"}}"#,
+        )
+        .unwrap();
+
+        let labels = labels_from_files(&path).unwrap().unwrap();
+        assert_eq!(labels, ["NEGATIVE", "POSITIVE"]);
+
+        // Another model in the same directory does not pick up that config
+        let other = dir.join("other.onnx");
+        std::fs::write(&other, [0x08, 0x07]).unwrap();
+        let _ = std::fs::remove_file(dir.join("other.config.json"));
+        let _ = std::fs::remove_file(dir.join("config.json"));
+        assert_eq!(labels_from_files(&other).unwrap(), None);
+
+        std::fs::write(dir.join("other.config.json"), "{ not json").unwrap();
+        assert!(matches!(
+            labels_from_files(&other),
+            Err(Error::InvalidLabels { .. })
+        ));
+    }
+
+    #[test]
+    fn test_labels_from_shared_config_json() {
+        // A plain Hugging Face export directory
+        let dir = model_dir("export");
+        let path = dir.join("model.onnx");
+        std::fs::write(&path, [0x08, 0x07]).unwrap();
+        let _ = std::fs::remove_file(dir.join("model.config.json"));
+        std::fs::write(
+            dir.join("config.json"),
+            r#"{"id2label": {"0": "O", "1": "B-LOC", "2": "I-LOC"}}"#,
+        )
+        .unwrap();
+
+        let labels = labels_from_files(&path).unwrap().unwrap();
+        assert_eq!(labels, ["O", "B-LOC", "I-LOC"]);
+
+        // A per model config overrides it
+        std::fs::write(
+            dir.join("model.config.json"),
+            r#"{"id2label": {"0": "NEGATIVE", "1": "POSITIVE"}}"#,
+        )
+        .unwrap();
+        let labels = labels_from_files(&path).unwrap().unwrap();
+        assert_eq!(labels, ["NEGATIVE", "POSITIVE"]);
+    }
+
+    #[test]
+    fn test_invalid_label_maps() {
+        let path = Path::new("config.json");
+        let id2label =
+            HashMap::from([("0".to_string(), "O".to_string()), ("2".into(), "B".into())]);
+        assert!(matches!(
+            ordered_labels(path, id2label),
+            Err(Error::InvalidLabels { .. })
+        ));
+
+        let labels = ["O".to_string(), "B-PER".to_string()];
+        assert!(check_label_count(path, &labels, 2).is_ok());
+        let error = check_label_count(path, &labels, 9).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "model `config.json` has 2 labels but returns 9 classes"
+        );
+    }
+}
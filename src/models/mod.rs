@@ -1,2 +1,12 @@
+pub mod batching;
+pub mod config;
+pub mod embedder;
+pub mod entities;
+pub mod labels;
+pub mod ner;
+pub mod onnx;
+pub mod parity;
+pub mod reranker;
+pub mod windows;
 pub mod xlm_roberta_onnx;
 pub mod xlm_roberta_rustbert;
@@ -0,0 +1,190 @@
+use rust_bert::pipelines::ner::Entity as RustBertEntity;
+use rust_bert::pipelines::token_classification::LabelAggregationOption;
+use rust_bert::RustBertError;
+
+use crate::models::config::{ModelBackend, ModelConfig, ModelTask, RustBertArchitecture};
+use crate::models::entities::{Entity, LabelAggregation};
+use crate::models::xlm_roberta_onnx::XlmRobertaOnnxNer;
+use crate::models::xlm_roberta_rustbert::XlmRobertaRustBertNer;
+use crate::tokens::bert_rustbert::BertRustBertNer;
+use crate::{Error, Result};
+
+/// The NER implementations that can be selected through a `NerConfig`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NerBackendKind {
+    BertRustBert,
+    XlmRobertaRustBert,
+    XlmRobertaOnnx,
+}
+
+/// Configuration shared by every `NerBackend`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NerConfig {
+    pub backend: NerBackendKind,
+    pub aggregation: LabelAggregation,
+    /// The manifest entry to load, for backends that read a model file.
+    /// The model bundled in `models.json` is used when it is `None`.
+    pub model: Option<ModelConfig>,
+}
+
+impl NerConfig {
+    #[must_use]
+    pub fn new(backend: NerBackendKind) -> NerConfig {
+        NerConfig {
+            backend,
+            aggregation: LabelAggregation::default(),
+            model: None,
+        }
+    }
+
+    /// It picks the backend matching a token classification manifest entry
+    pub fn from_model(model: ModelConfig) -> Result<NerConfig> {
+        if model.task != ModelTask::TokenClassification {
+            return Err(Error::missing_config(
+                &model.name,
+                "token_classification task",
+            ));
+        }
+
+        let backend = match model.backend {
+            ModelBackend::Onnx => NerBackendKind::XlmRobertaOnnx,
+            ModelBackend::RustBert(RustBertArchitecture::Bert) => NerBackendKind::BertRustBert,
+            ModelBackend::RustBert(RustBertArchitecture::XlmRoberta) => {
+                NerBackendKind::XlmRobertaRustBert
+            }
+        };
+
+        Ok(NerConfig {
+            backend,
+            aggregation: LabelAggregation::default(),
+            model: Some(model),
+        })
+    }
+}
+
+/// A named entity recognition model that returns the same `Entity` type
+/// whichever library runs it underneath.
+pub trait NerBackend: Sized {
+    /// It loads the model described by `config`
+    fn load(config: &NerConfig) -> Result<Self>;
+
+    /// It extracts the entities of every input text, whole words merged into entities
+    fn extract<S>(&self, input: &[S]) -> Result<Vec<Vec<Entity>>>
+    where
+        S: AsRef<str>;
+}
+
+/// A `NerBackend` whose implementation is picked at runtime from `NerConfig::backend`.
+pub enum NerPipeline {
+    BertRustBert(BertRustBertNer),
+    XlmRobertaRustBert(XlmRobertaRustBertNer),
+    XlmRobertaOnnx(XlmRobertaOnnxNer),
+}
+
+impl NerBackend for NerPipeline {
+    fn load(config: &NerConfig) -> Result<Self> {
+        Ok(match config.backend {
+            NerBackendKind::BertRustBert => {
+                NerPipeline::BertRustBert(BertRustBertNer::load(config)?)
+            }
+            NerBackendKind::XlmRobertaRustBert => {
+                NerPipeline::XlmRobertaRustBert(XlmRobertaRustBertNer::load(config)?)
+            }
+            NerBackendKind::XlmRobertaOnnx => {
+                NerPipeline::XlmRobertaOnnx(XlmRobertaOnnxNer::load(config)?)
+            }
+        })
+    }
+
+    fn extract<S>(&self, input: &[S]) -> Result<Vec<Vec<Entity>>>
+    where
+        S: AsRef<str>,
+    {
+        match self {
+            NerPipeline::BertRustBert(model) => model.extract(input),
+            NerPipeline::XlmRobertaRustBert(model) => model.extract(input),
+            NerPipeline::XlmRobertaOnnx(model) => model.extract(input),
+        }
+    }
+}
+
+/// It maps a `LabelAggregation` onto the options rust-bert supports
+pub(crate) fn rust_bert_aggregation(
+    aggregation: LabelAggregation,
+) -> std::result::Result<LabelAggregationOption, RustBertError> {
+    match aggregation {
+        LabelAggregation::First => Ok(LabelAggregationOption::First),
+        LabelAggregation::Mode => Ok(LabelAggregationOption::Mode),
+        other => Err(RustBertError::InvalidConfigurationError(format!(
+            "rust-bert NER models do not support {other:?} label aggregation"
+        ))),
+    }
+}
+
+impl From<RustBertEntity> for Entity {
+    fn from(entity: RustBertEntity) -> Self {
+        Entity {
+            word: entity.word,
+            label: entity.label,
+            score: entity.score,
+            start: entity.offset.begin as usize,
+            end: entity.offset.end as usize,
+        }
+    }
+}
+
+/// It converts the output of rust-bert's `predict_full_entities` into crate entities
+pub(crate) fn from_rust_bert(entities: Vec<Vec<RustBertEntity>>) -> Vec<Vec<Entity>> {
+    entities
+        .into_iter()
+        .map(|sentence| sentence.into_iter().map(Entity::from).collect())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::config::ModelManifest;
+
+    #[test]
+    fn test_rust_bert_aggregation() {
+        assert!(rust_bert_aggregation(LabelAggregation::First).is_ok());
+        assert!(rust_bert_aggregation(LabelAggregation::Mode).is_ok());
+        assert!(rust_bert_aggregation(LabelAggregation::Average).is_err());
+    }
+
+    #[test]
+    fn test_config_from_manifest() {
+        let manifest = ModelManifest::builtin().unwrap();
+
+        let onnx = NerConfig::from_model(manifest.get("roberta-ner").unwrap().clone()).unwrap();
+        assert_eq!(onnx.backend, NerBackendKind::XlmRobertaOnnx);
+
+        let bert = NerConfig::from_model(manifest.get("bert-ner").unwrap().clone()).unwrap();
+        assert_eq!(bert.backend, NerBackendKind::BertRustBert);
+
+        assert!(NerConfig::from_model(manifest.get("text-classify").unwrap().clone()).is_err());
+    }
+
+    #[test]
+    fn test_backends_agree_on_entities() {
+        let input = [
+            "My name is Amélie. I live in Москва.",
+            "My name is Mario and I live in Canada.",
+        ];
+
+        for backend in [
+            NerBackendKind::BertRustBert,
+            NerBackendKind::XlmRobertaRustBert,
+            NerBackendKind::XlmRobertaOnnx,
+        ] {
+            let model = NerPipeline::load(&NerConfig::new(backend)).unwrap();
+            let entities = model.extract(&input).unwrap();
+
+            assert_eq!(entities.len(), input.len());
+            let mario = entities[1].iter().find(|e| e.word == "Mario").unwrap();
+            assert_eq!(mario.label, "PER");
+            assert_eq!((mario.start, mario.end), (11, 16));
+        }
+    }
+}
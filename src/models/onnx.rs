@@ -0,0 +1,478 @@
+use std::env::var;
+use std::path::Path;
+use std::sync::Arc;
+
+use onnxruntime::environment::Environment;
+use onnxruntime::ndarray::{Array2, ArrayD, ArrayViewD};
+use onnxruntime::session::{InputTensor, Session};
+use onnxruntime::{GraphOptimizationLevel, LoggingLevel, TensorElementDataType};
+use tokenizers::{Encoding, Tokenizer};
+
+use crate::models::batching::run_batches;
+use crate::models::config::{ModelConfig, ModelTask, OptimizationLevel, SessionOptions};
+use crate::models::labels::load_labels;
+use crate::tokens::bert_roberta_tokenizers::{load_tokenizer_from, with_windowing, Embeddings};
+use crate::tokens::text_encoder::TextEncoder;
+use crate::{Error, Result};
+
+/// An ONNX model together with the tokenizer and manifest entry it was built from.
+pub struct OnnxModel {
+    // The session is declared first so it is dropped before its environment.
+    session: Session,
+    tokenizer: Arc<Tokenizer>,
+    config: ModelConfig,
+    binding: InputBinding,
+    labels: Option<Vec<String>>,
+    _environment: Environment,
+}
+
+impl OnnxModel {
+    /// It builds the environment, session and tokenizer declared by a manifest entry
+    ///
+    /// Arguments:
+    ///
+    /// * `config`: The manifest entry of the model.
+    ///
+    /// Returns:
+    ///
+    /// An `OnnxModel`
+    pub fn load(config: &ModelConfig) -> Result<OnnxModel> {
+        let (environment, session) = open_session(config)?;
+        let binding = InputBinding::from_session(&session, config.model_path()?)?;
+        let tokenizer = load_model_tokenizer(config)?;
+
+        let labels = match config.task {
+            ModelTask::SequenceClassification | ModelTask::TokenClassification => {
+                Some(load_labels(config, &session)?)
+            }
+            ModelTask::FeatureExtraction => None,
+        };
+
+        Ok(OnnxModel {
+            session,
+            tokenizer,
+            config: config.clone(),
+            binding,
+            labels,
+            _environment: environment,
+        })
+    }
+
+    #[must_use]
+    pub fn config(&self) -> &ModelConfig {
+        &self.config
+    }
+
+    /// The label of each output index, for classification models
+    pub fn labels(&self) -> Result<&[String]> {
+        self.labels
+            .as_deref()
+            .ok_or_else(|| Error::missing_config(&self.config.name, "label map"))
+    }
+
+    #[must_use]
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    #[must_use]
+    pub fn tokenizer(&self) -> &Tokenizer {
+        &self.tokenizer
+    }
+
+    #[must_use]
+    pub fn binding(&self) -> &InputBinding {
+        &self.binding
+    }
+
+    /// It runs encoded inputs through the model in the length-bucketed batches set in its manifest entry
+    ///
+    /// Arguments:
+    ///
+    /// * `encodings`: The unpadded encodings of the inputs.
+    /// * `rows`: Splits the output of one batch into one result per input, given the
+    ///   attention mask of the batch. The output is borrowed from the runtime and only
+    ///   lives for the call.
+    ///
+    /// Returns:
+    ///
+    /// One result per encoding, in the original order
+    pub fn run_batches<T, F>(&self, encodings: &[Encoding], mut rows: F) -> Result<Vec<T>>
+    where
+        F: FnMut(ArrayViewD<f32>, &Array2<i64>) -> Result<Vec<T>>,
+    {
+        run_batches(encodings, self.config.batching, |inputs| {
+            let attention_mask = inputs.1.clone();
+            self.run_with(inputs, |output| rows(output, &attention_mask))
+        })
+    }
+
+    /// It runs the model and returns a copy of its first output
+    pub fn run(&self, inputs: Embeddings) -> Result<ArrayD<f32>> {
+        self.run_with(inputs, |output| Ok(output.to_owned()))
+    }
+
+    /// It runs the model and decodes its first output where the runtime wrote it, without
+    /// copying it
+    ///
+    /// Arguments:
+    ///
+    /// * `inputs`: The tokenizer output.
+    /// * `decode`: Turns the output into the result, before the runtime releases it.
+    ///
+    /// Returns:
+    ///
+    /// The result of `decode`
+    pub fn run_with<T, F>(&self, inputs: Embeddings, decode: F) -> Result<T>
+    where
+        F: FnOnce(ArrayViewD<f32>) -> Result<T>,
+    {
+        run_bound(
+            &self.session,
+            &self.binding,
+            self.config.model_path()?,
+            inputs,
+            decode,
+        )
+    }
+
+    /// It runs the model over texts tokenized by any `TextEncoder`, instead of its own tokenizer
+    ///
+    /// Arguments:
+    ///
+    /// * `encoder`: The tokenizer, which must share the vocabulary of the model.
+    /// * `text`: The texts to run.
+    ///
+    /// Returns:
+    ///
+    /// The first output of the model
+    pub fn run_encoded<E, S>(&self, encoder: &E, text: &[S]) -> Result<ArrayD<f32>>
+    where
+        E: TextEncoder,
+        S: AsRef<str>,
+    {
+        self.run(encoder.encode_batch(text)?.into_embeddings())
+    }
+
+    /// It wraps a tokenizer error with the name of the model's tokenizer
+    pub(crate) fn tokenizer_error(&self, source: tokenizers::Error) -> Error {
+        let name = self
+            .config
+            .tokenizer
+            .as_ref()
+            .map_or_else(|| self.config.name.clone(), |source| source.name());
+        Error::Tokenizer { name, source }
+    }
+}
+
+/// Where the tensor of an ONNX input comes from in the tokenizer `Embeddings`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputSource {
+    InputIds,
+    AttentionMask,
+    TokenTypeIds,
+}
+
+impl InputSource {
+    fn from_name(name: &str) -> Option<InputSource> {
+        match name {
+            "input_ids" => Some(InputSource::InputIds),
+            "attention_mask" => Some(InputSource::AttentionMask),
+            "token_type_ids" => Some(InputSource::TokenTypeIds),
+            _ => None,
+        }
+    }
+}
+
+/// One input declared by an ONNX session, matched to the tokenizer output feeding it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BoundInput {
+    pub name: String,
+    pub source: InputSource,
+    /// The declared shape, `None` for dynamic axes
+    pub dimensions: Vec<Option<u32>>,
+}
+
+/// The inputs of a session in the order it declares them, read once from `session.inputs`.
+///
+/// Tokenizer outputs the model does not declare are dropped, and `token_type_ids` are
+/// filled with zeros when the tokenizer did not produce any.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputBinding {
+    inputs: Vec<BoundInput>,
+}
+
+impl InputBinding {
+    /// It matches every input of `session` to a tokenizer output
+    ///
+    /// Arguments:
+    ///
+    /// * `session`: The session to introspect.
+    /// * `path`: The model file, used in error messages.
+    ///
+    /// Returns:
+    ///
+    /// An `InputBinding`, or an error naming the first input that cannot be fed
+    pub fn from_session(session: &Session, path: &Path) -> Result<InputBinding> {
+        let inputs = session
+            .inputs
+            .iter()
+            .map(|input| {
+                let source =
+                    InputSource::from_name(&input.name).ok_or_else(|| Error::UnknownInput {
+                        path: path.to_path_buf(),
+                        input: input.name.clone(),
+                    })?;
+
+                if input.input_type != TensorElementDataType::Int64 {
+                    return Err(Error::InputType {
+                        path: path.to_path_buf(),
+                        input: input.name.clone(),
+                        found: format!("{:?}", input.input_type),
+                    });
+                }
+
+                Ok(BoundInput {
+                    name: input.name.clone(),
+                    source,
+                    dimensions: input.dimensions.clone(),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(InputBinding { inputs })
+    }
+
+    #[must_use]
+    pub fn inputs(&self) -> &[BoundInput] {
+        &self.inputs
+    }
+
+    /// It builds the input tensors of the session, in its declared order
+    ///
+    /// Arguments:
+    ///
+    /// * `path`: The model file, used in error messages.
+    /// * `inputs`: The tokenizer output.
+    ///
+    /// Returns:
+    ///
+    /// The tensors to pass to `session.run`
+    pub fn bind(&self, path: &Path, inputs: Embeddings) -> Result<Vec<InputTensor>> {
+        let (input_ids, attention_mask, type_ids) = inputs;
+
+        // Some tokenizers do not produce segment ids, models trained with them expect zeros
+        let type_ids = if type_ids.dim() == input_ids.dim() {
+            type_ids
+        } else {
+            Array2::zeros(input_ids.dim())
+        };
+
+        self.inputs
+            .iter()
+            .map(|input| {
+                let tensor = match input.source {
+                    InputSource::InputIds => &input_ids,
+                    InputSource::AttentionMask => &attention_mask,
+                    InputSource::TokenTypeIds => &type_ids,
+                };
+                check_shape(path, input, tensor.shape())?;
+                Ok(tensor.clone().into())
+            })
+            .collect()
+    }
+}
+
+/// It fails when a tensor does not fit the rank or the fixed axes declared for an input
+fn check_shape(path: &Path, input: &BoundInput, shape: &[usize]) -> Result<()> {
+    let fits =
+        input.dimensions.len() == shape.len()
+            && input.dimensions.iter().zip(shape).all(|(expected, found)| {
+                expected.is_none_or(|expected| expected as usize == *found)
+            });
+
+    if fits {
+        Ok(())
+    } else {
+        Err(Error::InputShape {
+            path: path.to_path_buf(),
+            input: input.name.clone(),
+            expected: input.dimensions.clone(),
+            found: shape.to_vec(),
+        })
+    }
+}
+
+fn run_bound<T, F>(
+    session: &Session,
+    binding: &InputBinding,
+    path: &Path,
+    inputs: Embeddings,
+    decode: F,
+) -> Result<T>
+where
+    F: FnOnce(ArrayViewD<f32>) -> Result<T>,
+{
+    let outputs = session
+        .run(binding.bind(path, inputs)?)
+        .map_err(Error::ort(path))?;
+
+    let output = outputs
+        .first()
+        .and_then(|output| output.float_array())
+        .ok_or_else(|| Error::missing_output(path, "float"))?;
+
+    decode(output.view())
+}
+
+/// It loads the tokenizer of a manifest entry, splitting long inputs into its windows if any.
+/// Padding is left to the batches the inputs are run in. The shared tokenizer is only copied
+/// when its padding or truncation must change.
+pub(crate) fn load_model_tokenizer(config: &ModelConfig) -> Result<Arc<Tokenizer>> {
+    let tokenizer = load_tokenizer_from(config.tokenizer_source()?)?;
+    if tokenizer.get_padding().is_none() && config.window.is_none() {
+        return Ok(tokenizer);
+    }
+
+    let mut tokenizer = Tokenizer::clone(&tokenizer);
+    tokenizer.with_padding(None);
+    if let Some(windowing) = config.window {
+        with_windowing(&mut tokenizer, windowing);
+    }
+    Ok(Arc::new(tokenizer))
+}
+
+/// It builds the environment and session of a manifest entry, checking the model file exists first
+pub(crate) fn open_session(config: &ModelConfig) -> Result<(Environment, Session)> {
+    let path = config.model_path()?;
+    // Fail with the model path instead of an opaque runtime error when the file is missing
+    std::fs::metadata(path).map_err(Error::io(path))?;
+
+    let environment = build_environment(path, &config.session)?;
+    let session = build_session(&environment, path, &config.session)?;
+    Ok((environment, session))
+}
+
+fn build_environment(model: &Path, options: &SessionOptions) -> Result<Environment> {
+    let path = var("RUST_ONNXRUNTIME_LIBRARY_PATH").ok();
+
+    let builder = Environment::builder()
+        .with_name(options.environment_name.as_str())
+        .with_log_level(LoggingLevel::Warning);
+
+    let builder = if let Some(path) = path {
+        builder.with_library_path(path)
+    } else {
+        builder
+    };
+
+    builder.build().map_err(Error::ort(model))
+}
+
+fn build_session(
+    environment: &Environment,
+    model: &Path,
+    options: &SessionOptions,
+) -> Result<Session> {
+    let builder = environment
+        .new_session_builder()
+        .map_err(Error::ort(model))?
+        .with_graph_optimization_level(options.optimization_level.into())
+        .map_err(Error::ort(model))?;
+
+    let builder = if let Some(threads) = options.intra_threads {
+        builder
+            .with_number_threads(threads)
+            .map_err(Error::ort(model))?
+    } else {
+        builder
+    };
+
+    builder
+        .with_model_from_file(model)
+        .map_err(Error::ort(model))
+}
+
+impl From<OptimizationLevel> for GraphOptimizationLevel {
+    fn from(level: OptimizationLevel) -> Self {
+        match level {
+            OptimizationLevel::DisableAll => GraphOptimizationLevel::DisableAll,
+            OptimizationLevel::Basic => GraphOptimizationLevel::Basic,
+            OptimizationLevel::Extended => GraphOptimizationLevel::Extended,
+            OptimizationLevel::All => GraphOptimizationLevel::All,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_model_is_an_error() {
+        let mut config = ModelConfig::builtin("roberta-ner").unwrap();
+        config.path = Some("resources/missing.onnx".into());
+
+        let error = OnnxModel::load(&config).err().unwrap();
+        assert!(matches!(error, Error::Io { .. }));
+        assert!(error.to_string().contains("resources/missing.onnx"));
+    }
+
+    #[test]
+    fn test_binding_follows_declared_inputs() {
+        let path = Path::new("resources/model.onnx");
+        let binding = InputBinding {
+            inputs: vec![
+                BoundInput {
+                    name: "attention_mask".to_string(),
+                    source: InputSource::AttentionMask,
+                    dimensions: vec![None, None],
+                },
+                BoundInput {
+                    name: "token_type_ids".to_string(),
+                    source: InputSource::TokenTypeIds,
+                    dimensions: vec![None, Some(3)],
+                },
+            ],
+        };
+
+        let input_ids = Array2::from_elem((2, 3), 7);
+        let attention_mask = Array2::ones((2, 3));
+        let tensors = binding
+            .bind(path, (input_ids, attention_mask, Array2::zeros((0, 0))))
+            .unwrap();
+
+        // input_ids are not declared, so only the mask and zeroed type ids are fed
+        assert_eq!(tensors.len(), 2);
+        match &tensors[1] {
+            InputTensor::Int64Tensor(type_ids) => {
+                assert_eq!(type_ids.shape(), [2, 3]);
+                assert!(type_ids.iter().all(|id| *id == 0));
+            }
+            _ => panic!("token_type_ids should be an int64 tensor"),
+        }
+
+        let error = binding
+            .bind(
+                path,
+                (
+                    Array2::zeros((2, 5)),
+                    Array2::ones((2, 5)),
+                    Array2::zeros((2, 5)),
+                ),
+            )
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "model `resources/model.onnx` expects input `token_type_ids` with shape [None, Some(3)], got [2, 5]"
+        );
+    }
+
+    #[test]
+    fn test_load_from_manifest() {
+        let config = ModelConfig::builtin("text-classify").unwrap();
+        let model = OnnxModel::load(&config).unwrap();
+        assert_eq!(model.config().name, "text-classify");
+    }
+}
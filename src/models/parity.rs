@@ -0,0 +1,273 @@
+use std::fmt;
+
+use ndarray::{ArrayView1, ArrayView2, Axis};
+use tokenizers::Encoding;
+
+use crate::models::xlm_roberta_onnx;
+use crate::models::xlm_roberta_rustbert::XlmRobertaTokenLogits;
+use crate::tokens::bert_roberta_tokenizers::to_embeddings;
+use crate::utilities::postprocess::argmax;
+use crate::utilities::retrieval::{Cosine, Metric};
+use crate::utilities::vec_array::{rows, view3, ParityTolerance};
+use crate::{Error, Result};
+
+/// How closely two backends agree on the token logits of one sentence.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SentenceParity {
+    pub text: String,
+    pub tokens: usize,
+    /// The largest absolute difference of a logit
+    pub max_abs_error: f32,
+    /// The largest difference of a logit relative to the reference, over non zero logits
+    pub max_rel_error: f32,
+    /// The lowest cosine similarity between the logits of a token in both backends, as
+    /// scored by the `Cosine` metric
+    pub min_cosine: f32,
+    /// The share of tokens given the same label by both backends
+    pub label_agreement: f32,
+    /// Whether every logit is within the tolerance
+    pub within_tolerance: bool,
+}
+
+/// The parity of every sentence checked, with the tolerance used.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParityReport {
+    pub tolerance: ParityTolerance,
+    pub sentences: Vec<SentenceParity>,
+}
+
+impl ParityReport {
+    /// Whether every sentence is within the tolerance and labeled the same by both backends
+    #[must_use]
+    pub fn passed(&self) -> bool {
+        self.sentences
+            .iter()
+            .all(|sentence| sentence.within_tolerance && sentence.label_agreement == 1.0)
+    }
+}
+
+impl fmt::Display for ParityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "tolerance: absolute {:e}, relative {:e}",
+            self.tolerance.absolute, self.tolerance.relative
+        )?;
+        for sentence in &self.sentences {
+            writeln!(
+                f,
+                "{} {:>4} tokens  max abs {:.2e}  max rel {:.2e}  min cosine {:.6}  labels {:.1}%  {:?}",
+                if sentence.within_tolerance { "ok  " } else { "FAIL" },
+                sentence.tokens,
+                sentence.max_abs_error,
+                sentence.max_rel_error,
+                sentence.min_cosine,
+                sentence.label_agreement * 100.0,
+                sentence.text,
+            )?;
+        }
+        write!(f, "{}", if self.passed() { "passed" } else { "failed" })
+    }
+}
+
+/// It runs the rust-bert and ONNX XLM Roberta NER models over the same sentences and
+/// compares their token logits
+///
+/// Both models are fed the token ids of the ONNX tokenizer, so differences come from the
+/// exported graph only. Sentences longer than the model window are compared on their
+/// first window.
+///
+/// Arguments:
+///
+/// * `text`: The sentences to check.
+/// * `tolerance`: How far the logits may drift apart.
+///
+/// Returns:
+///
+/// A `ParityReport` with the rust-bert model as the reference
+pub fn check_parity<S>(text: &[S], tolerance: ParityTolerance) -> Result<ParityReport>
+where
+    S: AsRef<str>,
+{
+    let model = xlm_roberta_onnx::build_model()?;
+    let reference =
+        XlmRobertaTokenLogits::build_model().map_err(Error::rust_bert("xlm-roberta-ner-en"))?;
+
+    let sentences = text
+        .iter()
+        .map(|text| {
+            let text = text.as_ref();
+            let encoding = model
+                .tokenizer()
+                .encode(text, true)
+                .map_err(|source| model.tokenizer_error(source))?;
+
+            let expected = reference.logits(&input_ids(&encoding))?;
+            model.run_with(to_embeddings(&[&encoding]), |found| {
+                compare_logits(
+                    text,
+                    expected.view(),
+                    view3(&found)?.index_axis(Axis(0), 0),
+                    tolerance,
+                )
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(ParityReport {
+        tolerance,
+        sentences,
+    })
+}
+
+/// It compares the token logits two backends produced for one sentence
+///
+/// Arguments:
+///
+/// * `text`: The sentence, kept in the report.
+/// * `reference`: The logits of the reference backend, shaped `(tokens, labels)`.
+/// * `candidate`: The logits of the backend under test, with the same shape.
+/// * `tolerance`: How far the logits may drift apart.
+///
+/// Returns:
+///
+/// A `SentenceParity`, or a `Dimension` error when the shapes differ
+pub fn compare_logits(
+    text: &str,
+    reference: ArrayView2<f32>,
+    candidate: ArrayView2<f32>,
+    tolerance: ParityTolerance,
+) -> Result<SentenceParity> {
+    if reference.dim() != candidate.dim() {
+        return Err(Error::Dimension {
+            expected: reference.len(),
+            found: candidate.len(),
+        });
+    }
+
+    let mut max_abs_error: f32 = 0.0;
+    let mut max_rel_error: f32 = 0.0;
+    let mut within_tolerance = true;
+    for (&expected, &found) in reference.iter().zip(&candidate) {
+        let error = (found - expected).abs();
+        max_abs_error = max_abs_error.max(error);
+        if expected != 0.0 {
+            max_rel_error = max_rel_error.max(error / expected.abs());
+        }
+        within_tolerance &= tolerance.accepts(expected, found);
+    }
+
+    let tokens = reference.nrows();
+    let mut min_cosine: f32 = 1.0;
+    let mut agreeing = 0;
+    for (expected, found) in rows(reference).zip(rows(candidate)) {
+        min_cosine = min_cosine.min(Cosine.score(&expected, &found));
+        let label = |logits: &[f32]| argmax(ArrayView1::from(logits)).map(|(label, _)| label);
+        if label(&expected) == label(&found) {
+            agreeing += 1;
+        }
+    }
+
+    Ok(SentenceParity {
+        text: text.to_string(),
+        tokens,
+        max_abs_error,
+        max_rel_error,
+        min_cosine,
+        label_agreement: if tokens == 0 {
+            1.0
+        } else {
+            agreeing as f32 / tokens as f32
+        },
+        within_tolerance,
+    })
+}
+
+fn input_ids(encoding: &Encoding) -> Vec<i64> {
+    encoding.get_ids().iter().map(|&id| i64::from(id)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::array;
+
+    use super::*;
+
+    #[test]
+    fn test_compare_logits() {
+        let reference = array![[4.0, -1.0, 0.0], [0.5, 2.0, -3.0]];
+        let close = &reference + 1e-4;
+        let parity = compare_logits(
+            "a b",
+            reference.view(),
+            close.view(),
+            ParityTolerance::default(),
+        )
+        .unwrap();
+        assert!(parity.within_tolerance);
+        assert_eq!(parity.label_agreement, 1.0);
+        assert!((parity.max_abs_error - 1e-4).abs() < 1e-6);
+        assert!(parity.min_cosine > 0.9999);
+
+        // The second token flips to the first label
+        let drifted = array![[4.0, -1.0, 0.0], [2.5, 2.0, -3.0]];
+        let parity = compare_logits(
+            "a b",
+            reference.view(),
+            drifted.view(),
+            ParityTolerance::default(),
+        )
+        .unwrap();
+        assert!(!parity.within_tolerance);
+        assert_eq!(parity.label_agreement, 0.5);
+        assert_eq!((parity.max_abs_error, parity.max_rel_error), (2.0, 4.0));
+
+        let report = ParityReport {
+            tolerance: ParityTolerance::default(),
+            sentences: vec![parity],
+        };
+        assert!(!report.passed());
+        assert!(report.to_string().ends_with("failed"));
+    }
+
+    #[test]
+    fn test_relative_tolerance_and_shapes() {
+        let reference = array![[1000.0, -2000.0]];
+        let candidate = array![[1000.5, -2000.5]];
+        let tolerance = ParityTolerance {
+            absolute: 0.0,
+            relative: 1e-3,
+        };
+        assert!(
+            compare_logits("a", reference.view(), candidate.view(), tolerance)
+                .unwrap()
+                .within_tolerance
+        );
+
+        let nan = array![[f32::NAN, -2000.0]];
+        assert!(
+            !compare_logits("a", reference.view(), nan.view(), tolerance)
+                .unwrap()
+                .within_tolerance
+        );
+
+        assert!(matches!(
+            compare_logits("a", reference.view(), reference.t(), tolerance),
+            Err(Error::Dimension { .. })
+        ));
+    }
+
+    #[test]
+    fn test_check_parity() {
+        let report = check_parity(
+            &[
+                "My name is Amélie. I live in Москва.",
+                "Chongqing is a city in China.",
+            ],
+            ParityTolerance::default(),
+        )
+        .unwrap();
+        println!("{report}");
+        assert!(report.passed());
+    }
+}
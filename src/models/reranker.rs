@@ -0,0 +1,220 @@
+use onnxruntime::ndarray::{ArrayView1, ArrayView2, Axis, Ix2};
+use tokenizers::Tokenizer;
+
+use crate::models::config::ModelConfig;
+use crate::models::onnx::OnnxModel;
+use crate::tokens::bert_roberta_tokenizers::{encode_pairs, with_pair_truncation};
+use crate::utilities::postprocess::{sigmoid, softmax};
+use crate::{Error, Result};
+
+/// The longest pair encoded when the manifest entry declares no window
+const DEFAULT_MAX_LENGTH: usize = 512;
+
+/// A candidate reordered by a `Reranker`, with the relevance the model gave it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reranked<T> {
+    pub candidate: T,
+    /// In `[0, 1]`, higher meaning more relevant to the query
+    pub score: f32,
+}
+
+/// A cross-encoder that scores how relevant passages are to a query.
+///
+/// Unlike an `Embedder`, it reads the query and a passage together, as one input with the
+/// query in segment 0 and the passage in segment 1, which makes it slower but much more
+/// accurate. It is meant to reorder the few best candidates of a retriever.
+pub struct Reranker {
+    model: OnnxModel,
+    tokenizer: Tokenizer,
+}
+
+impl Reranker {
+    /// It loads the `cross-encoder` model declared in the bundled `models.json`
+    ///
+    /// Returns:
+    ///
+    /// A `Reranker`
+    pub fn build_model() -> Result<Reranker> {
+        Reranker::from_config(&ModelConfig::builtin("cross-encoder")?)
+    }
+
+    /// It loads a cross-encoder from its manifest entry
+    ///
+    /// Arguments:
+    ///
+    /// * `config`: The manifest entry of a sequence classification model over sentence pairs,
+    ///   with one relevance logit or two classes, the last one meaning relevant.
+    ///
+    /// Returns:
+    ///
+    /// A `Reranker`
+    pub fn from_config(config: &ModelConfig) -> Result<Reranker> {
+        let model = OnnxModel::load(config)?;
+
+        // Pairs are truncated to one input rather than split into windows
+        let mut tokenizer = model.tokenizer().clone();
+        let max_length = config
+            .window
+            .map_or(DEFAULT_MAX_LENGTH, |window| window.max_length);
+        with_pair_truncation(&mut tokenizer, max_length);
+
+        Ok(Reranker { model, tokenizer })
+    }
+
+    /// It scores passages against a query, in the batches set in the manifest entry
+    ///
+    /// Arguments:
+    ///
+    /// * `query`: The query.
+    /// * `passages`: The passages to score.
+    ///
+    /// Returns:
+    ///
+    /// The relevance of every passage, in the same order
+    pub fn score<S>(&self, query: &str, passages: &[S]) -> Result<Vec<f32>>
+    where
+        S: AsRef<str>,
+    {
+        let pairs: Vec<(&str, &str)> = passages
+            .iter()
+            .map(|passage| (query, passage.as_ref()))
+            .collect();
+        let encodings = encode_pairs(&pairs, &self.tokenizer)
+            .map_err(|source| self.model.tokenizer_error(source))?;
+
+        self.model.run_batches(&encodings, |output, _| {
+            let Ok(logits) = output.into_dimensionality::<Ix2>() else {
+                return Err(Error::missing_output(
+                    self.model.config().model_path()?,
+                    "relevance logits",
+                ));
+            };
+            relevances(logits.view())
+        })
+    }
+
+    /// It reorders candidates by their relevance to a query
+    ///
+    /// Arguments:
+    ///
+    /// * `query`: The query.
+    /// * `candidates`: The candidates, usually the best results of a retriever.
+    /// * `passage`: The text of a candidate.
+    ///
+    /// Returns:
+    ///
+    /// The candidates with their relevance, the most relevant first. Candidates scored the
+    /// same keep their original order.
+    pub fn rerank<T, F>(
+        &self,
+        query: &str,
+        candidates: Vec<T>,
+        passage: F,
+    ) -> Result<Vec<Reranked<T>>>
+    where
+        F: Fn(&T) -> &str,
+    {
+        let passages: Vec<&str> = candidates.iter().map(&passage).collect();
+        let scores = self.score(query, &passages)?;
+
+        Ok(order(candidates, scores))
+    }
+}
+
+/// The relevance of every pair of a batch, from its logits of shape (pairs, classes)
+///
+/// It fails with `Error::Dimension` when the model outputs no class at all, which could
+/// only be scored `NaN`.
+fn relevances(logits: ArrayView2<f32>) -> Result<Vec<f32>> {
+    if logits.ncols() == 0 {
+        return Err(Error::Dimension {
+            expected: 1,
+            found: 0,
+        });
+    }
+
+    Ok(logits.axis_iter(Axis(0)).map(relevance).collect())
+}
+
+/// The relevance of one pair, from its logits, of which there is at least one
+///
+/// A single logit goes through a sigmoid, and several classes through a softmax whose
+/// last class is taken as relevant.
+fn relevance(logits: ArrayView1<f32>) -> f32 {
+    match logits.len() {
+        1 => sigmoid(&logits)[0],
+        classes => softmax(&logits, Axis(0))[classes - 1],
+    }
+}
+
+/// It pairs candidates with their scores, the highest first
+fn order<T>(candidates: Vec<T>, scores: Vec<f32>) -> Vec<Reranked<T>> {
+    let mut reranked: Vec<Reranked<T>> = candidates
+        .into_iter()
+        .zip(scores)
+        .map(|(candidate, score)| Reranked { candidate, score })
+        .collect();
+    reranked.sort_by(|first, second| second.score.total_cmp(&first.score));
+    reranked
+}
+
+#[cfg(test)]
+mod tests {
+    use onnxruntime::ndarray::{array, Array2};
+
+    use super::*;
+
+    #[test]
+    fn test_relevance() {
+        assert_eq!(relevance(array![0.0].view()), 0.5);
+        assert!(relevance(array![4.0].view()) > 0.98);
+        assert!((relevance(array![1.0, 1.0].view()) - 0.5).abs() < 1e-6);
+        assert!(relevance(array![-2.0, 3.0].view()) > 0.99);
+        // Large logits do not overflow
+        assert!((relevance(array![1000.0, 1000.0].view()) - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_relevances_need_a_class() {
+        let scores = relevances(array![[0.0], [4.0]].view()).unwrap();
+        assert_eq!(scores[0], 0.5);
+        assert!(scores[1] > 0.98);
+
+        let error = relevances(Array2::<f32>::zeros((2, 0)).view())
+            .err()
+            .unwrap();
+        assert!(matches!(
+            error,
+            Error::Dimension {
+                expected: 1,
+                found: 0
+            }
+        ));
+    }
+
+    #[test]
+    fn test_order() {
+        let reranked = order(vec!["a", "b", "c", "d"], vec![0.2, 0.9, 0.2, 0.5]);
+        let candidates: Vec<&str> = reranked.iter().map(|ranked| ranked.candidate).collect();
+        assert_eq!(candidates, ["b", "d", "a", "c"]);
+        assert_eq!(reranked[0].score, 0.9);
+    }
+
+    #[test]
+    fn test_reranker() {
+        let reranker = Reranker::build_model().unwrap();
+        let reranked = reranker
+            .rerank(
+                "How many people live in Berlin?",
+                vec![
+                    "Berlin has a population of 3,520,031 registered inhabitants.",
+                    "New York City is famous for the Metropolitan Museum of Art.",
+                ],
+                |passage| *passage,
+            )
+            .unwrap();
+
+        assert!(reranked[0].candidate.starts_with("Berlin"));
+        assert!(reranked[0].score > reranked[1].score);
+    }
+}
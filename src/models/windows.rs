@@ -0,0 +1,239 @@
+use std::collections::BTreeMap;
+
+use ndarray::{Array2, ArrayView1, ArrayView2, Axis};
+
+use crate::models::config::WindowAggregation;
+use crate::models::entities::TokenAlignment;
+
+/// The token classification logits of one document, stitched back together from the
+/// overlapping windows it was split into.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StitchedTokens {
+    offsets: Vec<(usize, usize)>,
+    special_tokens_mask: Vec<u32>,
+    word_ids: Vec<Option<u32>>,
+    logits: Array2<f32>,
+}
+
+impl StitchedTokens {
+    /// The alignment of the stitched tokens, ordered by their position in the document
+    #[must_use]
+    pub fn alignment(&self) -> TokenAlignment<'_> {
+        TokenAlignment {
+            offsets: &self.offsets,
+            special_tokens_mask: &self.special_tokens_mask,
+            word_ids: &self.word_ids,
+        }
+    }
+
+    #[must_use]
+    pub fn logits(&self) -> ArrayView2<'_, f32> {
+        self.logits.view()
+    }
+}
+
+/// The prediction kept so far for one token of the document.
+struct Candidate<'a> {
+    centrality: usize,
+    word_id: Option<u32>,
+    logits: ArrayView1<'a, f32>,
+}
+
+/// It merges the per-token logits of the windows of one document, keeping each token's
+/// prediction from the window where it sits furthest from the edges
+///
+/// Tokens are matched across windows by their offsets in the document, which the
+/// tokenizer keeps when it splits a text into overflowing windows.
+///
+/// Arguments:
+///
+/// * `windows`: The alignment and logits of every window of the document, one row per token.
+///
+/// Returns:
+///
+/// The `StitchedTokens` of the document, without special tokens
+pub fn stitch_token_logits<'a, I>(windows: I) -> StitchedTokens
+where
+    I: IntoIterator<Item = (TokenAlignment<'a>, ArrayView2<'a, f32>)>,
+{
+    let mut tokens: BTreeMap<(usize, usize), Candidate> = BTreeMap::new();
+
+    for (alignment, logits) in windows {
+        let content: Vec<usize> = (0..alignment.offsets.len().min(logits.nrows()))
+            .filter(|&index| {
+                let (start, end) = alignment.offsets[index];
+                alignment.special_tokens_mask[index] == 0 && start < end
+            })
+            .collect();
+
+        for (position, &index) in content.iter().enumerate() {
+            let centrality = position.min(content.len() - 1 - position);
+            let candidate = Candidate {
+                centrality,
+                word_id: alignment.word_ids[index],
+                logits: logits.index_axis_move(Axis(0), index),
+            };
+
+            // Ties keep the earlier window
+            match tokens.get(&alignment.offsets[index]) {
+                Some(kept) if kept.centrality >= centrality => {}
+                _ => {
+                    tokens.insert(alignment.offsets[index], candidate);
+                }
+            }
+        }
+    }
+
+    let classes = tokens.values().next().map_or(0, |kept| kept.logits.len());
+    let mut stitched = StitchedTokens {
+        logits: Array2::zeros((tokens.len(), classes)),
+        ..StitchedTokens::default()
+    };
+    for (row, (offset, candidate)) in tokens.into_iter().enumerate() {
+        stitched.offsets.push(offset);
+        stitched.special_tokens_mask.push(0);
+        stitched.word_ids.push(candidate.word_id);
+        stitched.logits.row_mut(row).assign(&candidate.logits);
+    }
+    stitched
+}
+
+/// It merges the class scores of the windows of one document
+///
+/// Arguments:
+///
+/// * `scores`: The class scores of every window of the document.
+/// * `aggregation`: Whether the windows are averaged or the highest score of each class is kept.
+///
+/// Returns:
+///
+/// One score per class
+pub fn aggregate_windows(scores: &[Vec<f32>], aggregation: WindowAggregation) -> Vec<f32> {
+    let classes = scores.first().map_or(0, Vec::len);
+
+    (0..classes)
+        .map(|class| {
+            let column = scores.iter().map(|window| window[class]);
+            match aggregation {
+                WindowAggregation::Mean => column.sum::<f32>() / scores.len() as f32,
+                WindowAggregation::Max => column.fold(f32::NEG_INFINITY, f32::max),
+            }
+        })
+        .collect()
+}
+
+/// It groups the rows of a windowed batch by the document each window came from
+///
+/// Arguments:
+///
+/// * `documents`: The document index of every window, in batch order.
+/// * `rows`: One row per window.
+///
+/// Returns:
+///
+/// The rows of each document, in document order
+pub fn group_windows<T>(documents: &[usize], rows: Vec<T>) -> Vec<Vec<T>> {
+    let count = documents.iter().max().map_or(0, |last| last + 1);
+    let mut grouped: Vec<Vec<T>> = (0..count).map(|_| Vec::new()).collect();
+
+    for (&document, row) in documents.iter().zip(rows) {
+        grouped[document].push(row);
+    }
+    grouped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn test_stitch_keeps_most_central_prediction() {
+        // "a b c d e" split in two windows of three tokens sharing "c"
+        let offsets = [(0, 0), (0, 1), (2, 3), (4, 5), (0, 0)];
+        let second_offsets = [(0, 0), (4, 5), (6, 7), (8, 9), (0, 0)];
+        let special = [1, 0, 0, 0, 1];
+        let first_words = [None, Some(0), Some(1), Some(2), None];
+        let second_words = [None, Some(2), Some(3), Some(4), None];
+
+        let first_logits = Array2::zeros((5, 1));
+        let mut second_logits = Array2::ones((5, 1));
+        second_logits[[1, 0]] = 2.0;
+
+        let stitched = stitch_token_logits([
+            (
+                TokenAlignment {
+                    offsets: &offsets,
+                    special_tokens_mask: &special,
+                    word_ids: &first_words,
+                },
+                first_logits.view(),
+            ),
+            (
+                TokenAlignment {
+                    offsets: &second_offsets,
+                    special_tokens_mask: &special,
+                    word_ids: &second_words,
+                },
+                second_logits.view(),
+            ),
+        ]);
+
+        assert_eq!(
+            stitched.alignment().offsets,
+            [(0, 1), (2, 3), (4, 5), (6, 7), (8, 9)]
+        );
+        assert_eq!(
+            stitched.alignment().word_ids,
+            [Some(0), Some(1), Some(2), Some(3), Some(4)]
+        );
+        // "c" sits on the edge of both windows, so the tie keeps the first one
+        assert_eq!(stitched.logits(), array![[0.0], [0.0], [0.0], [1.0], [1.0]]);
+    }
+
+    #[test]
+    fn test_stitch_prefers_central_window() {
+        let special = [0, 0, 0];
+        let words = [Some(0), Some(1), Some(2)];
+        let first = [(0, 1), (2, 3), (4, 5)];
+        let second = [(2, 3), (4, 5), (6, 7)];
+        let first_logits = Array2::zeros((3, 1));
+        let second_logits = Array2::ones((3, 1));
+
+        let stitched = stitch_token_logits([
+            (
+                TokenAlignment {
+                    offsets: &first,
+                    special_tokens_mask: &special,
+                    word_ids: &words,
+                },
+                first_logits.view(),
+            ),
+            (
+                TokenAlignment {
+                    offsets: &second,
+                    special_tokens_mask: &special,
+                    word_ids: &words,
+                },
+                second_logits.view(),
+            ),
+        ]);
+
+        // (2, 3) is central in the first window, (4, 5) in the second
+        assert_eq!(stitched.logits(), array![[0.0], [0.0], [1.0], [1.0]]);
+    }
+
+    #[test]
+    fn test_aggregate_windows() {
+        let scores = vec![vec![0.2, 0.8], vec![0.6, 0.4]];
+        let mean = aggregate_windows(&scores, WindowAggregation::Mean);
+        assert!((mean[0] - 0.4).abs() < 1e-6 && (mean[1] - 0.6).abs() < 1e-6);
+        assert_eq!(
+            aggregate_windows(&scores, WindowAggregation::Max),
+            [0.6, 0.8]
+        );
+
+        let grouped = group_windows(&[0, 0, 1], vec!['a', 'b', 'c']);
+        assert_eq!(grouped, [vec!['a', 'b'], vec!['c']]);
+    }
+}
@@ -1,159 +1,300 @@
-use std::cmp::Ordering;
-use std::collections::HashMap;
-use std::env::var;
-use std::path::Path;
+use crate::models::config::ModelConfig;
+use crate::models::entities::{decode_entities, Entity, LabelAggregation, TokenAlignment};
+use crate::models::labels::check_label_count;
+use crate::models::ner::{NerBackend, NerConfig};
+use crate::models::onnx::OnnxModel;
+use crate::models::windows::{
+    aggregate_windows, group_windows, stitch_token_logits, StitchedTokens,
+};
+use crate::tokens::bert_roberta_tokenizers::{encode, encode_windows};
+use crate::utilities::postprocess::softmax;
+use crate::utilities::vec_array::{array2_to_vec, array3_to_vec, view2, view3};
+use crate::Result;
 
-use crate::tokens::bert_roberta_tokenizers::tokenize;
-use crate::utilities::vec_array::{array2_to_vec, array3_to_vec};
+use onnxruntime::ndarray::{s, Array2, Axis};
 
-use onnxruntime::environment::Environment;
-use onnxruntime::ndarray::Axis;
-use onnxruntime::session::Session;
-use onnxruntime::tensor::ndarray_tensor::NdArrayTensor;
-use onnxruntime::{GraphOptimizationLevel, LoggingLevel};
+/// Class probabilities produced by the sentiment head for one input text.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SentimentScore {
+    pub negative: f32,
+    pub positive: f32,
+}
 
+/// A sentiment classifier that keeps its ONNX environment, session and tokenizer loaded
+/// between calls, so it can be built once and shared across threads behind an `Arc`.
+///
 /// Reference used from `NeuML` ;)
 /// https://colab.research.google.com/github/neuml/txtai/blob/master/examples/18_Export_and_run_models_with_ONNX.ipynb#scrollTo=_8fdRvO1fFBm
+pub struct SentimentClassifier {
+    model: OnnxModel,
+}
 
-pub fn predict_sentiment(text: &[&str]) -> Vec<Vec<f32>> {
-    // Start onnx session
+impl SentimentClassifier {
+    /// It loads the `text-classify` model declared in the bundled `models.json`
+    ///
+    /// Returns:
+    ///
+    /// A `SentimentClassifier`
+    pub fn build_model() -> Result<SentimentClassifier> {
+        SentimentClassifier::from_config(&ModelConfig::builtin("text-classify")?)
+    }
 
-    let path = var("RUST_ONNXRUNTIME_LIBRARY_PATH").ok();
+    /// It loads a sentiment model from its manifest entry
+    ///
+    /// Arguments:
+    ///
+    /// * `config`: The manifest entry of a two-label sequence classification model.
+    ///
+    /// Returns:
+    ///
+    /// A `SentimentClassifier`, or a `LabelCount` error when the model does not have
+    /// two labels
+    pub fn from_config(config: &ModelConfig) -> Result<SentimentClassifier> {
+        let model = OnnxModel::load(config)?;
+        check_label_count(config.model_path()?, model.labels()?, 2)?;
 
-    let builder = Environment::builder()
-        .with_name("test")
-        .with_log_level(LoggingLevel::Warning);
+        Ok(SentimentClassifier { model })
+    }
 
-    let builder = if let Some(path) = path {
-        builder.with_library_path(path)
-    } else {
-        builder
-    };
+    /// It runs the classifier over a batch of texts
+    ///
+    /// Arguments:
+    ///
+    /// * `text`: The texts to classify.
+    ///
+    /// Returns:
+    ///
+    /// A `SentimentScore` per input text, in the same order.
+    pub fn predict<S>(&self, text: &[S]) -> Result<Vec<SentimentScore>>
+    where
+        S: AsRef<str>,
+    {
+        let windows = encode_windows(text, self.model.tokenizer())
+            .map_err(|source| self.model.tokenizer_error(source))?;
 
-    let environment = builder.build().unwrap();
-    // Derive model path
-    let model = Path::new("resources/text-classify.onnx");
+        let scores = self.model.run_batches(&windows.encodings, |output, _| {
+            let logits = view2(&output)?;
+            // Models with dynamic output shapes are only checked once they run
+            check_label_count(
+                self.model.config().model_path()?,
+                self.model.labels()?,
+                logits.ncols(),
+            )?;
+            Ok(array2_to_vec(&softmax(&logits, Axis(1))))
+        })?;
+        let aggregation = self
+            .model
+            .config()
+            .window
+            .map(|window| window.aggregation)
+            .unwrap_or_default();
 
-    let session = environment
-        .new_session_builder()
-        .unwrap()
-        .with_graph_optimization_level(GraphOptimizationLevel::Basic)
-        .unwrap()
-        .with_model_from_file(model)
-        .unwrap();
+        Ok(group_windows(&windows.documents, scores)
+            .iter()
+            .map(|windows| aggregate_windows(windows, aggregation))
+            .map(|scores| SentimentScore {
+                negative: scores[0],
+                positive: scores[1],
+            })
+            .collect())
+    }
+}
 
-    let inputs = tokenize(text, "bert-base-uncased");
-    let (input_ids, attention_mask, tids) = inputs;
+/// It builds a one-off `SentimentClassifier` and runs it over `text`.
+/// Prefer keeping a `SentimentClassifier` around when predicting more than once.
+pub fn predict_sentiment(text: &[&str]) -> Result<Vec<Vec<f32>>> {
+    Ok(SentimentClassifier::build_model()?
+        .predict(text)?
+        .iter()
+        .map(|score| vec![score.negative, score.positive])
+        .collect())
+}
 
-    let outputs = session
-        .run(vec![input_ids.into(), attention_mask.into(), tids.into()])
-        .unwrap();
+/// It runs the NER model and returns the label logits of every token of each input text,
+/// special and padding tokens included, padded to the longest text.
+/// Texts longer than the model's window are cut to their first window, `predict_windows`
+/// covers them whole.
+pub fn predict<S>(text: &[S], model: &OnnxModel) -> Result<Vec<Vec<Vec<f32>>>>
+where
+    S: AsRef<str>,
+{
+    let inputs = encode(text, model.tokenizer()).map_err(|source| model.tokenizer_error(source))?;
 
-    let output = outputs[0].float_array().unwrap();
+    model.run_with(inputs, |output| Ok(array3_to_vec(&view3(&output)?)))
+}
 
-    array2_to_vec(&output.softmax(Axis(1)).to_owned())
+/// It runs the NER model over the overlapping windows of every input text and stitches
+/// the windows of each text back together
+///
+/// Arguments:
+///
+/// * `text`: The texts to run.
+/// * `model`: A model built with `build_model`.
+///
+/// Returns:
+///
+/// The `StitchedTokens` of each input text, without special or padding tokens
+pub fn predict_windows<S>(text: &[S], model: &OnnxModel) -> Result<Vec<StitchedTokens>>
+where
+    S: AsRef<str>,
+{
+    Ok(predict_tokens(text, model)?.0)
 }
 
-pub fn predict<S>(text: &[S], session: &Session) -> Vec<Vec<Vec<f32>>>
+/// It runs the NER model and decodes its logits into entities for every input text
+///
+/// Arguments:
+///
+/// * `text`: The texts to extract entities from.
+/// * `model`: A model built with `build_model`.
+/// * `aggregation`: How the sub token predictions of a word are merged.
+///
+/// Returns:
+///
+/// The entities found in each input text.
+pub fn predict_entities<S>(
+    text: &[S],
+    model: &OnnxModel,
+    aggregation: LabelAggregation,
+) -> Result<Vec<Vec<Entity>>>
 where
     S: AsRef<str>,
 {
-    // Start onnx session
+    let labels = model.labels()?;
+    let (tokens, classes) = predict_tokens(text, model)?;
+    if let Some(classes) = classes {
+        check_label_count(model.config().model_path()?, labels, classes)?;
+    }
+
+    Ok(text
+        .iter()
+        .zip(&tokens)
+        .map(|(text, tokens)| {
+            decode_entities(
+                text.as_ref(),
+                tokens.logits(),
+                &tokens.alignment(),
+                labels,
+                aggregation,
+            )
+        })
+        .collect())
+}
 
-    let inputs = tokenize(text, "xlm-roberta-large-finetuned-conll03-english");
-    let (input_ids, attention_mask, _) = inputs;
+/// It runs a token classifier over the windows of every text and stitches them back together
+///
+/// Returns:
+///
+/// The `StitchedTokens` of each text and the number of classes of the model output,
+/// `None` when there was nothing to run
+fn predict_tokens<S>(text: &[S], model: &OnnxModel) -> Result<(Vec<StitchedTokens>, Option<usize>)>
+where
+    S: AsRef<str>,
+{
+    let windows =
+        encode_windows(text, model.tokenizer()).map_err(|source| model.tokenizer_error(source))?;
 
-    let outputs = session
-        .run(vec![input_ids.into(), attention_mask.into()])
-        .unwrap();
+    // The runtime frees each batch output before the next batch runs, while the windows
+    // of one text can land in different length buckets, so the logits of the real tokens
+    // of every window are the one copy kept until stitching.
+    let predictions = model.run_batches(&windows.encodings, |output, attention_mask| {
+        Ok(view3(&output)?
+            .outer_iter()
+            .zip(attention_mask.outer_iter())
+            .map(|(logits, mask)| {
+                let tokens = mask.iter().filter(|&&attend| attend != 0).count();
+                logits.slice(s![..tokens, ..]).to_owned()
+            })
+            .collect())
+    })?;
+    let classes = predictions.first().map(Array2::ncols);
 
-    let output = outputs[0].float_array().unwrap();
+    let tokens = group_windows(
+        &windows.documents,
+        windows.encodings.iter().zip(&predictions).collect(),
+    )
+    .iter()
+    .map(|windows| {
+        stitch_token_logits(
+            windows
+                .iter()
+                .map(|(encoding, logits)| (TokenAlignment::from(*encoding), logits.view())),
+        )
+    })
+    .collect();
 
-    return array3_to_vec(&output.view().to_owned());
+    Ok((tokens, classes))
 }
 
-pub fn build_model() -> Session {
-    let path = var("RUST_ONNXRUNTIME_LIBRARY_PATH").ok();
-
-    let builder = Environment::builder()
-        .with_name("test")
-        .with_log_level(LoggingLevel::Warning);
-
-    let builder = if let Some(path) = path {
-        builder.with_library_path(path)
-    } else {
-        builder
-    };
-
-    let environment = builder.build().unwrap();
-    // Derive model path
-    let model = Path::new("resources/roberta-ner.onnx");
-
-    let session = environment
-        .new_session_builder()
-        .unwrap()
-        .with_graph_optimization_level(GraphOptimizationLevel::Basic)
-        .unwrap()
-        .with_model_from_file(model)
-        .unwrap();
-    session
+/// The ONNX XLM Roberta NER model behind the `NerBackend` interface.
+/// It keeps its environment, session and tokenizer loaded between calls.
+pub struct XlmRobertaOnnxNer {
+    model: OnnxModel,
+    aggregation: LabelAggregation,
 }
 
-fn parse_tokens(predictions: &Vec<Vec<Vec<f32>>>) -> Vec<Vec<&str>> {
-    let id_labels = HashMap::from([
-        (0, "B-LOC"),
-        (1, "B-MISC"),
-        (2, "B-ORG"),
-        (3, "I-LOC"),
-        (4, "I-MISC"),
-        (5, "I-ORG"),
-        (6, "I-PER"),
-        (7, "O"),
-    ]);
-
-    let res = predictions
-        .iter()
-        .map(|s| {
-            s.iter()
-                .map(|t| {
-                    id_labels
-                        .get(
-                            &t.iter()
-                                .enumerate()
-                                .max_by(|(_, a), (_, b)| {
-                                    a.partial_cmp(b).unwrap_or(Ordering::Equal)
-                                })
-                                .map(|(index, _)| index)
-                                .unwrap(),
-                        )
-                        .unwrap()
-                        .to_owned()
-                })
-                .collect()
+impl NerBackend for XlmRobertaOnnxNer {
+    fn load(config: &NerConfig) -> Result<Self> {
+        let model = match &config.model {
+            Some(model) => OnnxModel::load(model)?,
+            None => OnnxModel::load(&ModelConfig::builtin("roberta-ner")?)?,
+        };
+
+        Ok(XlmRobertaOnnxNer {
+            model,
+            aggregation: config.aggregation,
         })
-        .collect();
+    }
+
+    fn extract<S>(&self, input: &[S]) -> Result<Vec<Vec<Entity>>>
+    where
+        S: AsRef<str>,
+    {
+        predict_entities(input, &self.model, self.aggregation)
+    }
+}
 
-    dbg!(&res);
-    res
+/// It loads the `roberta-ner` model declared in the bundled `models.json`, keeping its
+/// environment alive for as long as its session
+pub fn build_model() -> Result<OnnxModel> {
+    OnnxModel::load(&ModelConfig::builtin("roberta-ner")?)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::utilities::vec_array::{assert_golden, ParityTolerance};
     #[test]
     fn test_softmax() {
         // Tokenize input string
         let text_positive = vec!["You are awesome", "You are bad"];
 
-        let responses = predict_sentiment(&text_positive);
-        let res_positive = responses.get(0).unwrap();
-        let res_negative = responses.get(1).unwrap();
+        let responses = predict_sentiment(&text_positive).unwrap();
+        let res_positive = &responses[0];
+        let res_negative = &responses[1];
 
         assert!(res_positive[0] < res_positive[1]);
         println!("{} {:?}", text_positive[0], res_positive);
         assert!(res_negative[0] > res_negative[1]);
     }
 
+    #[test]
+    fn test_sentiment_classifier() {
+        let classifier = std::sync::Arc::new(SentimentClassifier::build_model().unwrap());
+
+        let handles: Vec<_> = ["You are awesome", "You are bad"]
+            .into_iter()
+            .map(|text| {
+                let classifier = std::sync::Arc::clone(&classifier);
+                std::thread::spawn(move || classifier.predict(&[text]).unwrap()[0])
+            })
+            .collect();
+        let scores: Vec<SentimentScore> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+        assert!(scores[0].negative < scores[0].positive);
+        assert!(scores[1].negative > scores[1].positive);
+    }
+
     #[test]
     fn test_ner() {
         // Tokenize input string
@@ -161,15 +302,32 @@ mod tests {
             "HuggingFace is a company based in Paris and New York",
             "I'm Waner and work for Microsoft from Brazil",
         ];
-        let session = build_model();
-
-        let responses = predict(&text_positive, &session);
-        println!(
-            "{:?} {:?} {:?}",
-            responses.len(),
-            responses[0].len(),
-            responses[0][0].len()
+        let model = build_model().unwrap();
+
+        // The logits PyTorch returns for the same padded batch, written by
+        // `scripts/export_ner_golden.py`
+        let logits = model
+            .run(encode(&text_positive, model.tokenizer()).unwrap())
+            .unwrap();
+        assert_golden(
+            &logits,
+            "resources/roberta-ner.logits.npy",
+            ParityTolerance::default(),
         );
-        println!("{:?}", parse_tokens(&responses));
+
+        let responses = predict(&text_positive, &model).unwrap();
+        assert_eq!(responses, array3_to_vec(&view3(&logits).unwrap()));
+
+        let stitched = predict_windows(&text_positive, &model).unwrap();
+        // The stitched tokens drop <s>, </s> and the padding of the shorter text
+        for (stitched, padded) in stitched.iter().zip(&responses) {
+            assert!(stitched.logits().nrows() + 2 <= padded.len());
+        }
+
+        let entities = predict_entities(&text_positive, &model, LabelAggregation::First).unwrap();
+        println!("{entities:?}");
+        let labels: Vec<&str> = entities[1].iter().map(|e| e.label.as_str()).collect();
+        assert!(labels.contains(&"PER"));
+        assert!(labels.contains(&"ORG"));
     }
 }
@@ -1,11 +1,23 @@
 extern crate rust_bert;
 
+use ndarray::Array2;
+use rust_bert::bert::BertConfig;
 use rust_bert::pipelines::common::ModelType;
 use rust_bert::pipelines::ner::NERModel;
-use rust_bert::pipelines::token_classification::TokenClassificationConfig;
-use rust_bert::resources::RemoteResource;
-use rust_bert::roberta::{RobertaConfigResources, RobertaModelResources, RobertaVocabResources};
-use rust_bert::RustBertError;
+use rust_bert::pipelines::token_classification::{
+    LabelAggregationOption, TokenClassificationConfig,
+};
+use rust_bert::resources::{RemoteResource, ResourceProvider};
+use rust_bert::roberta::{
+    RobertaConfigResources, RobertaForTokenClassification, RobertaModelResources,
+    RobertaVocabResources,
+};
+use rust_bert::{Config, RustBertError};
+use tch::{nn, no_grad, Device, Kind, Tensor};
+
+use crate::models::entities::Entity;
+use crate::models::ner::{from_rust_bert, rust_bert_aggregation, NerBackend, NerConfig};
+use crate::Error;
 
 // /// `NERModel::new(config)` creates a new NER model from the XML Roberta configuration `config`
 /// It creates a `TokenClassificationConfig` object, which is then used to create a `NERModel` object
@@ -14,6 +26,21 @@ use rust_bert::RustBertError;
 ///
 /// A `NERModel`
 pub fn build_model() -> Result<NERModel, RustBertError> {
+    build_model_with_aggregation(LabelAggregationOption::First)
+}
+
+/// It builds the XLM Roberta NER model with a custom label aggregation for sub tokens
+///
+/// Arguments:
+///
+/// * `label_aggregation`: How the labels of the sub tokens of a word are merged.
+///
+/// Returns:
+///
+/// A `NERModel`
+pub fn build_model_with_aggregation(
+    label_aggregation: LabelAggregationOption,
+) -> Result<NERModel, RustBertError> {
     let config: TokenClassificationConfig = TokenClassificationConfig {
         model_type: ModelType::XLMRoberta,
         model_resource: Box::new(RemoteResource::from_pretrained(
@@ -26,6 +53,7 @@ pub fn build_model() -> Result<NERModel, RustBertError> {
             RobertaVocabResources::XLM_ROBERTA_NER_EN,
         )),
         lower_case: false,
+        label_aggregation_function: label_aggregation,
         // device: Device::cuda_if_available(),
         ..Default::default()
     };
@@ -34,3 +62,105 @@ pub fn build_model() -> Result<NERModel, RustBertError> {
     let token_classification_model = NERModel::new(config)?;
     Ok(token_classification_model)
 }
+
+/// The token classification head of the XLM Roberta NER model, run directly through tch.
+///
+/// `NERModel` only returns decoded entities, this returns the raw label logits of every
+/// token for the same weights, so they can be compared with another backend.
+pub struct XlmRobertaTokenLogits {
+    model: RobertaForTokenClassification,
+    // The weights live in the store, which is kept as long as the model
+    _var_store: nn::VarStore,
+}
+
+impl XlmRobertaTokenLogits {
+    /// It loads the weights used by `build_model` on the CPU
+    ///
+    /// Returns:
+    ///
+    /// A `XlmRobertaTokenLogits`
+    pub fn build_model() -> Result<XlmRobertaTokenLogits, RustBertError> {
+        let config_path =
+            RemoteResource::from_pretrained(RobertaConfigResources::XLM_ROBERTA_NER_EN)
+                .get_local_path()?;
+        let weights_path =
+            RemoteResource::from_pretrained(RobertaModelResources::XLM_ROBERTA_NER_EN)
+                .get_local_path()?;
+
+        let config = BertConfig::from_file(config_path);
+        let mut var_store = nn::VarStore::new(Device::Cpu);
+        let model = RobertaForTokenClassification::new(var_store.root(), &config);
+        var_store.load(weights_path)?;
+
+        Ok(XlmRobertaTokenLogits {
+            model,
+            _var_store: var_store,
+        })
+    }
+
+    /// It runs one sequence of token ids through the model
+    ///
+    /// Arguments:
+    ///
+    /// * `input_ids`: The ids of every token, special tokens included, without padding.
+    ///
+    /// Returns:
+    ///
+    /// The label logits, shaped `(tokens, labels)`
+    pub fn logits(&self, input_ids: &[i64]) -> crate::Result<Array2<f32>> {
+        let tokens = input_ids.len();
+        let input_ids = Tensor::of_slice(input_ids).view((1, tokens as i64));
+
+        let output = no_grad(|| {
+            self.model
+                .forward_t(Some(&input_ids), None, None, None, None, false)
+        });
+        let shape: Vec<usize> = output
+            .logits
+            .size()
+            .iter()
+            .map(|&size| size as usize)
+            .collect();
+        let [1, rows, labels] = shape[..] else {
+            return Err(Error::Rank {
+                expected: 3,
+                found: shape,
+            });
+        };
+        if rows != tokens {
+            return Err(Error::Dimension {
+                expected: tokens,
+                found: rows,
+            });
+        }
+        let values = Vec::<f32>::try_from(&output.logits.to_kind(Kind::Float).view(-1))
+            .map_err(|source| Error::rust_bert("xlm-roberta-ner-en")(source.into()))?;
+        let found = values.len();
+
+        Array2::from_shape_vec((tokens, labels), values).map_err(|_| Error::Dimension {
+            expected: tokens * labels,
+            found,
+        })
+    }
+}
+
+/// The rust-bert XLM Roberta NER model behind the `NerBackend` interface.
+pub struct XlmRobertaRustBertNer {
+    model: NERModel,
+}
+
+impl NerBackend for XlmRobertaRustBertNer {
+    fn load(config: &NerConfig) -> crate::Result<Self> {
+        let model = rust_bert_aggregation(config.aggregation)
+            .and_then(build_model_with_aggregation)
+            .map_err(Error::rust_bert("xlm-roberta-ner-en"))?;
+        Ok(XlmRobertaRustBertNer { model })
+    }
+
+    fn extract<S>(&self, input: &[S]) -> crate::Result<Vec<Vec<Entity>>>
+    where
+        S: AsRef<str>,
+    {
+        Ok(from_rust_bert(self.model.predict_full_entities(input)))
+    }
+}
@@ -1,8 +1,15 @@
+use crate::models::config::{TokenizerSource, Windowing};
+use crate::tokens::tokenizer_store::TokenizerStore;
+use crate::{Error, Result};
+
+use std::sync::Arc;
+
 use ndarray::{Array2, ArrayBase, Dim, OwnedRepr};
-use tokenizers::tokenizer::Tokenizer;
+use tokenizers::tokenizer::{Encoding, Tokenizer};
 use tokenizers::utils::padding::{
     PaddingDirection::Right, PaddingParams, PaddingStrategy::BatchLongest,
 };
+use tokenizers::utils::truncation::{TruncationDirection, TruncationParams, TruncationStrategy};
 
 pub type Embeddings = (
     ArrayBase<OwnedRepr<i64>, Dim<[usize; 2]>>,
@@ -10,14 +17,41 @@ pub type Embeddings = (
     ArrayBase<OwnedRepr<i64>, Dim<[usize; 2]>>,
 );
 
-pub fn tokenize<S>(input_texts: &[S], tokenizer_name: &str) -> Embeddings
-where
-    S: AsRef<str>,
-{
-    let batch_size = input_texts.len();
-    // Load tokenizer from HF Hub
-    let mut tokenizer = Tokenizer::from_pretrained(tokenizer_name, None).unwrap();
+/// It loads a tokenizer through the global `TokenizerStore` and configures it to pad every
+/// batch to its longest input. The hub is only contacted the first time a name is seen, and
+/// the padded tokenizer is built once and then shared by every call.
+///
+/// Arguments:
+///
+/// * `tokenizer_name`: The name of the tokenizer on the HF Hub.
+///
+/// Returns:
+///
+/// A shared, padded `Tokenizer`
+pub fn load_tokenizer(tokenizer_name: &str) -> Result<Arc<Tokenizer>> {
+    TokenizerStore::global().get_configured(tokenizer_name, "batch-padding", with_batch_padding)
+}
+
+/// It loads a tokenizer from the hub or from a local `tokenizer.json`, as declared in a model
+/// manifest, keeping the padding and truncation of its `tokenizer.json`
+///
+/// Arguments:
+///
+/// * `source`: Where the tokenizer comes from.
+///
+/// Returns:
+///
+/// The `Tokenizer`, shared with the global `TokenizerStore` when it comes from the hub
+pub fn load_tokenizer_from(source: &TokenizerSource) -> Result<Arc<Tokenizer>> {
+    match source {
+        TokenizerSource::Hub(name) => TokenizerStore::global().get(name),
+        TokenizerSource::File(path) => Tokenizer::from_file(path)
+            .map(Arc::new)
+            .map_err(Error::tokenizer(&source.name())),
+    }
+}
 
+fn with_batch_padding(tokenizer: &mut Tokenizer) {
     tokenizer.with_padding(Some(PaddingParams {
         strategy: BatchLongest,
         direction: Right,
@@ -26,26 +60,185 @@ where
         pad_token: "[PAD]".into(),
         pad_to_multiple_of: Some(2),
     }));
+}
+
+/// A batch of overlapping windows, with every window mapped back to its input text.
+pub struct WindowedEncoding {
+    /// The raw `Encoding` of every window, with offsets into its input text
+    pub encodings: Vec<Encoding>,
+    /// The index of the input text of every window
+    pub documents: Vec<usize>,
+}
+
+/// It configures a tokenizer to split long inputs into overlapping windows
+///
+/// Arguments:
+///
+/// * `tokenizer`: The tokenizer to configure.
+/// * `windowing`: The length of a window and the number of tokens two windows share.
+pub fn with_windowing(tokenizer: &mut Tokenizer, windowing: Windowing) {
+    tokenizer.with_truncation(Some(TruncationParams {
+        direction: TruncationDirection::Right,
+        max_length: windowing.max_length,
+        strategy: TruncationStrategy::LongestFirst,
+        stride: windowing.stride,
+    }));
+}
+
+/// It configures a tokenizer to cut sentence pairs down to `max_length` tokens, trimming
+/// the longer sentence of a pair first, which for a (query, passage) pair is the passage
+///
+/// Arguments:
+///
+/// * `tokenizer`: The tokenizer to configure.
+/// * `max_length`: The most tokens of an encoded pair, special tokens included.
+pub fn with_pair_truncation(tokenizer: &mut Tokenizer, max_length: usize) {
+    tokenizer.with_truncation(Some(TruncationParams {
+        direction: TruncationDirection::Right,
+        max_length,
+        strategy: TruncationStrategy::LongestFirst,
+        stride: 0,
+    }));
+}
+
+/// It encodes sentence pairs, such as a query and a passage, into one input each
+///
+/// The tokens of the first sentence get the token type id 0 and those of the second 1,
+/// the segment layout BERT cross-encoders are trained on.
+///
+/// Arguments:
+///
+/// * `pairs`: The sentence pairs to encode.
+/// * `tokenizer`: A tokenizer, usually configured with `with_pair_truncation`.
+///
+/// Returns:
+///
+/// One unpadded `Encoding` per pair
+pub fn encode_pairs<A, B>(
+    pairs: &[(A, B)],
+    tokenizer: &Tokenizer,
+) -> tokenizers::Result<Vec<Encoding>>
+where
+    A: AsRef<str>,
+    B: AsRef<str>,
+{
+    let inputs = pairs
+        .iter()
+        .map(|(first, second)| (first.as_ref(), second.as_ref()))
+        .collect();
+
+    tokenizer.encode_batch(inputs, true)
+}
 
+/// It encodes a batch of texts with an already loaded tokenizer
+///
+/// Arguments:
+///
+/// * `input_texts`: The texts to encode.
+/// * `tokenizer`: A tokenizer, usually built with `load_tokenizer`.
+///
+/// Returns:
+///
+/// The token ids, attention masks and token type ids as `Embeddings`
+pub fn encode<S>(input_texts: &[S], tokenizer: &Tokenizer) -> tokenizers::Result<Embeddings>
+where
+    S: AsRef<str>,
+{
+    Ok(encode_with_offsets(input_texts, tokenizer)?.0)
+}
+
+/// It encodes a batch of texts and also returns the raw `Encoding` of each text,
+/// which keeps the offsets, word ids and special tokens mask of every token
+///
+/// Arguments:
+///
+/// * `input_texts`: The texts to encode.
+/// * `tokenizer`: A tokenizer, usually built with `load_tokenizer`.
+///
+/// Returns:
+///
+/// A tuple of `Embeddings` and one `Encoding` per input text
+pub fn encode_with_offsets<S>(
+    input_texts: &[S],
+    tokenizer: &Tokenizer,
+) -> tokenizers::Result<(Embeddings, Vec<Encoding>)>
+where
+    S: AsRef<str>,
+{
     let inputs = input_texts.iter().map(|s| s.as_ref()).collect();
 
     // Encode input text
-    let encoding = tokenizer.encode_batch(inputs, true).unwrap();
+    let encoding = tokenizer.encode_batch(inputs, true)?;
 
-    let max_len = encoding
+    Ok((
+        to_embeddings(&encoding.iter().collect::<Vec<_>>()),
+        encoding,
+    ))
+}
+
+/// It encodes a batch of texts into the overlapping windows set up with `with_windowing`.
+/// A tokenizer without truncation produces a single window per text.
+///
+/// Arguments:
+///
+/// * `input_texts`: The texts to encode.
+/// * `tokenizer`: A tokenizer, usually configured with `with_windowing`.
+///
+/// Returns:
+///
+/// A `WindowedEncoding` with the windows of every text, in input order
+pub fn encode_windows<S>(
+    input_texts: &[S],
+    tokenizer: &Tokenizer,
+) -> tokenizers::Result<WindowedEncoding>
+where
+    S: AsRef<str>,
+{
+    let inputs = input_texts.iter().map(|s| s.as_ref()).collect();
+
+    let mut encodings = Vec::new();
+    let mut documents = Vec::new();
+    for (document, mut encoding) in tokenizer
+        .encode_batch(inputs, true)?
+        .into_iter()
+        .enumerate()
+    {
+        let overflowing = encoding.take_overflowing();
+        documents.resize(documents.len() + overflowing.len() + 1, document);
+        encodings.push(encoding);
+        encodings.extend(overflowing);
+    }
+
+    Ok(WindowedEncoding {
+        encodings,
+        documents,
+    })
+}
+
+impl WindowedEncoding {
+    /// The token ids, attention masks and token type ids of every window, padded to the longest
+    #[must_use]
+    pub fn embeddings(&self) -> Embeddings {
+        to_embeddings(&self.encodings.iter().collect::<Vec<_>>())
+    }
+}
+
+/// It stacks encodings into `Embeddings`, padding shorter ones with zeros
+pub(crate) fn to_embeddings(encodings: &[&Encoding]) -> Embeddings {
+    let max_len = encodings
         .iter()
         .map(|feature| feature.get_ids().len())
         .max()
-        .unwrap();
+        .unwrap_or(0);
 
-    let input_shape = (batch_size, max_len);
+    let input_shape = (encodings.len(), max_len);
 
     let mut masks = Array2::<i64>::zeros(input_shape);
     let mut token_ids = Array2::<i64>::zeros(input_shape);
     let mut type_ids = Array2::<i64>::zeros(input_shape);
 
-    for (i, e) in encoding.iter().enumerate() {
-        for j in 0..max_len {
+    for (i, e) in encodings.iter().enumerate() {
+        for j in 0..e.get_ids().len() {
             token_ids[[i, j]] = i64::from(e.get_ids()[j].to_owned());
             masks[[i, j]] = i64::from(e.get_attention_mask()[j].to_owned());
             type_ids[[i, j]] = i64::from(e.get_type_ids()[j].to_owned());
@@ -55,6 +248,38 @@ where
     (token_ids, masks, type_ids)
 }
 
+pub fn tokenize<S>(input_texts: &[S], tokenizer_name: &str) -> Result<Embeddings>
+where
+    S: AsRef<str>,
+{
+    encode(input_texts, &*load_tokenizer(tokenizer_name)?).map_err(Error::tokenizer(tokenizer_name))
+}
+
+/// It tokenizes texts into overlapping windows of at most `windowing.max_length` tokens
+///
+/// Arguments:
+///
+/// * `input_texts`: The texts to tokenize.
+/// * `tokenizer_name`: The name of the tokenizer on the HF Hub.
+/// * `windowing`: The length of a window and the number of tokens two windows share.
+///
+/// Returns:
+///
+/// A `WindowedEncoding` with the windows of every text
+pub fn tokenize_windows<S>(
+    input_texts: &[S],
+    tokenizer_name: &str,
+    windowing: Windowing,
+) -> Result<WindowedEncoding>
+where
+    S: AsRef<str>,
+{
+    let mut tokenizer = Tokenizer::clone(&*load_tokenizer(tokenizer_name)?);
+    with_windowing(&mut tokenizer, windowing);
+    encode_windows(input_texts, &tokenizer).map_err(Error::tokenizer(tokenizer_name))
+}
+
+#[cfg(test)]
 mod tests {
     use ndarray::array;
 
@@ -62,7 +287,7 @@ mod tests {
     #[test]
     fn test_tokenizer() {
         let test_inputs = vec!["You are awesome", "You are bad"];
-        let token_results = tokenize(&test_inputs, "bert-base-uncased");
+        let token_results = tokenize(&test_inputs, "bert-base-uncased").unwrap();
         let (input_ids, attention_mask, tids) = token_results;
         println!("{input_ids:?}");
         assert_eq!(
@@ -82,4 +307,33 @@ mod tests {
         println!("{tids:?}");
         assert_eq!(array![[0, 0, 0, 0, 0, 0], [0, 0, 0, 0, 0, 0]], tids);
     }
+
+    #[test]
+    fn test_tokenize_windows() {
+        let text = "one two three four five six seven eight nine ten";
+        let windowing = Windowing {
+            max_length: 6,
+            stride: 2,
+            aggregation: Default::default(),
+        };
+        let windows = tokenize_windows(&[text, "short"], "bert-base-uncased", windowing).unwrap();
+
+        let (input_ids, _, _) = windows.embeddings();
+        assert_eq!(input_ids.ncols(), 6);
+        assert_eq!(windows.documents.last(), Some(&1));
+        assert!(windows.documents.iter().filter(|&&d| d == 0).count() > 1);
+        assert_eq!(windows.encodings.len(), windows.documents.len());
+    }
+
+    #[test]
+    fn test_encode_reuses_tokenizer() {
+        let tokenizer = load_tokenizer("bert-base-uncased").unwrap();
+        let (first, _, _) = encode(&["You are awesome"], &tokenizer).unwrap();
+        let (second, _, _) = encode(&["You are awesome"], &tokenizer).unwrap();
+        assert_eq!(first, second);
+        assert!(Arc::ptr_eq(
+            &tokenizer,
+            &load_tokenizer("bert-base-uncased").unwrap()
+        ));
+    }
 }
@@ -9,6 +9,10 @@ use rust_bert::pipelines::token_classification::{
 use rust_bert::resources::RemoteResource;
 use rust_bert::RustBertError;
 
+use crate::models::entities::Entity;
+use crate::models::ner::{from_rust_bert, rust_bert_aggregation, NerBackend, NerConfig};
+use crate::Error;
+
 // /// `NERModel::new(config)` creates a new NER model from the BertModel configuration `config`
 /// It creates a `TokenClassificationConfig` object, which is then used to create a `NERModel` object
 ///
@@ -16,6 +20,21 @@ use rust_bert::RustBertError;
 ///
 /// A `NERModel`
 pub fn build_model() -> Result<NERModel, RustBertError> {
+    build_model_with_aggregation(LabelAggregationOption::Mode)
+}
+
+/// It builds the BERT NER model with a custom label aggregation for sub tokens
+///
+/// Arguments:
+///
+/// * `label_aggregation`: How the labels of the sub tokens of a word are merged.
+///
+/// Returns:
+///
+/// A `NERModel`
+pub fn build_model_with_aggregation(
+    label_aggregation: LabelAggregationOption,
+) -> Result<NERModel, RustBertError> {
     let config = TokenClassificationConfig::new(
         ModelType::Bert,
         RemoteResource::from_pretrained(BertModelResources::BERT_NER),
@@ -25,10 +44,31 @@ pub fn build_model() -> Result<NERModel, RustBertError> {
         false, //lowercase
         false,
         None,
-        LabelAggregationOption::Mode,
+        label_aggregation,
     );
 
     //    Create the model
     let token_classification_model = NERModel::new(config)?;
     Ok(token_classification_model)
 }
+
+/// The rust-bert BERT NER model behind the `NerBackend` interface.
+pub struct BertRustBertNer {
+    model: NERModel,
+}
+
+impl NerBackend for BertRustBertNer {
+    fn load(config: &NerConfig) -> crate::Result<Self> {
+        let model = rust_bert_aggregation(config.aggregation)
+            .and_then(build_model_with_aggregation)
+            .map_err(Error::rust_bert("bert-ner"))?;
+        Ok(BertRustBertNer { model })
+    }
+
+    fn extract<S>(&self, input: &[S]) -> crate::Result<Vec<Vec<Entity>>>
+    where
+        S: AsRef<str>,
+    {
+        Ok(from_rust_bert(self.model.predict_full_entities(input)))
+    }
+}
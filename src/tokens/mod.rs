@@ -1,3 +1,5 @@
 pub mod bert_roberta_tokenizers;
 pub mod bert_rustbert;
 pub mod roberta_rustbert;
+pub mod text_encoder;
+pub mod tokenizer_store;
@@ -1,19 +1,23 @@
-use cached_path::{Cache, Error, ProgressBar};
-use rust_tokenizers::tokenizer::MultiThreadedTokenizer;
-
-use rust_tokenizers::{error::TokenizerError, tokenizer::RobertaTokenizer};
+use cached_path::{Cache, ProgressBar};
+use rust_tokenizers::tokenizer::RobertaTokenizer;
 use std::path::PathBuf;
 
-pub fn download_file_to_cache(src: &str) -> Result<PathBuf, Error> {
-    let mut cache_dir = dirs::home_dir().unwrap();
+use crate::{Error, Result};
+
+pub fn download_file_to_cache(src: &str) -> Result<PathBuf> {
+    let mut cache_dir = dirs::home_dir().ok_or_else(|| Error::NoCacheDirectory {
+        resource: src.to_string(),
+    })?;
     cache_dir.push(".cache");
     cache_dir.push(".rust_tokenizers");
 
     let cached_path = Cache::builder()
         .dir(cache_dir)
         .progress_bar(Some(ProgressBar::Light))
-        .build()?
-        .cached_path(src)?;
+        .build()
+        .map_err(Error::download(src))?
+        .cached_path(src)
+        .map_err(Error::download(src))?;
     Ok(cached_path)
 }
 
@@ -26,21 +30,17 @@ pub fn download_file_to_cache(src: &str) -> Result<PathBuf, Error> {
 /// Returns:
 ///
 /// A `RobertaTokenizer`
-///
-
-pub fn build_tokenizer() -> Result<RobertaTokenizer, TokenizerError> {
+pub fn build_tokenizer() -> Result<RobertaTokenizer> {
     let lower_case = false;
     let add_prefix_space = true;
 
     let vocab_path = download_file_to_cache(
         "https://s3.amazonaws.com/models.huggingface.co/bert/roberta-base-vocab.json",
-    )
-    .unwrap();
+    )?;
 
     let merges_path = download_file_to_cache(
         "https://s3.amazonaws.com/models.huggingface.co/bert/roberta-base-merges.txt",
-    )
-    .unwrap();
+    )?;
 
     let tokenizer = RobertaTokenizer::from_file(
         vocab_path,  // "resources/roberta-base-vocab.json",
@@ -48,12 +48,14 @@ pub fn build_tokenizer() -> Result<RobertaTokenizer, TokenizerError> {
         lower_case,
         add_prefix_space,
     )
-    .unwrap();
+    .map_err(Error::rust_tokenizer("roberta-base"))?;
 
     Ok(tokenizer)
 }
 
+#[cfg(test)]
 mod tests {
+    use rust_tokenizers::tokenizer::MultiThreadedTokenizer;
 
     use super::*;
     #[test]
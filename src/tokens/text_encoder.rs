@@ -0,0 +1,425 @@
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use ndarray::{s, Array2, ArrayView1};
+use rust_tokenizers::tokenizer::{Tokenizer as RustTokenizer, TruncationStrategy};
+use rust_tokenizers::vocab::Vocab;
+use rust_tokenizers::{Offset, TokenizedInput};
+use tokenizers::{Encoding, Tokenizer};
+
+use crate::tokens::bert_roberta_tokenizers::Embeddings;
+use crate::{Error, Result};
+
+/// A batch of texts encoded by any `TextEncoder`, padded to its longest text.
+///
+/// Padding has id 0, attention mask 0 and is flagged as a special token, as in the
+/// `Embeddings` of `bert_roberta_tokenizers`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodedBatch {
+    pub input_ids: Array2<i64>,
+    pub attention_mask: Array2<i64>,
+    pub type_ids: Array2<i64>,
+    /// 1 for the special tokens added by the tokenizer, and for padding
+    pub special_tokens_mask: Array2<i64>,
+    /// The byte span of every token in its text, `None` for special tokens. Padding has no
+    /// entry.
+    pub offsets: Vec<Vec<Option<(usize, usize)>>>,
+}
+
+impl EncodedBatch {
+    /// It pads the tokens of every text into one batch
+    fn from_texts(texts: Vec<EncodedText>) -> EncodedBatch {
+        let longest = texts.iter().map(|text| text.ids.len()).max().unwrap_or(0);
+        let shape = (texts.len(), longest);
+
+        let mut batch = EncodedBatch {
+            input_ids: Array2::zeros(shape),
+            attention_mask: Array2::zeros(shape),
+            type_ids: Array2::zeros(shape),
+            special_tokens_mask: Array2::ones(shape),
+            offsets: Vec::with_capacity(texts.len()),
+        };
+        for (row, text) in texts.into_iter().enumerate() {
+            let tokens = s![row, ..text.ids.len()];
+            let rows = [
+                (&mut batch.input_ids, &text.ids),
+                (&mut batch.attention_mask, &text.attention_mask),
+                (&mut batch.type_ids, &text.type_ids),
+                (&mut batch.special_tokens_mask, &text.special_tokens_mask),
+            ];
+            for (array, values) in rows {
+                array.slice_mut(tokens).assign(&ArrayView1::from(values));
+            }
+            batch.offsets.push(text.offsets);
+        }
+        batch
+    }
+
+    /// The number of texts in the batch
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    /// The ids, attention mask and type ids, as fed to the ONNX models
+    #[must_use]
+    pub fn embeddings(&self) -> Embeddings {
+        (
+            self.input_ids.clone(),
+            self.attention_mask.clone(),
+            self.type_ids.clone(),
+        )
+    }
+
+    #[must_use]
+    pub fn into_embeddings(self) -> Embeddings {
+        (self.input_ids, self.attention_mask, self.type_ids)
+    }
+
+    /// It lists the texts another encoder tokenized differently
+    ///
+    /// A text differs when its ids, type ids, special tokens or offsets differ, ignoring the
+    /// padding each batch needed.
+    ///
+    /// Arguments:
+    ///
+    /// * `other`: The same texts, encoded by another encoder.
+    ///
+    /// Returns:
+    ///
+    /// The index of every differing text, and of the texts only one batch holds
+    #[must_use]
+    pub fn differing_texts(&self, other: &EncodedBatch) -> Vec<usize> {
+        (0..self.len().max(other.len()))
+            .filter(|&text| {
+                text >= self.len().min(other.len())
+                    || self.offsets[text] != other.offsets[text]
+                    || self.tokens(text) != other.tokens(text)
+            })
+            .collect()
+    }
+
+    /// The id, type id and special flag of every token of a text, without its padding
+    fn tokens(&self, text: usize) -> Vec<(i64, i64, i64)> {
+        (0..self.offsets[text].len())
+            .map(|token| {
+                (
+                    self.input_ids[[text, token]],
+                    self.type_ids[[text, token]],
+                    self.special_tokens_mask[[text, token]],
+                )
+            })
+            .collect()
+    }
+}
+
+/// The unpadded tokens of one text.
+struct EncodedText {
+    ids: Vec<i64>,
+    attention_mask: Vec<i64>,
+    type_ids: Vec<i64>,
+    special_tokens_mask: Vec<i64>,
+    offsets: Vec<Option<(usize, usize)>>,
+}
+
+/// A tokenizer turning texts into the inputs of a transformer model.
+///
+/// Both the HF `tokenizers` and the `rust_tokenizers` libraries implement it, so a model can
+/// be driven by either one and their outputs compared with `EncodedBatch::differing_texts`.
+pub trait TextEncoder {
+    /// The name of the tokenizer, for error messages and reports
+    fn name(&self) -> &str;
+
+    /// It encodes a batch of texts, adding the special tokens of the model
+    ///
+    /// Arguments:
+    ///
+    /// * `text`: The texts to encode.
+    ///
+    /// Returns:
+    ///
+    /// An `EncodedBatch` with one row per text
+    fn encode_batch<S: AsRef<str>>(&self, text: &[S]) -> Result<EncodedBatch>;
+}
+
+/// A `TextEncoder` over a HF `tokenizers` tokenizer.
+pub struct HfTextEncoder {
+    name: String,
+    tokenizer: Arc<Tokenizer>,
+}
+
+impl HfTextEncoder {
+    /// It wraps a tokenizer, such as the one of `load_tokenizer`
+    ///
+    /// Arguments:
+    ///
+    /// * `name`: The name of the tokenizer, for error messages.
+    /// * `tokenizer`: The tokenizer, owned or shared. Its truncation settings are kept, its
+    ///   padding is replaced by the padding of `EncodedBatch`.
+    #[must_use]
+    pub fn new<T: Into<Arc<Tokenizer>>>(name: &str, tokenizer: T) -> HfTextEncoder {
+        HfTextEncoder {
+            name: name.to_string(),
+            tokenizer: tokenizer.into(),
+        }
+    }
+
+    #[must_use]
+    pub fn tokenizer(&self) -> &Tokenizer {
+        &self.tokenizer
+    }
+}
+
+impl TextEncoder for HfTextEncoder {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn encode_batch<S: AsRef<str>>(&self, text: &[S]) -> Result<EncodedBatch> {
+        let inputs: Vec<&str> = text.iter().map(AsRef::as_ref).collect();
+        let encodings = self
+            .tokenizer
+            .encode_batch(inputs, true)
+            .map_err(Error::tokenizer(&self.name))?;
+
+        Ok(EncodedBatch::from_texts(
+            encodings.iter().map(hf_encoded_text).collect(),
+        ))
+    }
+}
+
+/// The tokens of one encoding, without the padding the tokenizer may have added
+fn hf_encoded_text(encoding: &Encoding) -> EncodedText {
+    let tokens: Vec<usize> = (0..encoding.len())
+        .filter(|&token| encoding.get_attention_mask()[token] == 1)
+        .collect();
+    let widen = |values: &[u32]| -> Vec<i64> {
+        tokens
+            .iter()
+            .map(|&token| i64::from(values[token]))
+            .collect()
+    };
+    let special_tokens_mask = widen(encoding.get_special_tokens_mask());
+    EncodedText {
+        ids: widen(encoding.get_ids()),
+        attention_mask: vec![1; tokens.len()],
+        type_ids: widen(encoding.get_type_ids()),
+        offsets: tokens
+            .iter()
+            .zip(&special_tokens_mask)
+            .map(|(&token, &special)| (special == 0).then_some(encoding.get_offsets()[token]))
+            .collect(),
+        special_tokens_mask,
+    }
+}
+
+/// A `TextEncoder` over a `rust_tokenizers` tokenizer, such as the `RobertaTokenizer` of
+/// `roberta_rustbert::build_tokenizer`.
+///
+/// Texts are truncated to `max_length` tokens, special tokens included.
+pub struct RustTextEncoder<T, V> {
+    name: String,
+    tokenizer: T,
+    max_length: usize,
+    vocab: PhantomData<fn() -> V>,
+}
+
+impl<T, V> RustTextEncoder<T, V>
+where
+    T: RustTokenizer<V>,
+    V: Vocab,
+{
+    /// It wraps a tokenizer
+    ///
+    /// Arguments:
+    ///
+    /// * `name`: The name of the tokenizer, for error messages.
+    /// * `tokenizer`: The tokenizer.
+    /// * `max_length`: The most tokens kept per text, usually the window of the model.
+    #[must_use]
+    pub fn new(name: &str, tokenizer: T, max_length: usize) -> RustTextEncoder<T, V> {
+        RustTextEncoder {
+            name: name.to_string(),
+            tokenizer,
+            max_length,
+            vocab: PhantomData,
+        }
+    }
+
+    #[must_use]
+    pub fn tokenizer(&self) -> &T {
+        &self.tokenizer
+    }
+}
+
+impl<T, V> TextEncoder for RustTextEncoder<T, V>
+where
+    T: RustTokenizer<V>,
+    V: Vocab,
+{
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn encode_batch<S: AsRef<str>>(&self, text: &[S]) -> Result<EncodedBatch> {
+        let inputs =
+            self.tokenizer
+                .encode_list(text, self.max_length, &TruncationStrategy::LongestFirst, 0);
+
+        Ok(EncodedBatch::from_texts(
+            text.iter()
+                .zip(&inputs)
+                .map(|(text, input)| rust_encoded_text(text.as_ref(), input))
+                .collect(),
+        ))
+    }
+}
+
+fn rust_encoded_text(text: &str, input: &TokenizedInput) -> EncodedText {
+    let narrow = |values: &[i8]| values.iter().map(|&value| i64::from(value)).collect();
+    let bytes = char_to_byte_offsets(text);
+    EncodedText {
+        ids: input.token_ids.clone(),
+        attention_mask: vec![1; input.token_ids.len()],
+        type_ids: narrow(&input.segment_ids),
+        special_tokens_mask: narrow(&input.special_tokens_mask),
+        offsets: input
+            .token_offsets
+            .iter()
+            .zip(&input.special_tokens_mask)
+            .map(|(offset, &special)| {
+                offset
+                    .filter(|_| special == 0)
+                    .and_then(|offset| byte_span(&bytes, offset))
+            })
+            .collect(),
+    }
+}
+
+/// The byte offset of every char of a text, followed by the length of the text
+fn char_to_byte_offsets(text: &str) -> Vec<usize> {
+    text.char_indices()
+        .map(|(byte, _)| byte)
+        .chain([text.len()])
+        .collect()
+}
+
+/// It turns the char offsets of `rust_tokenizers` into the byte offsets of `tokenizers`
+fn byte_span(bytes: &[usize], offset: Offset) -> Option<(usize, usize)> {
+    let begin = *bytes.get(usize::try_from(offset.begin).ok()?)?;
+    let end = *bytes.get(usize::try_from(offset.end).ok()?)?;
+    Some((begin, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::array;
+
+    use crate::tokens::bert_roberta_tokenizers::load_tokenizer;
+    use crate::tokens::roberta_rustbert::build_tokenizer;
+
+    use super::*;
+
+    fn encoded_text(ids: &[i64], special: &[i64]) -> EncodedText {
+        EncodedText {
+            ids: ids.to_vec(),
+            attention_mask: vec![1; ids.len()],
+            type_ids: vec![0; ids.len()],
+            special_tokens_mask: special.to_vec(),
+            offsets: special
+                .iter()
+                .enumerate()
+                .map(|(token, &special)| (special == 0).then_some((token, token + 1)))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_batch_padding() {
+        let batch = EncodedBatch::from_texts(vec![
+            encoded_text(&[0, 10, 11, 2], &[1, 0, 0, 1]),
+            encoded_text(&[0, 12, 2], &[1, 0, 1]),
+        ]);
+
+        assert_eq!(batch.input_ids, array![[0, 10, 11, 2], [0, 12, 2, 0]]);
+        assert_eq!(batch.attention_mask, array![[1, 1, 1, 1], [1, 1, 1, 0]]);
+        assert_eq!(
+            batch.special_tokens_mask,
+            array![[1, 0, 0, 1], [1, 0, 1, 1]]
+        );
+        assert_eq!(batch.offsets[1], [None, Some((1, 2)), None]);
+
+        let (ids, mask, type_ids) = batch.embeddings();
+        assert_eq!(
+            (ids.dim(), mask.dim(), type_ids.dim()),
+            ((2, 4), (2, 4), (2, 4))
+        );
+        assert!(EncodedBatch::from_texts(vec![]).is_empty());
+    }
+
+    #[test]
+    fn test_differing_texts() {
+        let batch = EncodedBatch::from_texts(vec![
+            encoded_text(&[0, 10, 2], &[1, 0, 1]),
+            encoded_text(&[0, 12, 2], &[1, 0, 1]),
+        ]);
+        // The padding of a longer batch is ignored
+        let padded = EncodedBatch::from_texts(vec![
+            encoded_text(&[0, 10, 2], &[1, 0, 1]),
+            encoded_text(&[0, 13, 2], &[1, 0, 1]),
+            encoded_text(&[0, 14, 15, 16, 2], &[1, 0, 0, 0, 1]),
+        ]);
+
+        assert!(batch.differing_texts(&batch).is_empty());
+        assert_eq!(batch.differing_texts(&padded), [1, 2]);
+        assert_eq!(padded.differing_texts(&batch), [1, 2]);
+    }
+
+    #[test]
+    fn test_char_offsets_to_bytes() {
+        let bytes = char_to_byte_offsets("é a");
+        assert_eq!(bytes, [0, 2, 3, 4]);
+        assert_eq!(byte_span(&bytes, Offset { begin: 0, end: 1 }), Some((0, 2)));
+        assert_eq!(byte_span(&bytes, Offset { begin: 2, end: 3 }), Some((3, 4)));
+        assert_eq!(byte_span(&bytes, Offset { begin: 2, end: 9 }), None);
+    }
+
+    #[test]
+    fn test_roberta_encoders() {
+        let text = [
+            "My name is Amélie. I live in Москва.",
+            "Chongqing is a city.",
+        ];
+        let hf = HfTextEncoder::new("roberta-base", load_tokenizer("roberta-base").unwrap());
+        let rust = RustTextEncoder::new("roberta-base", build_tokenizer().unwrap(), 512);
+
+        let (hf_batch, rust_batch) = (
+            hf.encode_batch(&text).unwrap(),
+            rust.encode_batch(&text).unwrap(),
+        );
+        for batch in [&hf_batch, &rust_batch] {
+            assert_eq!(batch.len(), text.len());
+            for (row, offsets) in batch.offsets.iter().enumerate() {
+                assert_eq!(batch.special_tokens_mask[[row, 0]], 1);
+                // Every token spans a slice of its own text
+                for &(begin, end) in offsets.iter().flatten() {
+                    assert!(text[row].get(begin..end).is_some());
+                }
+            }
+        }
+
+        // The only known difference: `build_tokenizer` adds a prefix space, the roberta-base
+        // tokenizer.json does not, so the first word of every text gets another id
+        assert_eq!(hf_batch.differing_texts(&rust_batch), [0, 1]);
+        let spaced: Vec<String> = text.iter().map(|text| format!(" {text}")).collect();
+        let spaced = hf.encode_batch(&spaced).unwrap();
+        for row in 0..text.len() {
+            assert_eq!(spaced.tokens(row), rust_batch.tokens(row));
+        }
+    }
+}
@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+use std::env::var;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError};
+
+use tokenizers::Tokenizer;
+
+use crate::{Error, Result};
+
+/// The environment variable naming a directory of `<name>/tokenizer.json` files to load first
+pub const TOKENIZERS_DIR_VAR: &str = "SANDBOX_TOKENIZERS_DIR";
+/// The Hugging Face environment variable that turns off every hub download when set to `1`
+pub const OFFLINE_VAR: &str = "HF_HUB_OFFLINE";
+/// The Hugging Face environment variable naming the hub cache directory
+pub const HUB_CACHE_VAR: &str = "HF_HUB_CACHE";
+/// The Hugging Face environment variable naming its home, whose `hub` directory is the hub cache
+pub const HF_HOME_VAR: &str = "HF_HOME";
+
+const TOKENIZER_FILE: &str = "tokenizer.json";
+
+/// Resolves tokenizer names to local `tokenizer.json` files and keeps the loaded tokenizers
+/// in memory, so a name is looked up at most once per process.
+///
+/// A name is resolved from the configured directory first, then from the snapshots of the
+/// HF Hub cache other Hugging Face tools download into, then from the cache of this crate,
+/// and only then from the HF Hub, saving the downloaded file into the cache. In offline mode
+/// the hub is never contacted and a missing tokenizer is an error.
+///
+/// ```no_run
+/// use sandbox_rust::tokens::tokenizer_store::TokenizerStore;
+/// let store = TokenizerStore::new().with_dir("resources/tokenizers").offline(true);
+/// let tokenizer = store.get("bert-base-uncased").unwrap();
+/// ```
+pub struct TokenizerStore {
+    dir: Option<PathBuf>,
+    hub_cache_dir: PathBuf,
+    cache_dir: PathBuf,
+    offline: bool,
+    loaded: Mutex<HashMap<String, Arc<Tokenizer>>>,
+    configured: Mutex<HashMap<(&'static str, String), Arc<Tokenizer>>>,
+}
+
+impl Default for TokenizerStore {
+    fn default() -> Self {
+        TokenizerStore::new()
+    }
+}
+
+impl TokenizerStore {
+    /// It builds a store configured from `SANDBOX_TOKENIZERS_DIR` and `HF_HUB_OFFLINE`,
+    /// reading the HF Hub cache from `HF_HUB_CACHE` or `HF_HOME` like the Hugging Face
+    /// libraries do, and caching downloads under `~/.cache/sandbox-rust/tokenizers`
+    #[must_use]
+    pub fn new() -> TokenizerStore {
+        let cache = dirs::home_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join(".cache");
+        let hub_cache_dir = var(HUB_CACHE_VAR).map(PathBuf::from).unwrap_or_else(|_| {
+            var(HF_HOME_VAR)
+                .map_or_else(|_| cache.join("huggingface"), PathBuf::from)
+                .join("hub")
+        });
+
+        TokenizerStore {
+            dir: var(TOKENIZERS_DIR_VAR).ok().map(PathBuf::from),
+            hub_cache_dir,
+            cache_dir: cache.join("sandbox-rust").join("tokenizers"),
+            offline: var(OFFLINE_VAR).is_ok_and(|offline| offline == "1"),
+            loaded: Mutex::new(HashMap::new()),
+            configured: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The store shared by the loading functions of this crate
+    pub fn global() -> &'static TokenizerStore {
+        static STORE: OnceLock<TokenizerStore> = OnceLock::new();
+        STORE.get_or_init(TokenizerStore::new)
+    }
+
+    /// It sets the directory searched before the cache
+    #[must_use]
+    pub fn with_dir<P: Into<PathBuf>>(mut self, dir: P) -> TokenizerStore {
+        self.dir = Some(dir.into());
+        self
+    }
+
+    /// It sets the HF Hub cache, the directory holding the `models--<org>--<name>` repositories
+    #[must_use]
+    pub fn with_hub_cache_dir<P: Into<PathBuf>>(mut self, dir: P) -> TokenizerStore {
+        self.hub_cache_dir = dir.into();
+        self
+    }
+
+    /// It sets the directory downloaded tokenizers are saved into
+    #[must_use]
+    pub fn with_cache_dir<P: Into<PathBuf>>(mut self, dir: P) -> TokenizerStore {
+        self.cache_dir = dir.into();
+        self
+    }
+
+    /// It turns hub downloads off, so missing tokenizers fail instead of touching the network
+    #[must_use]
+    pub fn offline(mut self, offline: bool) -> TokenizerStore {
+        self.offline = offline;
+        self
+    }
+
+    /// It returns the tokenizer called `name`, loading it on first use
+    ///
+    /// Arguments:
+    ///
+    /// * `name`: The name of the tokenizer on the HF Hub, such as `bert-base-uncased`.
+    ///
+    /// Returns:
+    ///
+    /// The shared `Tokenizer`, as stored in its `tokenizer.json`
+    pub fn get(&self, name: &str) -> Result<Arc<Tokenizer>> {
+        if let Some(tokenizer) = lock(&self.loaded).get(name) {
+            return Ok(Arc::clone(tokenizer));
+        }
+
+        let path = self.resolve(name)?;
+        let tokenizer = Arc::new(Tokenizer::from_file(&path).map_err(Error::tokenizer(name))?);
+
+        // Another thread may have loaded it meanwhile, keep whichever came first
+        Ok(Arc::clone(
+            lock(&self.loaded)
+                .entry(name.to_string())
+                .or_insert(tokenizer),
+        ))
+    }
+
+    /// It returns the tokenizer called `name` as changed by `configure`. The tokenizer is
+    /// copied and changed once per name and `key`, later calls share that copy.
+    ///
+    /// Arguments:
+    ///
+    /// * `name`: The name of the tokenizer on the HF Hub.
+    /// * `key`: The name of the change, every caller passing it must pass the same `configure`.
+    /// * `configure`: The change, such as setting the padding.
+    ///
+    /// Returns:
+    ///
+    /// The shared, changed `Tokenizer`
+    pub fn get_configured(
+        &self,
+        name: &str,
+        key: &'static str,
+        configure: fn(&mut Tokenizer),
+    ) -> Result<Arc<Tokenizer>> {
+        let entry = (key, name.to_string());
+        if let Some(tokenizer) = lock(&self.configured).get(&entry) {
+            return Ok(Arc::clone(tokenizer));
+        }
+
+        let mut tokenizer = Tokenizer::clone(&*self.get(name)?);
+        configure(&mut tokenizer);
+
+        Ok(Arc::clone(
+            lock(&self.configured)
+                .entry(entry)
+                .or_insert_with(|| Arc::new(tokenizer)),
+        ))
+    }
+
+    /// It finds the `tokenizer.json` of `name`, downloading it into the cache when allowed
+    ///
+    /// Arguments:
+    ///
+    /// * `name`: The name of the tokenizer on the HF Hub.
+    ///
+    /// Returns:
+    ///
+    /// The path of a local `tokenizer.json`
+    pub fn resolve(&self, name: &str) -> Result<PathBuf> {
+        let cached = tokenizer_file(&self.cache_dir, name);
+        let searched: Vec<PathBuf> = self
+            .dir
+            .iter()
+            .map(|dir| tokenizer_file(dir, name))
+            .chain(hub_snapshot_file(&self.hub_cache_dir, name))
+            .chain([cached.clone()])
+            .collect();
+
+        if let Some(path) = searched.iter().find(|path| path.is_file()) {
+            return Ok(path.clone());
+        }
+
+        if self.offline {
+            return Err(Error::TokenizerNotFound {
+                name: name.to_string(),
+                searched,
+            });
+        }
+
+        let tokenizer = Tokenizer::from_pretrained(name, None).map_err(Error::tokenizer(name))?;
+        if let Some(parent) = cached.parent() {
+            std::fs::create_dir_all(parent).map_err(Error::io(parent))?;
+        }
+        tokenizer
+            .save(&cached, false)
+            .map_err(Error::tokenizer(name))?;
+
+        Ok(cached)
+    }
+}
+
+fn lock<T>(map: &Mutex<T>) -> MutexGuard<'_, T> {
+    // The maps are only ever inserted into, so they are still valid after a panic elsewhere
+    map.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The place of `name`'s `tokenizer.json` under `dir`, keeping hub namespaces as sub directories
+fn tokenizer_file(dir: &Path, name: &str) -> PathBuf {
+    dir.join(name).join(TOKENIZER_FILE)
+}
+
+/// The `tokenizer.json` of the `main` snapshot of `name` in a HF Hub cache, if it has one
+fn hub_snapshot_file(hub_cache_dir: &Path, name: &str) -> Option<PathBuf> {
+    let repository = hub_cache_dir.join(format!("models--{}", name.replace('/', "--")));
+    let revision = std::fs::read_to_string(repository.join("refs").join("main")).ok()?;
+
+    Some(
+        repository
+            .join("snapshots")
+            .join(revision.trim())
+            .join(TOKENIZER_FILE),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offline_store_does_not_download() {
+        let dir = std::env::temp_dir().join("sandbox-rust-tokenizer-store");
+        let store = TokenizerStore::new()
+            .with_dir(dir.join("configured"))
+            .with_hub_cache_dir(dir.join("hub"))
+            .with_cache_dir(dir.join("cache"))
+            .offline(true);
+
+        let error = store.get("bert-base-uncased").err().unwrap();
+        assert!(matches!(
+            &error,
+            Error::TokenizerNotFound { searched, .. } if searched == &[
+                dir.join("configured/bert-base-uncased/tokenizer.json"),
+                dir.join("cache/bert-base-uncased/tokenizer.json"),
+            ]
+        ));
+        assert!(error.to_string().contains("offline"));
+    }
+
+    #[test]
+    fn test_configured_dir_comes_first() {
+        let dir = std::env::temp_dir().join("sandbox-rust-tokenizer-store-dir");
+        let configured = tokenizer_file(&dir.join("configured"), "org/tokenizer");
+        std::fs::create_dir_all(configured.parent().unwrap()).unwrap();
+        std::fs::write(&configured, "{}").unwrap();
+
+        let store = TokenizerStore::new()
+            .with_dir(dir.join("configured"))
+            .with_cache_dir(dir.join("cache"))
+            .offline(true);
+        assert_eq!(store.resolve("org/tokenizer").unwrap(), configured);
+    }
+
+    #[test]
+    fn test_hub_cache_comes_before_download() {
+        let dir = std::env::temp_dir().join("sandbox-rust-tokenizer-store-hub");
+        let repository = dir.join("hub/models--org--tokenizer");
+        let snapshot = repository.join("snapshots/abc123/tokenizer.json");
+        std::fs::create_dir_all(snapshot.parent().unwrap()).unwrap();
+        std::fs::create_dir_all(repository.join("refs")).unwrap();
+        std::fs::write(repository.join("refs/main"), "abc123\n").unwrap();
+        std::fs::write(&snapshot, "{}").unwrap();
+
+        let store = TokenizerStore::new()
+            .with_hub_cache_dir(dir.join("hub"))
+            .with_cache_dir(dir.join("cache"))
+            .offline(true);
+        assert_eq!(store.resolve("org/tokenizer").unwrap(), snapshot);
+    }
+
+    #[test]
+    fn test_store_loads_once() {
+        let store = TokenizerStore::new();
+        let first = store.get("bert-base-uncased").unwrap();
+        let second = store.get("bert-base-uncased").unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+    }
+
+    #[test]
+    fn test_configured_tokenizer_is_shared() {
+        fn without_padding(tokenizer: &mut Tokenizer) {
+            tokenizer.with_padding(None);
+        }
+
+        let store = TokenizerStore::new();
+        let first = store
+            .get_configured("bert-base-uncased", "no-padding", without_padding)
+            .unwrap();
+        let second = store
+            .get_configured("bert-base-uncased", "no-padding", without_padding)
+            .unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert!(!Arc::ptr_eq(
+            &first,
+            &store.get("bert-base-uncased").unwrap()
+        ));
+    }
+}
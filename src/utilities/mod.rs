@@ -1,3 +1,4 @@
+pub mod postprocess;
 pub mod retrieval;
 pub mod time;
 pub mod tokens;
@@ -0,0 +1,284 @@
+use std::cmp::Ordering;
+
+use ndarray::{Array, ArrayBase, ArrayView1, Axis, Data, Dimension};
+
+use crate::{Error, Result};
+
+/// It turns logits into probabilities along an axis
+///
+/// The largest logit of every lane is subtracted before exponentiating, so large logits
+/// do not overflow. A lane holding a NaN or `+inf`, or no finite logit at all, has no
+/// meaningful distribution and becomes NaN.
+///
+/// Arguments:
+///
+/// * `logits`: The logits, of any rank and memory layout.
+/// * `axis`: The axis of the classes, usually the last one.
+///
+/// Returns:
+///
+/// The probabilities, shaped like `logits`, summing to 1 along `axis`
+/// ```
+/// use ndarray::{array, Axis};
+/// use sandbox_rust::utilities::postprocess::softmax;
+/// let probabilities = softmax(&array![[0.0, 0.0], [1000.0, 0.0]], Axis(1));
+/// assert_eq!(probabilities, array![[0.5, 0.5], [1.0, 0.0]]);
+/// ```
+pub fn softmax<S, D>(logits: &ArrayBase<S, D>, axis: Axis) -> Array<f32, D>
+where
+    S: Data<Elem = f32>,
+    D: Dimension,
+{
+    let mut probabilities = logits.to_owned();
+    for mut lane in probabilities.lanes_mut(axis) {
+        let Some(highest) = highest(lane.view()) else {
+            lane.fill(f32::NAN);
+            continue;
+        };
+        lane.mapv_inplace(|logit| (logit - highest).exp());
+        let total = lane.sum();
+        lane /= total;
+    }
+    probabilities
+}
+
+/// It turns logits into log probabilities along an axis
+///
+/// Unlike the log of `softmax`, it keeps precision for very unlikely classes. Lanes without
+/// a distribution become NaN, as in `softmax`.
+///
+/// Arguments:
+///
+/// * `logits`: The logits, of any rank and memory layout.
+/// * `axis`: The axis of the classes.
+///
+/// Returns:
+///
+/// The log probabilities, shaped like `logits`
+pub fn log_softmax<S, D>(logits: &ArrayBase<S, D>, axis: Axis) -> Array<f32, D>
+where
+    S: Data<Elem = f32>,
+    D: Dimension,
+{
+    let mut log_probabilities = logits.to_owned();
+    for mut lane in log_probabilities.lanes_mut(axis) {
+        let Some(highest) = highest(lane.view()) else {
+            lane.fill(f32::NAN);
+            continue;
+        };
+        let total: f32 = lane.iter().map(|logit| (logit - highest).exp()).sum();
+        let log_partition = highest + total.ln();
+        lane.mapv_inplace(|logit| logit - log_partition);
+    }
+    log_probabilities
+}
+
+/// It applies the logistic function to every logit, for heads whose labels are independent
+///
+/// Arguments:
+///
+/// * `logits`: The logits, of any rank and memory layout.
+///
+/// Returns:
+///
+/// Probabilities in `[0, 1]`, shaped like `logits`, NaN where the logit is NaN
+pub fn sigmoid<S, D>(logits: &ArrayBase<S, D>) -> Array<f32, D>
+where
+    S: Data<Elem = f32>,
+    D: Dimension,
+{
+    logits.mapv(|logit| {
+        // Only ever exponentiating a non positive number keeps both tails finite
+        if logit >= 0.0 {
+            1.0 / (1.0 + (-logit).exp())
+        } else {
+            let exp = logit.exp();
+            exp / (1.0 + exp)
+        }
+    })
+}
+
+/// It divides logits by a temperature, before `softmax` or `sigmoid`
+///
+/// A temperature above 1 flattens the distribution, below 1 sharpens it.
+///
+/// Arguments:
+///
+/// * `logits`: The logits, of any rank and memory layout.
+/// * `temperature`: A positive temperature.
+///
+/// Returns:
+///
+/// The scaled logits, shaped like `logits`, or a `Temperature` error when the temperature
+/// is zero, negative or NaN
+pub fn with_temperature<S, D>(logits: &ArrayBase<S, D>, temperature: f32) -> Result<Array<f32, D>>
+where
+    S: Data<Elem = f32>,
+    D: Dimension,
+{
+    if temperature.is_nan() || temperature <= 0.0 {
+        return Err(Error::Temperature { temperature });
+    }
+    Ok(logits.mapv(|logit| logit / temperature))
+}
+
+/// It orders scores from the lowest to the highest, with NaN below everything else
+#[must_use]
+pub fn compare_scores(first: f32, second: f32) -> Ordering {
+    match (first.is_nan(), second.is_nan()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Less,
+        (false, true) => Ordering::Greater,
+        (false, false) => first.total_cmp(&second),
+    }
+}
+
+/// It finds the highest score of a lane
+///
+/// Arguments:
+///
+/// * `scores`: The scores of every class, such as one lane of `softmax`.
+///
+/// Returns:
+///
+/// The index and score of the highest score, the first one on ties, or `None` when the
+/// lane is empty or only holds NaN
+#[must_use]
+pub fn argmax(scores: ArrayView1<f32>) -> Option<(usize, f32)> {
+    scores
+        .iter()
+        .copied()
+        .enumerate()
+        .filter(|(_, score)| !score.is_nan())
+        .reduce(|best, candidate| {
+            if compare_scores(candidate.1, best.1) == Ordering::Greater {
+                candidate
+            } else {
+                best
+            }
+        })
+}
+
+/// It finds the `k` highest scores of a lane
+///
+/// Arguments:
+///
+/// * `scores`: The scores of every class.
+/// * `k`: The number of classes kept.
+///
+/// Returns:
+///
+/// At most `k` indices with their scores, the highest first and the lowest index first on
+/// ties. NaN scores are never returned.
+#[must_use]
+pub fn top_k(scores: ArrayView1<f32>, k: usize) -> Vec<(usize, f32)> {
+    let mut top: Vec<(usize, f32)> = scores
+        .iter()
+        .copied()
+        .enumerate()
+        .filter(|(_, score)| !score.is_nan())
+        .collect();
+    // The sort is stable, so equal scores stay in index order
+    top.sort_by(|first, second| compare_scores(second.1, first.1));
+    top.truncate(k);
+    top
+}
+
+/// The largest logit of a lane, `None` when the lane has no distribution
+fn highest(lane: ArrayView1<f32>) -> Option<f32> {
+    let highest = lane.fold(f32::NEG_INFINITY, |highest, &logit| highest.max(logit));
+    if highest.is_finite() && !lane.iter().any(|logit| logit.is_nan()) {
+        Some(highest)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{array, Array3};
+
+    use super::*;
+
+    fn close(first: f32, second: f32) -> bool {
+        (first - second).abs() < 1e-6
+    }
+
+    #[test]
+    fn test_softmax() {
+        let logits = array![[1.0, 2.0, 3.0], [1000.0, 1000.0, f32::NEG_INFINITY]];
+        let probabilities = softmax(&logits, Axis(1));
+
+        for row in probabilities.outer_iter() {
+            assert!(close(row.sum(), 1.0));
+        }
+        assert!(close(probabilities[[0, 2]], 0.665_240_9));
+        assert_eq!(probabilities.row(1), array![0.5, 0.5, 0.0]);
+
+        // Along the first axis of a transposed view, the columns are the distributions
+        let transposed = softmax(&logits.t(), Axis(0));
+        assert_eq!(transposed.t(), probabilities);
+
+        // Every lane of a higher rank tensor is normalized on its own
+        let tokens = Array3::from_shape_fn((2, 3, 4), |(i, j, k)| (i * 12 + j * 4 + k) as f32);
+        let tokens = softmax(&tokens, Axis(2));
+        assert!(tokens
+            .lanes(Axis(2))
+            .into_iter()
+            .all(|lane| close(lane.sum(), 1.0)));
+    }
+
+    #[test]
+    fn test_nan_and_infinite_lanes() {
+        let logits = array![
+            [f32::NAN, 1.0],
+            [f32::INFINITY, 1.0],
+            [f32::NEG_INFINITY, f32::NEG_INFINITY]
+        ];
+        assert!(softmax(&logits, Axis(1)).iter().all(|p| p.is_nan()));
+        assert!(log_softmax(&logits, Axis(1)).iter().all(|p| p.is_nan()));
+    }
+
+    #[test]
+    fn test_log_softmax() {
+        let logits = array![[0.0, -200.0], [3.0, 1.0]];
+        let log_probabilities = log_softmax(&logits, Axis(1));
+
+        // The log of softmax would give -inf for the unlikely class
+        assert!(close(log_probabilities[[0, 1]], -200.0));
+        let probabilities = softmax(&logits, Axis(1));
+        assert!(close(log_probabilities[[1, 0]], probabilities[[1, 0]].ln()));
+    }
+
+    #[test]
+    fn test_sigmoid_and_temperature() {
+        let probabilities = sigmoid(&array![0.0, 2.0, -1000.0, 1000.0, f32::NAN]);
+        assert_eq!(probabilities[0], 0.5);
+        assert!(close(probabilities[1], 0.880_797));
+        assert_eq!((probabilities[2], probabilities[3]), (0.0, 1.0));
+        assert!(probabilities[4].is_nan());
+
+        let logits = array![1.0, 2.0];
+        let flat = softmax(&with_temperature(&logits, 10.0).unwrap(), Axis(0));
+        let sharp = softmax(&with_temperature(&logits, 0.1).unwrap(), Axis(0));
+        assert!(flat[1] < softmax(&logits, Axis(0))[1] && sharp[1] > 0.999);
+        for temperature in [0.0, -1.0, f32::NAN] {
+            assert!(matches!(
+                with_temperature(&logits, temperature),
+                Err(Error::Temperature { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_argmax_and_top_k() {
+        let scores = array![0.1, f32::NAN, 0.7, 0.2, 0.7];
+        assert_eq!(argmax(scores.view()), Some((2, 0.7)));
+        assert_eq!(top_k(scores.view(), 3), [(2, 0.7), (4, 0.7), (3, 0.2)]);
+        assert_eq!(top_k(scores.view(), 10).len(), 4);
+
+        assert_eq!(argmax(array![f32::NAN].view()), None);
+        assert_eq!(argmax(ArrayView1::from(&[])), None);
+        assert_eq!(compare_scores(f32::NAN, f32::NEG_INFINITY), Ordering::Less);
+    }
+}
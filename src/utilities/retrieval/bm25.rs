@@ -0,0 +1,412 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokenizers::tokenizer::Tokenizer;
+
+use crate::tokens::tokenizer_store::TokenizerStore;
+use crate::utilities::retrieval::storage::replace_file;
+use crate::utilities::retrieval::top_k::TopK;
+use crate::utilities::retrieval::SearchResult;
+use crate::{Error, Result};
+
+/// The version of the JSON files written by `Bm25Index::save`, apart from the binary
+/// vector index format
+pub const BM25_FORMAT_VERSION: u32 = 1;
+
+/// How documents and queries are split into the terms of a `Bm25Index`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Analyzer {
+    /// Lowercased runs of alphanumeric characters
+    Words,
+    /// The tokens of a tokenizer from the `TokenizerStore`, without special tokens
+    Tokenizer(String),
+}
+
+/// The parameters of BM25 scoring.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Bm25Params {
+    /// How quickly repeated terms stop adding to the score
+    pub k1: f32,
+    /// How much longer documents are penalized, from 0 for not at all to 1
+    pub b: f32,
+    /// The bonus of every matched term, 0 for BM25 and usually 1 for BM25+
+    pub delta: f32,
+}
+
+impl Default for Bm25Params {
+    fn default() -> Self {
+        Bm25Params {
+            k1: 1.2,
+            b: 0.75,
+            delta: 0.0,
+        }
+    }
+}
+
+impl Bm25Params {
+    /// BM25+, which keeps long documents that match a term above those that do not
+    #[must_use]
+    pub fn plus() -> Bm25Params {
+        Bm25Params {
+            delta: 1.0,
+            ..Bm25Params::default()
+        }
+    }
+}
+
+/// A lexical index that ranks documents by BM25 over an inverted index of their terms.
+///
+/// ```
+/// use sandbox_rust::utilities::retrieval::{Analyzer, Bm25Index, Bm25Params};
+/// let mut index = Bm25Index::new(Analyzer::Words, Bm25Params::default()).unwrap();
+/// index.add("rust", "Rust is a systems language", None).unwrap();
+/// index.add("python", "Python is a scripting language", None).unwrap();
+/// assert_eq!(index.search("systems", 1).unwrap()[0].id, "rust");
+/// ```
+#[derive(Serialize, Deserialize)]
+pub struct Bm25Index {
+    analyzer: Analyzer,
+    params: Bm25Params,
+    ids: Vec<String>,
+    payloads: Vec<Option<Value>>,
+    /// The number of terms of every document
+    lengths: Vec<u32>,
+    total_length: u64,
+    /// The documents containing every term, with how often the term appears in them
+    postings: HashMap<String, Vec<(usize, u32)>>,
+    #[serde(skip)]
+    tokenizer: Option<Arc<Tokenizer>>,
+}
+
+/// The file written by `Bm25Index::save`.
+#[derive(Serialize, Deserialize)]
+struct Saved<T> {
+    version: u32,
+    index: T,
+}
+
+impl Bm25Index {
+    /// It builds an empty index
+    ///
+    /// Arguments:
+    ///
+    /// * `analyzer`: How documents and queries are split into terms.
+    /// * `params`: The BM25 parameters.
+    ///
+    /// Returns:
+    ///
+    /// A `Bm25Index`, or an error when the tokenizer of the analyzer cannot be loaded
+    pub fn new(analyzer: Analyzer, params: Bm25Params) -> Result<Bm25Index> {
+        let mut index = Bm25Index {
+            analyzer,
+            params,
+            ids: Vec::new(),
+            payloads: Vec::new(),
+            lengths: Vec::new(),
+            total_length: 0,
+            postings: HashMap::new(),
+            tokenizer: None,
+        };
+        index.load_tokenizer()?;
+        Ok(index)
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    #[must_use]
+    pub fn analyzer(&self) -> &Analyzer {
+        &self.analyzer
+    }
+
+    #[must_use]
+    pub fn params(&self) -> Bm25Params {
+        self.params
+    }
+
+    /// It changes the parameters used by later searches, the index itself does not depend on them
+    pub fn set_params(&mut self, params: Bm25Params) {
+        self.params = params;
+    }
+
+    /// It splits a text into terms with the index analyzer
+    pub fn analyze(&self, text: &str) -> Result<Vec<String>> {
+        match (&self.analyzer, &self.tokenizer) {
+            (Analyzer::Tokenizer(name), Some(tokenizer)) => Ok(tokenizer
+                .encode(text, false)
+                .map_err(Error::tokenizer(name))?
+                .get_tokens()
+                .to_vec()),
+            _ => Ok(text
+                .split(|c: char| !c.is_alphanumeric())
+                .filter(|word| !word.is_empty())
+                .map(str::to_lowercase)
+                .collect()),
+        }
+    }
+
+    /// It adds a document to the index
+    ///
+    /// Arguments:
+    ///
+    /// * `id`: The id returned in search results.
+    /// * `text`: The text of the document.
+    /// * `payload`: Optional data returned along with the id.
+    pub fn add<S: Into<String>>(
+        &mut self,
+        id: S,
+        text: &str,
+        payload: Option<Value>,
+    ) -> Result<()> {
+        let terms = self.analyze(text)?;
+        let document = self.ids.len();
+
+        let mut frequencies: HashMap<String, u32> = HashMap::new();
+        for term in &terms {
+            *frequencies.entry(term.clone()).or_default() += 1;
+        }
+        for (term, frequency) in frequencies {
+            self.postings
+                .entry(term)
+                .or_default()
+                .push((document, frequency));
+        }
+
+        self.ids.push(id.into());
+        self.payloads.push(payload);
+        self.lengths.push(terms.len() as u32);
+        self.total_length += terms.len() as u64;
+        Ok(())
+    }
+
+    /// It finds the `k` documents that best match `query`
+    ///
+    /// Arguments:
+    ///
+    /// * `query`: The query text.
+    /// * `k`: The number of results.
+    ///
+    /// Returns:
+    ///
+    /// At most `k` results, the best first, among the documents sharing a term with the query
+    pub fn search(&self, query: &str, k: usize) -> Result<Vec<SearchResult>> {
+        let Bm25Params { k1, b, delta } = self.params;
+        let documents = self.len() as f32;
+        let average_length = (self.total_length as f32 / documents).max(1.0);
+
+        let mut scores: HashMap<usize, f32> = HashMap::new();
+        for term in self.analyze(query)? {
+            let Some(postings) = self.postings.get(&term) else {
+                continue;
+            };
+
+            // The idf of Lucene, which stays positive for terms in most documents
+            let matched = postings.len() as f32;
+            let idf = (1.0 + (documents - matched + 0.5) / (matched + 0.5)).ln();
+
+            for &(document, frequency) in postings {
+                let frequency = frequency as f32;
+                let length = self.lengths[document] as f32 / average_length;
+                let saturation = frequency * (k1 + 1.0) / (frequency + k1 * (1.0 - b + b * length));
+                *scores.entry(document).or_default() += idf * (saturation + delta);
+            }
+        }
+
+        let mut top = TopK::new(k);
+        for (document, score) in scores {
+            top.push(document, score);
+        }
+        Ok(top
+            .into_sorted_vec()
+            .into_iter()
+            .map(|scored| SearchResult {
+                id: self.ids[scored.index].clone(),
+                score: scored.score,
+                payload: self.payloads[scored.index].clone(),
+            })
+            .collect())
+    }
+
+    /// It writes the index to a JSON file, under a temporary name renamed over `path` once
+    /// complete
+    ///
+    /// Arguments:
+    ///
+    /// * `path`: The file to write.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let saved = Saved {
+            version: BM25_FORMAT_VERSION,
+            index: self,
+        };
+        let json = serde_json::to_vec(&saved).expect("the index serializes to JSON");
+        replace_file(path, |writer| writer.write_all(&json))
+    }
+
+    /// It opens an index written by `save`, loading the tokenizer of its analyzer
+    ///
+    /// Arguments:
+    ///
+    /// * `path`: The file written by `save`.
+    ///
+    /// Returns:
+    ///
+    /// A `Bm25Index` that can keep growing
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Bm25Index> {
+        let path = path.as_ref();
+        let json = fs::read(path).map_err(Error::io(path))?;
+        let saved: Saved<Bm25Index> = serde_json::from_slice(&json)
+            .map_err(|source| Error::invalid_index(path, &source.to_string()))?;
+
+        if saved.version != BM25_FORMAT_VERSION {
+            return Err(Error::invalid_index(
+                path,
+                &format!(
+                    "format version {} is not supported, expected {BM25_FORMAT_VERSION}",
+                    saved.version
+                ),
+            ));
+        }
+
+        let mut index = saved.index;
+        let documents = index.ids.len();
+        let consistent = index.payloads.len() == documents
+            && index.lengths.len() == documents
+            && index
+                .postings
+                .values()
+                .flatten()
+                .all(|&(document, _)| document < documents);
+        if !consistent {
+            return Err(Error::invalid_index(
+                path,
+                "the postings do not match the documents",
+            ));
+        }
+
+        index.load_tokenizer()?;
+        Ok(index)
+    }
+
+    fn load_tokenizer(&mut self) -> Result<()> {
+        if let Analyzer::Tokenizer(name) = &self.analyzer {
+            self.tokenizer = Some(TokenizerStore::global().get(name)?);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn corpus(params: Bm25Params) -> Bm25Index {
+        let mut index = Bm25Index::new(Analyzer::Words, params).unwrap();
+        index
+            .add("paris", "Paris is the capital of France.", None)
+            .unwrap();
+        index
+            .add(
+                "berlin",
+                "Berlin is the capital of Germany",
+                Some(json!({"country": "DE"})),
+            )
+            .unwrap();
+        index
+            .add(
+                "capitals",
+                "A capital is a city, and the capital of a country is usually its largest city, \
+                 but Bern is the capital of Switzerland while Zurich is larger.",
+                None,
+            )
+            .unwrap();
+        index
+    }
+
+    #[test]
+    fn test_words_analyzer() {
+        let index = corpus(Bm25Params::default());
+        assert_eq!(
+            index.analyze("Zürich's  CAPITAL-city!").unwrap(),
+            ["zürich", "s", "capital", "city"]
+        );
+    }
+
+    #[test]
+    fn test_search() {
+        let index = corpus(Bm25Params::default());
+
+        let results = index.search("capital of Germany", 3).unwrap();
+        assert_eq!(results[0].id, "berlin");
+        assert_eq!(results[0].payload, Some(json!({"country": "DE"})));
+        assert_eq!(results.len(), 3);
+        assert!(results[0].score > results[1].score);
+
+        // Only documents sharing a term are returned
+        assert_eq!(index.search("Bern", 3).unwrap().len(), 1);
+        assert!(index.search("Madrid", 3).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_bm25_plus_favours_matches_in_long_documents() {
+        let score = |params, id: &str| {
+            corpus(params)
+                .search("capital", 3)
+                .unwrap()
+                .into_iter()
+                .find(|result| result.id == id)
+                .unwrap()
+                .score
+        };
+
+        let bm25 = score(Bm25Params::default(), "capitals");
+        let plus = score(Bm25Params::plus(), "capitals");
+        assert!(plus > bm25);
+    }
+
+    #[test]
+    fn test_incremental_adds_and_persistence() {
+        let mut index = corpus(Bm25Params::default());
+        let rare = index.search("France", 1).unwrap()[0].score;
+
+        // A term becomes less informative once more documents contain it
+        index.add("lyon", "Lyon is a city in France", None).unwrap();
+        assert!(index.search("France", 1).unwrap()[0].score < rare);
+
+        let path = std::env::temp_dir().join("sandbox-rust-bm25.json");
+        index.save(&path).unwrap();
+        let mut opened = Bm25Index::open(&path).unwrap();
+        assert_eq!(opened.len(), 4);
+        assert_eq!(
+            opened.search("city in France", 4).unwrap(),
+            index.search("city in France", 4).unwrap()
+        );
+
+        opened.add("nice", "Nice", None).unwrap();
+        assert_eq!(opened.search("nice", 1).unwrap()[0].id, "nice");
+
+        let mut saved: Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(saved["version"], json!(BM25_FORMAT_VERSION));
+        saved["version"] = json!(BM25_FORMAT_VERSION + 1);
+        fs::write(&path, saved.to_string()).unwrap();
+        assert!(matches!(
+            Bm25Index::open(&path),
+            Err(Error::InvalidIndex { .. })
+        ));
+    }
+}
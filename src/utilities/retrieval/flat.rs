@@ -0,0 +1,390 @@
+use std::borrow::Cow;
+use std::path::Path;
+
+use ndarray::{Array2, ArrayView1, ArrayView2, Axis};
+use rayon::prelude::*;
+use serde_json::Value;
+
+use crate::utilities::retrieval::storage::{
+    read_index, write_index, MappedVectors, Records, Vectors,
+};
+use crate::utilities::retrieval::top_k::TopK;
+use crate::utilities::retrieval::{Cosine, Metric, SearchResult};
+use crate::{Error, Result};
+
+/// An exact nearest neighbour index that compares a query against every stored vector.
+///
+/// The vectors are kept in one contiguous `Array2`, next to their ids and optional JSON
+/// payloads. It is the baseline the approximate indexes are evaluated against.
+///
+/// An index saved with `save` is opened again without copying its vectors, they are read
+/// from the memory mapped file until the index is modified.
+///
+/// ```
+/// use sandbox_rust::utilities::retrieval::{Cosine, FlatIndex};
+/// let mut index = FlatIndex::new(2, Cosine);
+/// index.add("north", &[0.0, 1.0], None).unwrap();
+/// index.add("east", &[1.0, 0.0], None).unwrap();
+/// assert_eq!(index.search(&[0.1, 0.9], 1).unwrap()[0].id, "north");
+/// ```
+#[derive(Debug, Clone)]
+pub struct FlatIndex<M = Cosine> {
+    metric: M,
+    ids: Vec<String>,
+    vectors: Vectors,
+    payloads: Vec<Option<Value>>,
+}
+
+impl<M: Metric + Sync> FlatIndex<M> {
+    /// It builds an empty index of `dimension` long vectors compared with `metric`
+    #[must_use]
+    pub fn new(dimension: usize, metric: M) -> FlatIndex<M> {
+        FlatIndex {
+            metric,
+            ids: Vec::new(),
+            vectors: Vectors::Owned(Array2::zeros((0, dimension))),
+            payloads: Vec::new(),
+        }
+    }
+
+    /// It builds an index over existing vectors, one row per id
+    ///
+    /// Arguments:
+    ///
+    /// * `ids`: The id of every row.
+    /// * `vectors`: The vectors, shaped `(ids.len(), dimension)`.
+    /// * `metric`: How queries are compared against the vectors.
+    ///
+    /// Returns:
+    ///
+    /// A `FlatIndex` without payloads
+    pub fn from_vectors(ids: Vec<String>, vectors: Array2<f32>, metric: M) -> Result<FlatIndex<M>> {
+        if ids.len() != vectors.nrows() {
+            return Err(Error::Dimension {
+                expected: vectors.nrows(),
+                found: ids.len(),
+            });
+        }
+
+        Ok(FlatIndex {
+            metric,
+            payloads: vec![None; ids.len()],
+            ids,
+            // Searches read every row as a slice
+            vectors: Vectors::Owned(vectors.as_standard_layout().into_owned()),
+        })
+    }
+
+    /// It appends a vector to the index
+    ///
+    /// Arguments:
+    ///
+    /// * `id`: The id returned in search results.
+    /// * `vector`: A vector of the index dimension.
+    /// * `payload`: Optional data returned along with the id.
+    pub fn add<S: Into<String>>(
+        &mut self,
+        id: S,
+        vector: &[f32],
+        payload: Option<Value>,
+    ) -> Result<()> {
+        self.check_dimension(vector)?;
+
+        self.vectors
+            .to_mut()
+            .push_row(ArrayView1::from(vector))
+            .expect("the row has the index dimension");
+        self.ids.push(id.into());
+        self.payloads.push(payload);
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    #[must_use]
+    pub fn dimension(&self) -> usize {
+        self.vectors.view().ncols()
+    }
+
+    #[must_use]
+    pub fn metric(&self) -> &M {
+        &self.metric
+    }
+
+    #[must_use]
+    pub fn ids(&self) -> &[String] {
+        &self.ids
+    }
+
+    #[must_use]
+    pub fn vectors(&self) -> ArrayView2<'_, f32> {
+        self.vectors.view()
+    }
+
+    #[must_use]
+    pub fn payloads(&self) -> &[Option<Value>] {
+        &self.payloads
+    }
+
+    /// It finds the `k` stored vectors closest to `query`
+    ///
+    /// Arguments:
+    ///
+    /// * `query`: A vector of the index dimension.
+    /// * `k`: The number of results.
+    ///
+    /// Returns:
+    ///
+    /// At most `k` results, the closest first, or a `Dimension` error when the query has
+    /// another length than the stored vectors
+    pub fn search(&self, query: &[f32], k: usize) -> Result<Vec<SearchResult>> {
+        self.check_dimension(query)?;
+
+        let mut top = TopK::new(k);
+        for (index, row) in self.vectors.view().outer_iter().enumerate() {
+            let row = row.as_slice().expect("index rows are contiguous");
+            top.push(index, self.metric.score(query, row));
+        }
+
+        Ok(top
+            .into_sorted_vec()
+            .into_iter()
+            .map(|scored| self.result(scored.index, scored.score))
+            .collect())
+    }
+
+    /// It runs `search` for every row of `queries` in parallel
+    ///
+    /// Arguments:
+    ///
+    /// * `queries`: The queries, one per row.
+    /// * `k`: The number of results per query.
+    ///
+    /// Returns:
+    ///
+    /// The results of every query, in the order of the rows
+    pub fn search_batch(
+        &self,
+        queries: ArrayView2<f32>,
+        k: usize,
+    ) -> Result<Vec<Vec<SearchResult>>> {
+        (0..queries.nrows())
+            .into_par_iter()
+            .map(|row| self.search(&queries.index_axis(Axis(0), row).to_vec(), k))
+            .collect()
+    }
+
+    /// It writes the index to `path`, and its ids and payloads to the sidecar next to it
+    ///
+    /// Arguments:
+    ///
+    /// * `path`: The index file, see `sidecar_path` for the sidecar.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        write_index(path.as_ref(), M::NAME, self.vectors(), &self.records())
+    }
+
+    /// It opens an index written by `save`, mapping its vectors instead of reading them
+    ///
+    /// Arguments:
+    ///
+    /// * `path`: The index file.
+    /// * `metric`: The metric the index was saved with.
+    ///
+    /// Returns:
+    ///
+    /// A `FlatIndex`, or an `InvalidIndex` error when the file is truncated or was saved
+    /// with another metric. Damaged vectors are only detected by `verify`.
+    pub fn open<P: AsRef<Path>>(path: P, metric: M) -> Result<FlatIndex<M>> {
+        let path = path.as_ref();
+        let (vectors, records) = read_index(path, M::NAME)?;
+        FlatIndex::from_mapped(path, vectors, records, metric)
+    }
+
+    /// It checks the vectors of an opened index against the checksum saved with them
+    ///
+    /// `open` leaves the vectors in the file until searches read them, this reads them all
+    /// at once.
+    ///
+    /// Returns:
+    ///
+    /// An `InvalidIndex` error when the vectors were damaged since `save`. Indexes held in
+    /// memory always pass.
+    pub fn verify(&self) -> Result<()> {
+        self.vectors.verify()
+    }
+
+    pub(crate) fn records(&self) -> Records<'_> {
+        Records {
+            ids: Cow::Borrowed(&self.ids),
+            payloads: Cow::Borrowed(&self.payloads),
+        }
+    }
+
+    pub(crate) fn from_mapped(
+        path: &Path,
+        vectors: MappedVectors,
+        records: Records,
+        metric: M,
+    ) -> Result<FlatIndex<M>> {
+        if records.ids.len() != vectors.rows() || records.payloads.len() != vectors.rows() {
+            return Err(Error::invalid_index(
+                path,
+                "the sidecar does not hold one id and payload per vector",
+            ));
+        }
+
+        Ok(FlatIndex {
+            metric,
+            ids: records.ids.into_owned(),
+            vectors: Vectors::Mapped(vectors),
+            payloads: records.payloads.into_owned(),
+        })
+    }
+
+    /// The search result of the vector stored at `index`
+    pub(crate) fn result(&self, index: usize, score: f32) -> SearchResult {
+        SearchResult {
+            id: self.ids[index].clone(),
+            score,
+            payload: self.payloads[index].clone(),
+        }
+    }
+
+    pub(crate) fn check_dimension(&self, vector: &[f32]) -> Result<()> {
+        if vector.len() == self.dimension() {
+            Ok(())
+        } else {
+            Err(Error::Dimension {
+                expected: self.dimension(),
+                found: vector.len(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::array;
+    use serde_json::json;
+
+    use super::*;
+    use crate::utilities::retrieval::{DotProduct, Euclidean};
+
+    fn compass() -> FlatIndex<Euclidean> {
+        let mut index = FlatIndex::new(2, Euclidean);
+        index
+            .add("north", &[0.0, 1.0], Some(json!({"angle": 90})))
+            .unwrap();
+        index.add("east", &[1.0, 0.0], None).unwrap();
+        index.add("south", &[0.0, -1.0], None).unwrap();
+        index.add("west", &[-1.0, 0.0], None).unwrap();
+        index
+    }
+
+    #[test]
+    fn test_search() {
+        let index = compass();
+
+        let results = index.search(&[0.2, 0.9], 2).unwrap();
+        let ids: Vec<&str> = results.iter().map(|result| result.id.as_str()).collect();
+        assert_eq!(ids, ["north", "east"]);
+        assert_eq!(results[0].payload, Some(json!({"angle": 90})));
+        assert!(results[0].score > results[1].score);
+
+        assert_eq!(index.search(&[0.0, 0.0], 10).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_dimension_mismatch() {
+        let mut index = compass();
+        assert!(matches!(
+            index.add("up", &[0.0, 0.0, 1.0], None),
+            Err(Error::Dimension {
+                expected: 2,
+                found: 3
+            })
+        ));
+        assert_eq!(index.len(), 4);
+
+        assert!(matches!(
+            index.search(&[0.0], 1),
+            Err(Error::Dimension {
+                expected: 2,
+                found: 1
+            })
+        ));
+        assert!(index.search_batch(Array2::zeros((2, 3)).view(), 1).is_err());
+    }
+
+    #[test]
+    fn test_search_batch_matches_search() {
+        let index = FlatIndex::from_vectors(
+            vec!["a".into(), "b".into(), "c".into()],
+            array![[1.0, 0.0], [0.5, 0.5], [0.0, 2.0]],
+            DotProduct,
+        )
+        .unwrap();
+        let queries = array![[1.0, 0.0], [0.0, 1.0], [1.0, 1.0]];
+
+        let batch = index.search_batch(queries.view(), 2).unwrap();
+        for (row, results) in queries.outer_iter().zip(&batch) {
+            assert_eq!(results, &index.search(row.as_slice().unwrap(), 2).unwrap());
+        }
+        assert_eq!(batch[1][0].id, "c");
+    }
+
+    #[test]
+    fn test_save_and_open() {
+        let path = std::env::temp_dir().join("sandbox-rust-flat.index");
+        let index = compass();
+        index.save(&path).unwrap();
+
+        let mut opened = FlatIndex::open(&path, Euclidean).unwrap();
+        assert!(matches!(opened.vectors, Vectors::Mapped(_)));
+        assert_eq!(opened.vectors(), index.vectors());
+        assert!(opened.verify().is_ok());
+        assert_eq!(
+            opened.search(&[0.2, 0.9], 4).unwrap(),
+            index.search(&[0.2, 0.9], 4).unwrap()
+        );
+
+        // Adding to an opened index leaves the file untouched
+        opened.add("up", &[0.0, 2.0], None).unwrap();
+        assert_eq!(opened.search(&[0.0, 3.0], 1).unwrap()[0].id, "up");
+        assert_eq!(FlatIndex::open(&path, Euclidean).unwrap().len(), 4);
+
+        assert!(matches!(
+            FlatIndex::open(&path, Cosine),
+            Err(Error::InvalidIndex { .. })
+        ));
+    }
+
+    #[test]
+    fn test_save_over_opened_index() {
+        let path = std::env::temp_dir().join("sandbox-rust-flat-resave.index");
+        let index = compass();
+        index.save(&path).unwrap();
+
+        // The opened index still maps the file it is written over
+        let opened = FlatIndex::open(&path, Euclidean).unwrap();
+        opened.save(&path).unwrap();
+        opened.save(&path).unwrap();
+        assert!(matches!(opened.vectors, Vectors::Mapped(_)));
+        assert_eq!(opened.vectors(), index.vectors());
+
+        let reopened = FlatIndex::open(&path, Euclidean).unwrap();
+        assert_eq!(reopened.vectors(), index.vectors());
+        assert_eq!(
+            reopened.search(&[0.2, 0.9], 4).unwrap(),
+            index.search(&[0.2, 0.9], 4).unwrap()
+        );
+    }
+}
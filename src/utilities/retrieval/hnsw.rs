@@ -0,0 +1,474 @@
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet};
+use std::path::Path;
+
+use ndarray::{ArrayView2, Axis};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::utilities::retrieval::storage::{read_index, write_index, Records};
+use crate::utilities::retrieval::top_k::Scored;
+use crate::utilities::retrieval::{splitmix64, Cosine, FlatIndex, Metric, SearchResult};
+use crate::{Error, Result};
+
+/// The parameters of an `HnswIndex`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HnswParams {
+    /// The number of neighbours linked to every node above the bottom layer, which keeps twice as many
+    pub m: usize,
+    /// The number of candidates considered while linking a new node
+    pub ef_construction: usize,
+    /// The number of candidates considered while searching, raised to `k` when lower
+    pub ef_search: usize,
+    /// The seed of the layer assignment, so an index built twice is the same graph
+    pub seed: u64,
+}
+
+impl Default for HnswParams {
+    fn default() -> Self {
+        HnswParams {
+            m: 16,
+            ef_construction: 200,
+            ef_search: 64,
+            seed: 42,
+        }
+    }
+}
+
+/// An approximate nearest neighbour index over a Hierarchical Navigable Small World graph.
+///
+/// Vectors are stored in a `FlatIndex`, so the exact search stays available to measure recall.
+/// Every insert links the new vector into the graph, there is no separate build step.
+///
+/// ```
+/// use sandbox_rust::utilities::retrieval::{Euclidean, HnswIndex, HnswParams};
+/// let mut index = HnswIndex::new(2, Euclidean, HnswParams::default());
+/// index.add("north", &[0.0, 1.0], None).unwrap();
+/// index.add("east", &[1.0, 0.0], None).unwrap();
+/// assert_eq!(index.search(&[0.1, 0.9], 1).unwrap()[0].id, "north");
+/// ```
+pub struct HnswIndex<M = Cosine> {
+    params: HnswParams,
+    store: FlatIndex<M>,
+    /// The neighbours of every node, per layer from the bottom up
+    links: Vec<Vec<Vec<usize>>>,
+    entry: Option<usize>,
+    rng: u64,
+}
+
+/// The sidecar of a saved `HnswIndex`, the graph along with the ids and payloads.
+#[derive(Serialize, Deserialize)]
+struct Graph<'a> {
+    records: Records<'a>,
+    params: HnswParams,
+    links: Cow<'a, [Vec<Vec<usize>>]>,
+    entry: Option<usize>,
+    rng: u64,
+}
+
+impl<M: Metric + Sync> HnswIndex<M> {
+    /// It builds an empty index of `dimension` long vectors compared with `metric`
+    #[must_use]
+    pub fn new(dimension: usize, metric: M, params: HnswParams) -> HnswIndex<M> {
+        HnswIndex {
+            params,
+            store: FlatIndex::new(dimension, metric),
+            links: Vec::new(),
+            entry: None,
+            rng: params.seed,
+        }
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.store.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.store.is_empty()
+    }
+
+    #[must_use]
+    pub fn params(&self) -> HnswParams {
+        self.params
+    }
+
+    /// It changes the number of candidates considered by later searches
+    pub fn set_ef_search(&mut self, ef_search: usize) {
+        self.params.ef_search = ef_search;
+    }
+
+    /// The stored vectors, for exact searches over the same data
+    #[must_use]
+    pub fn store(&self) -> &FlatIndex<M> {
+        &self.store
+    }
+
+    /// It inserts a vector and links it into the graph
+    ///
+    /// Arguments:
+    ///
+    /// * `id`: The id returned in search results.
+    /// * `vector`: A vector of the index dimension.
+    /// * `payload`: Optional data returned along with the id.
+    pub fn add<S: Into<String>>(
+        &mut self,
+        id: S,
+        vector: &[f32],
+        payload: Option<Value>,
+    ) -> Result<()> {
+        self.store.add(id, vector, payload)?;
+
+        let node = self.store.len() - 1;
+        let level = self.random_level();
+        self.links.push(vec![Vec::new(); level + 1]);
+
+        let Some(entry) = self.entry else {
+            self.entry = Some(node);
+            return Ok(());
+        };
+
+        let top = self.links[entry].len() - 1;
+        let mut nearest = vec![self.near(vector, entry)];
+
+        // Walk down the layers the new node is not part of
+        for layer in (level + 1..=top).rev() {
+            nearest = self.search_layer(vector, &nearest, 1, layer);
+        }
+
+        for layer in (0..=level.min(top)).rev() {
+            nearest = self.search_layer(vector, &nearest, self.params.ef_construction, layer);
+
+            let neighbours: Vec<usize> = nearest
+                .iter()
+                .take(self.params.m)
+                .map(|near| near.index)
+                .collect();
+
+            for &neighbour in &neighbours {
+                self.links[neighbour][layer].push(node);
+                self.prune(neighbour, layer);
+            }
+            self.links[node][layer] = neighbours;
+        }
+
+        if level > top {
+            self.entry = Some(node);
+        }
+        Ok(())
+    }
+
+    /// It writes the vectors to `path`, and the graph, ids and payloads to the sidecar next to it
+    ///
+    /// Arguments:
+    ///
+    /// * `path`: The index file, see `sidecar_path` for the sidecar.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let graph = Graph {
+            records: self.store.records(),
+            params: self.params,
+            links: Cow::Borrowed(&self.links),
+            entry: self.entry,
+            rng: self.rng,
+        };
+        write_index(path.as_ref(), M::NAME, self.store.vectors(), &graph)
+    }
+
+    /// It opens an index written by `save`, mapping its vectors instead of reading them
+    ///
+    /// Inserts continue from the saved graph, with the saved parameters.
+    ///
+    /// Arguments:
+    ///
+    /// * `path`: The index file.
+    /// * `metric`: The metric the index was saved with.
+    ///
+    /// Returns:
+    ///
+    /// An `HnswIndex`, or an `InvalidIndex` error when the file is truncated, its graph is
+    /// damaged or it was saved with another metric. Damaged vectors are only detected by
+    /// `store().verify()`.
+    pub fn open<P: AsRef<Path>>(path: P, metric: M) -> Result<HnswIndex<M>> {
+        let path = path.as_ref();
+        let (vectors, graph): (_, Graph) = read_index(path, M::NAME)?;
+        let store = FlatIndex::from_mapped(path, vectors, graph.records, metric)?;
+
+        let nodes = store.len();
+        let linked = graph.links.len() == nodes
+            && graph.entry.map_or(nodes == 0, |entry| entry < nodes)
+            && graph
+                .links
+                .iter()
+                .flatten()
+                .flatten()
+                .all(|&node| node < nodes);
+        if !linked {
+            return Err(Error::invalid_index(
+                path,
+                "the graph does not match the stored vectors",
+            ));
+        }
+
+        Ok(HnswIndex {
+            params: graph.params,
+            store,
+            links: graph.links.into_owned(),
+            entry: graph.entry,
+            rng: graph.rng,
+        })
+    }
+
+    /// It finds approximately the `k` stored vectors closest to `query`
+    ///
+    /// Arguments:
+    ///
+    /// * `query`: A vector of the index dimension.
+    /// * `k`: The number of results.
+    ///
+    /// Returns:
+    ///
+    /// At most `k` results, the closest first, or a `Dimension` error when the query has
+    /// another length than the stored vectors
+    pub fn search(&self, query: &[f32], k: usize) -> Result<Vec<SearchResult>> {
+        self.store.check_dimension(query)?;
+        let Some(entry) = self.entry else {
+            return Ok(Vec::new());
+        };
+
+        let mut nearest = vec![self.near(query, entry)];
+        for layer in (1..self.links[entry].len()).rev() {
+            nearest = self.search_layer(query, &nearest, 1, layer);
+        }
+        nearest = self.search_layer(query, &nearest, self.params.ef_search.max(k), 0);
+
+        Ok(nearest
+            .into_iter()
+            .take(k)
+            .map(|near| {
+                let score = self.store.metric().score(query, self.vector(near.index));
+                self.store.result(near.index, score)
+            })
+            .collect())
+    }
+
+    /// It runs `search` for every row of `queries` in parallel
+    ///
+    /// Arguments:
+    ///
+    /// * `queries`: The queries, one per row.
+    /// * `k`: The number of results per query.
+    ///
+    /// Returns:
+    ///
+    /// The results of every query, in the order of the rows
+    pub fn search_batch(
+        &self,
+        queries: ArrayView2<f32>,
+        k: usize,
+    ) -> Result<Vec<Vec<SearchResult>>> {
+        (0..queries.nrows())
+            .into_par_iter()
+            .map(|row| self.search(&queries.index_axis(Axis(0), row).to_vec(), k))
+            .collect()
+    }
+
+    /// It searches one layer of the graph from `entries`, keeping the `ef` closest nodes
+    ///
+    /// Returns:
+    ///
+    /// The closest nodes found, the closest first
+    fn search_layer(
+        &self,
+        query: &[f32],
+        entries: &[Scored],
+        ef: usize,
+        layer: usize,
+    ) -> Vec<Scored> {
+        let mut visited: HashSet<usize> = entries.iter().map(|near| near.index).collect();
+        // Candidates to expand, closest first, and the best nodes found, furthest first
+        let mut candidates: BinaryHeap<Reverse<Scored>> =
+            entries.iter().copied().map(Reverse).collect();
+        let mut found: BinaryHeap<Scored> = entries.iter().copied().collect();
+
+        while let Some(Reverse(candidate)) = candidates.pop() {
+            let furthest = found.peek().map_or(f32::INFINITY, |near| near.score);
+            if candidate.score > furthest && found.len() >= ef {
+                break;
+            }
+
+            for &neighbour in &self.links[candidate.index][layer] {
+                if !visited.insert(neighbour) {
+                    continue;
+                }
+
+                let near = self.near(query, neighbour);
+                let furthest = found.peek().map_or(f32::INFINITY, |near| near.score);
+                if found.len() < ef || near.score < furthest {
+                    candidates.push(Reverse(near));
+                    found.push(near);
+                    if found.len() > ef {
+                        found.pop();
+                    }
+                }
+            }
+        }
+
+        found.into_sorted_vec()
+    }
+
+    /// It keeps only the closest neighbours of `node` once it has more than a layer allows
+    fn prune(&mut self, node: usize, layer: usize) {
+        let limit = if layer == 0 {
+            2 * self.params.m
+        } else {
+            self.params.m
+        };
+        if self.links[node][layer].len() <= limit {
+            return;
+        }
+
+        let vector = self.vector(node).to_vec();
+        let mut neighbours: Vec<Scored> = self.links[node][layer]
+            .iter()
+            .map(|&neighbour| self.near(&vector, neighbour))
+            .collect();
+        neighbours.sort();
+        neighbours.truncate(limit);
+
+        self.links[node][layer] = neighbours.into_iter().map(|near| near.index).collect();
+    }
+
+    /// The distance from `query` to a stored node, as a `Scored` ordered by distance
+    fn near(&self, query: &[f32], index: usize) -> Scored {
+        Scored {
+            score: self.store.metric().distance(query, self.vector(index)),
+            index,
+        }
+    }
+
+    fn vector(&self, index: usize) -> &[f32] {
+        self.store
+            .vectors()
+            .index_axis_move(Axis(0), index)
+            .to_slice()
+            .expect("index rows are contiguous")
+    }
+
+    /// It draws the top layer of a new node, each layer holding about `1 / m` of the one below
+    fn random_level(&mut self) -> usize {
+        // A uniform draw in (0, 1]
+        let uniform = ((splitmix64(&mut self.rng) >> 11) as f64 + 1.0) / (1u64 << 53) as f64;
+        let scale = 1.0 / (self.params.m.max(2) as f64).ln();
+        (-uniform.ln() * scale).floor() as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::Array2;
+
+    use super::*;
+    use crate::utilities::retrieval::{random_vectors as generate, Euclidean};
+
+    fn build<M: Metric + Sync>(vectors: &Array2<f32>, metric: M) -> HnswIndex<M> {
+        let mut index = HnswIndex::new(vectors.ncols(), metric, HnswParams::default());
+        for (row, vector) in vectors.outer_iter().enumerate() {
+            index
+                .add(row.to_string(), vector.as_slice().unwrap(), None)
+                .unwrap();
+        }
+        index
+    }
+
+    fn recall<M: Metric + Sync>(index: &HnswIndex<M>, queries: &Array2<f32>, k: usize) -> f32 {
+        let approximate = index.search_batch(queries.view(), k).unwrap();
+        let exact = index.store().search_batch(queries.view(), k).unwrap();
+
+        let found: usize = approximate
+            .iter()
+            .zip(&exact)
+            .map(|(approximate, exact)| {
+                approximate
+                    .iter()
+                    .filter(|result| exact.iter().any(|expected| expected.id == result.id))
+                    .count()
+            })
+            .sum();
+        found as f32 / (queries.nrows() * k) as f32
+    }
+
+    #[test]
+    fn test_recall_against_exact_search() {
+        let vectors = generate(2000, 16, 7);
+        let queries = generate(50, 16, 11);
+
+        let euclidean = build(&vectors, Euclidean);
+        assert_eq!(euclidean.len(), 2000);
+        assert!(recall(&euclidean, &queries, 10) >= 0.95);
+
+        let cosine = build(&vectors, Cosine);
+        assert!(recall(&cosine, &queries, 10) >= 0.95);
+    }
+
+    #[test]
+    fn test_incremental_inserts() {
+        let vectors = generate(300, 8, 3);
+        let mut index = build(&vectors, Euclidean);
+
+        // A vector added after the others is found right away
+        index.add("late", &[5.0; 8], None).unwrap();
+        assert_eq!(index.search(&[4.9; 8], 1).unwrap()[0].id, "late");
+
+        for (row, vector) in vectors.outer_iter().enumerate().take(20) {
+            let results = index.search(vector.as_slice().unwrap(), 1).unwrap();
+            assert_eq!(results[0].id, row.to_string());
+        }
+    }
+
+    #[test]
+    fn test_save_and_open() {
+        let path = std::env::temp_dir().join("sandbox-rust-hnsw.index");
+        let vectors = generate(200, 8, 5);
+        let queries = generate(10, 8, 13);
+        let mut index = build(&vectors, Euclidean);
+        index.save(&path).unwrap();
+
+        let mut opened = HnswIndex::open(&path, Euclidean).unwrap();
+        assert_eq!(opened.params(), index.params());
+        assert_eq!(
+            opened.search_batch(queries.view(), 5).unwrap(),
+            index.search_batch(queries.view(), 5).unwrap()
+        );
+
+        // Both keep growing the same graph
+        index.add("late", &[2.0; 8], None).unwrap();
+        opened.add("late", &[2.0; 8], None).unwrap();
+        assert_eq!(opened.links, index.links);
+    }
+
+    #[test]
+    fn test_save_over_opened_index() {
+        let path = std::env::temp_dir().join("sandbox-rust-hnsw-resave.index");
+        let vectors = generate(50, 4, 3);
+        let index = build(&vectors, Euclidean);
+        index.save(&path).unwrap();
+
+        let opened = HnswIndex::open(&path, Euclidean).unwrap();
+        opened.save(&path).unwrap();
+        assert_eq!(opened.store.vectors(), vectors);
+
+        let reopened = HnswIndex::open(&path, Euclidean).unwrap();
+        assert_eq!(reopened.store.vectors(), vectors);
+        assert_eq!(reopened.links, index.links);
+    }
+
+    #[test]
+    fn test_empty_index() {
+        let index = HnswIndex::new(4, Cosine, HnswParams::default());
+        assert!(index.search(&[1.0, 0.0, 0.0, 0.0], 3).unwrap().is_empty());
+        assert!(index.search(&[1.0, 0.0], 3).is_err());
+    }
+}
@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+use crate::models::embedder::Embedder;
+use crate::utilities::retrieval::{Bm25Index, SearchResult, VectorIndex};
+use crate::{Error, Result};
+
+/// How the rankings of the lexical and vector indexes are merged.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fusion {
+    /// Reciprocal Rank Fusion, every list adding `weight / (k + rank)` with ranks from 1
+    Reciprocal { k: f32 },
+    /// The scores of every list scaled to `[0, 1]` and added with their weights
+    Normalized,
+}
+
+/// The fusion of one hybrid search.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FusionParams {
+    pub fusion: Fusion,
+    pub lexical_weight: f32,
+    pub vector_weight: f32,
+    /// The number of results fetched from each index before fusing
+    pub candidates: usize,
+}
+
+impl Default for FusionParams {
+    fn default() -> Self {
+        FusionParams {
+            fusion: Fusion::Reciprocal { k: 60.0 },
+            lexical_weight: 1.0,
+            vector_weight: 1.0,
+            candidates: 100,
+        }
+    }
+}
+
+/// A retriever that searches a `Bm25Index` and a vector index over the same documents and
+/// fuses their rankings.
+///
+/// Lexical search finds rare names and codes that embeddings blur, vector search finds
+/// paraphrases that share no word with the query.
+pub struct HybridRetriever<V> {
+    lexical: Bm25Index,
+    vectors: V,
+}
+
+impl<V: VectorIndex> HybridRetriever<V> {
+    /// It builds a retriever over indexes that hold the same document ids
+    #[must_use]
+    pub fn new(lexical: Bm25Index, vectors: V) -> HybridRetriever<V> {
+        HybridRetriever { lexical, vectors }
+    }
+
+    #[must_use]
+    pub fn lexical(&self) -> &Bm25Index {
+        &self.lexical
+    }
+
+    #[must_use]
+    pub fn vectors(&self) -> &V {
+        &self.vectors
+    }
+
+    /// It adds a document to both indexes
+    ///
+    /// Arguments:
+    ///
+    /// * `id`: The id returned in search results.
+    /// * `text`: The text indexed for lexical search.
+    /// * `embedding`: The embedding of the text.
+    /// * `payload`: Optional data returned along with the id.
+    pub fn add<S: Into<String>>(
+        &mut self,
+        id: S,
+        text: &str,
+        embedding: &[f32],
+        payload: Option<Value>,
+    ) -> Result<()> {
+        // Checked up front, so a failed add leaves both indexes untouched
+        if embedding.len() != self.vectors.dimension() {
+            return Err(Error::Dimension {
+                expected: self.vectors.dimension(),
+                found: embedding.len(),
+            });
+        }
+
+        let id = id.into();
+        self.lexical.add(id.clone(), text, payload.clone())?;
+        self.vectors.add(id, embedding, payload)
+    }
+
+    /// It searches both indexes and fuses their results
+    ///
+    /// Arguments:
+    ///
+    /// * `text`: The query text, for the lexical index.
+    /// * `embedding`: The embedding of the query, for the vector index.
+    /// * `k`: The number of results.
+    /// * `params`: How the two rankings are fused.
+    ///
+    /// Returns:
+    ///
+    /// At most `k` results, the best fused score first
+    pub fn search(
+        &self,
+        text: &str,
+        embedding: &[f32],
+        k: usize,
+        params: FusionParams,
+    ) -> Result<Vec<SearchResult>> {
+        let candidates = params.candidates.max(k);
+        let lexical = self.lexical.search(text, candidates)?;
+        let vectors = self.vectors.search(embedding, candidates)?;
+
+        Ok(fuse(
+            &[
+                (lexical, params.lexical_weight),
+                (vectors, params.vector_weight),
+            ],
+            params.fusion,
+            k,
+        ))
+    }
+
+    /// It embeds the query with `embedder`, then runs `search`
+    pub fn search_text(
+        &self,
+        embedder: &Embedder,
+        text: &str,
+        k: usize,
+        params: FusionParams,
+    ) -> Result<Vec<SearchResult>> {
+        let embedding = embedder.embed(&[text])?.row(0).to_vec();
+        self.search(text, &embedding, k, params)
+    }
+}
+
+/// It merges ranked lists of results into one
+///
+/// Arguments:
+///
+/// * `lists`: Every ranking, the best first, with its weight.
+/// * `fusion`: How ranks or scores are combined.
+/// * `k`: The number of results.
+///
+/// Returns:
+///
+/// At most `k` results scored by `fusion`, the best first. Ties keep the order in which
+/// the documents were first ranked.
+#[must_use]
+pub fn fuse(lists: &[(Vec<SearchResult>, f32)], fusion: Fusion, k: usize) -> Vec<SearchResult> {
+    let mut fused: Vec<SearchResult> = Vec::new();
+    let mut positions: HashMap<&str, usize> = HashMap::new();
+
+    for (results, weight) in lists {
+        let (lowest, highest) = results.iter().fold(
+            (f32::INFINITY, f32::NEG_INFINITY),
+            |(lowest, highest), result| (lowest.min(result.score), highest.max(result.score)),
+        );
+
+        for (rank, result) in results.iter().enumerate() {
+            let score = match fusion {
+                Fusion::Reciprocal { k } => weight / (k + rank as f32 + 1.0),
+                // A list of equal scores gives all of them the top score
+                Fusion::Normalized if highest > lowest => {
+                    weight * (result.score - lowest) / (highest - lowest)
+                }
+                Fusion::Normalized => *weight,
+            };
+
+            let position = *positions.entry(&result.id).or_insert_with(|| {
+                fused.push(SearchResult {
+                    id: result.id.clone(),
+                    score: 0.0,
+                    payload: None,
+                });
+                fused.len() - 1
+            });
+            let entry = &mut fused[position];
+            entry.score += score;
+            if entry.payload.is_none() {
+                entry.payload = result.payload.clone();
+            }
+        }
+    }
+
+    fused.sort_by(|first, second| second.score.total_cmp(&first.score));
+    fused.truncate(k);
+    fused
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::utilities::retrieval::{Analyzer, Bm25Params, Cosine, FlatIndex};
+
+    fn retriever() -> HybridRetriever<FlatIndex> {
+        let lexical = Bm25Index::new(Analyzer::Words, Bm25Params::default()).unwrap();
+        let mut retriever = HybridRetriever::new(lexical, FlatIndex::new(2, Cosine));
+        retriever
+            .add(
+                "sku",
+                "Replacement filter XR-2041 for the purifier",
+                &[0.2, 1.0],
+                None,
+            )
+            .unwrap();
+        retriever
+            .add(
+                "faq",
+                "How often should I change the air cleaner cartridge?",
+                &[1.0, 0.1],
+                Some(json!({"section": "faq"})),
+            )
+            .unwrap();
+        retriever
+            .add(
+                "manual",
+                "Purifier manual and filter cartridge guide",
+                &[0.8, 0.6],
+                None,
+            )
+            .unwrap();
+        retriever
+    }
+
+    fn ids(results: &[SearchResult]) -> Vec<&str> {
+        results.iter().map(|result| result.id.as_str()).collect()
+    }
+
+    #[test]
+    fn test_reciprocal_rank_fusion() {
+        let retriever = retriever();
+        let results = retriever
+            .search("filter cartridge", &[0.9, 0.5], 3, FusionParams::default())
+            .unwrap();
+
+        // The manual is ranked well by both lists
+        assert_eq!(results[0].id, "manual");
+        assert_eq!(results.len(), 3);
+        let faq = results.iter().find(|result| result.id == "faq").unwrap();
+        assert_eq!(faq.payload, Some(json!({"section": "faq"})));
+
+        assert!(matches!(
+            retriever.search("filter", &[0.9, 0.5, 0.1], 3, FusionParams::default()),
+            Err(Error::Dimension {
+                expected: 2,
+                found: 3
+            })
+        ));
+    }
+
+    #[test]
+    fn test_weights_per_query() {
+        let retriever = retriever();
+        let lexical_only = FusionParams {
+            fusion: Fusion::Normalized,
+            vector_weight: 0.0,
+            ..FusionParams::default()
+        };
+        let vector_only = FusionParams {
+            fusion: Fusion::Normalized,
+            lexical_weight: 0.0,
+            ..FusionParams::default()
+        };
+
+        let query = ("XR-2041", [1.0, 0.0]);
+        let lexical = retriever
+            .search(query.0, &query.1, 1, lexical_only)
+            .unwrap();
+        let vector = retriever.search(query.0, &query.1, 1, vector_only).unwrap();
+        assert_eq!(ids(&lexical), ["sku"]);
+        assert_eq!(ids(&vector), ["faq"]);
+    }
+
+    #[test]
+    fn test_fuse() {
+        let result = |id: &str, score| SearchResult {
+            id: id.into(),
+            score,
+            payload: None,
+        };
+        let lists = [
+            (
+                vec![result("a", 10.0), result("b", 5.0), result("c", 0.0)],
+                1.0,
+            ),
+            (vec![result("b", 0.9), result("c", 0.8)], 1.0),
+        ];
+
+        let normalized = fuse(&lists, Fusion::Normalized, 3);
+        assert_eq!(ids(&normalized), ["b", "a", "c"]);
+        assert_eq!(normalized[0].score, 1.5);
+
+        let reciprocal = fuse(&lists, Fusion::Reciprocal { k: 0.0 }, 2);
+        assert_eq!(ids(&reciprocal), ["b", "a"]);
+        assert_eq!(reciprocal[0].score, 1.5);
+    }
+}
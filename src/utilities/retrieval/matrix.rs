@@ -0,0 +1,270 @@
+use std::f32::consts::PI;
+use std::ops::Range;
+
+use ndarray::{s, Array1, Array2, ArrayView1, ArrayView2, Axis};
+
+use crate::utilities::retrieval::{l2_normalize, Kernel, Metric};
+
+/// The rows, and for `similar_pairs` the columns, scored at once, which bounds the
+/// prepared copies and score blocks held next to the result
+const CHUNK_ROWS: usize = 1024;
+
+/// Vectors transformed once for a metric's kernel.
+struct Prepared {
+    vectors: Array2<f32>,
+    /// The squared norm of every row, used by `Kernel::Euclidean`
+    squared_norms: Array1<f32>,
+}
+
+/// The rows of `Prepared` vectors scored in one block.
+struct PreparedRows<'a> {
+    vectors: ArrayView2<'a, f32>,
+    squared_norms: ArrayView1<'a, f32>,
+}
+
+impl Prepared {
+    fn new<M: Metric>(vectors: ArrayView2<f32>) -> Prepared {
+        let vectors = match M::KERNEL {
+            Kernel::Cosine | Kernel::Angular => {
+                let mut vectors = vectors.to_owned();
+                l2_normalize(&mut vectors);
+                vectors
+            }
+            Kernel::PositivePartCosine => {
+                let mut vectors = vectors.mapv(|value| value.max(0.0));
+                l2_normalize(&mut vectors);
+                vectors
+            }
+            Kernel::Dot | Kernel::Euclidean | Kernel::Pairwise => {
+                vectors.as_standard_layout().into_owned()
+            }
+        };
+        let squared_norms = vectors.map_axis(Axis(1), |row| row.dot(&row));
+
+        Prepared {
+            vectors,
+            squared_norms,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.vectors.nrows()
+    }
+
+    fn rows(&self, rows: Range<usize>) -> PreparedRows<'_> {
+        PreparedRows {
+            vectors: self.vectors.slice(s![rows.clone(), ..]),
+            squared_norms: self.squared_norms.slice(s![rows]),
+        }
+    }
+}
+
+impl PreparedRows<'_> {
+    /// It scores every row against every row of `other`, one row of the block per own row
+    fn scores<M: Metric>(&self, other: &PreparedRows, metric: &M) -> Array2<f32> {
+        let (a, b) = (&self.vectors, &other.vectors);
+        match M::KERNEL {
+            Kernel::Pairwise => Array2::from_shape_fn((a.nrows(), b.nrows()), |(i, j)| {
+                let (v1, v2) = (a.row(i), b.row(j));
+                metric.score(
+                    v1.as_slice().expect("prepared rows are contiguous"),
+                    v2.as_slice().expect("prepared rows are contiguous"),
+                )
+            }),
+            Kernel::Dot | Kernel::Cosine | Kernel::PositivePartCosine => a.dot(&b.t()),
+            Kernel::Angular => a
+                .dot(&b.t())
+                .mapv_into(|cosine| 1.0 - cosine.clamp(-1.0, 1.0).acos() / PI),
+            Kernel::Euclidean => {
+                let mut scores = a.dot(&b.t());
+                for (mut row, norm) in scores.outer_iter_mut().zip(self.squared_norms) {
+                    row.zip_mut_with(&other.squared_norms, |dot, other_norm| {
+                        // Rounding can leave a slightly negative square for equal vectors
+                        *dot = -(norm + other_norm - 2.0 * *dot).max(0.0).sqrt();
+                    });
+                }
+                scores
+            }
+        }
+    }
+}
+
+/// It scores every row of `a` against every row of `b`
+///
+/// The vectors are prepared once for the metric, normalized for the cosines, and scored
+/// with matrix products over chunks of `a`, instead of one `Metric::score` call per pair.
+///
+/// Arguments:
+///
+/// * `a`: The first vectors, one per row.
+/// * `b`: The vectors to compare against, with as many columns as `a`.
+/// * `metric`: How two vectors are scored, higher meaning closer.
+///
+/// Returns:
+///
+/// The scores, shaped `(a.nrows(), b.nrows())`
+/// ```
+/// use ndarray::array;
+/// use sandbox_rust::utilities::retrieval::{similarity_matrix, Cosine};
+/// let scores = similarity_matrix(array![[1.0, 0.0]].view(), array![[2.0, 0.0], [0.0, 1.0]].view(), Cosine);
+/// assert_eq!(scores, array![[1.0, 0.0]]);
+/// ```
+#[must_use]
+pub fn similarity_matrix<M: Metric>(
+    a: ArrayView2<f32>,
+    b: ArrayView2<f32>,
+    metric: M,
+) -> Array2<f32> {
+    chunked_similarity_matrix(a, b, &metric, CHUNK_ROWS)
+}
+
+/// It finds the pairs of rows of `vectors` that score at least `threshold` with each other
+///
+/// Only the upper triangle of the self similarity matrix is computed, one square block of
+/// rows and columns at a time, so neither the full matrix nor a full band of it is ever
+/// held in memory.
+///
+/// Arguments:
+///
+/// * `vectors`: The vectors, one per row.
+/// * `metric`: How two vectors are scored, higher meaning closer.
+/// * `threshold`: The lowest score kept.
+///
+/// Returns:
+///
+/// The `(i, j, score)` of every pair with `i < j` scoring at least `threshold`, ordered by
+/// `i` then `j`
+#[must_use]
+pub fn similar_pairs<M: Metric>(
+    vectors: ArrayView2<f32>,
+    metric: M,
+    threshold: f32,
+) -> Vec<(usize, usize, f32)> {
+    chunked_similar_pairs(vectors, &metric, threshold, CHUNK_ROWS)
+}
+
+fn chunked_similarity_matrix<M: Metric>(
+    a: ArrayView2<f32>,
+    b: ArrayView2<f32>,
+    metric: &M,
+    chunk_rows: usize,
+) -> Array2<f32> {
+    assert_eq!(a.ncols(), b.ncols(), "the vectors have the same dimension");
+
+    let b = Prepared::new::<M>(b);
+    let b = b.rows(0..b.len());
+    let mut scores = Array2::zeros((a.nrows(), b.vectors.nrows()));
+    for (chunk, mut block) in a
+        .axis_chunks_iter(Axis(0), chunk_rows)
+        .zip(scores.axis_chunks_iter_mut(Axis(0), chunk_rows))
+    {
+        let chunk = Prepared::new::<M>(chunk);
+        block.assign(&chunk.rows(0..chunk.len()).scores(&b, metric));
+    }
+    scores
+}
+
+fn chunked_similar_pairs<M: Metric>(
+    vectors: ArrayView2<f32>,
+    metric: &M,
+    threshold: f32,
+    chunk_rows: usize,
+) -> Vec<(usize, usize, f32)> {
+    let vectors = Prepared::new::<M>(vectors);
+    let count = vectors.len();
+    let mut pairs = Vec::new();
+
+    for start in (0..count).step_by(chunk_rows) {
+        let rows = vectors.rows(start..(start + chunk_rows).min(count));
+        let band = pairs.len();
+
+        // Only the columns from `start` on hold pairs of the upper triangle
+        for column_start in (start..count).step_by(chunk_rows) {
+            let columns = vectors.rows(column_start..(column_start + chunk_rows).min(count));
+
+            for (offset, scores) in rows.scores(&columns, metric).outer_iter().enumerate() {
+                let i = start + offset;
+                for (column, &score) in scores.iter().enumerate() {
+                    let j = column_start + column;
+                    if j > i && score >= threshold {
+                        pairs.push((i, j, score));
+                    }
+                }
+            }
+        }
+        // The column blocks of a band interleave its rows
+        pairs[band..].sort_unstable_by_key(|&(i, j, _)| (i, j));
+    }
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::array;
+
+    use super::*;
+    use crate::utilities::retrieval::{
+        Angular, Cosine, DotProduct, Euclidean, Manhattan, PositivePartCosine,
+    };
+
+    fn vectors() -> Array2<f32> {
+        array![
+            [1.0, 2.0, 0.0],
+            [-1.0, 0.5, 3.0],
+            [0.0, 0.0, 0.0],
+            [2.0, 4.0, 0.0],
+            [0.3, -2.0, 1.0]
+        ]
+    }
+
+    fn matches_pairwise<M: Metric>(metric: M) {
+        let a = vectors();
+        // A transposed copy checks that views of any layout are accepted
+        let b = vectors().slice(s![1.., ..]).t().to_owned();
+        let b = b.t();
+
+        let scores = chunked_similarity_matrix(a.view(), b, &metric, 2);
+        assert_eq!(scores.dim(), (5, 4));
+        for ((i, j), &score) in scores.indexed_iter() {
+            let expected = metric.score(&a.row(i).to_vec(), &b.row(j).to_vec());
+            // The angle of near parallel vectors magnifies rounding of their cosine
+            assert!((score - expected).abs() < 1e-3, "{} {i} {j}", M::NAME);
+        }
+    }
+
+    #[test]
+    fn test_matrix_matches_pairwise_scores() {
+        matches_pairwise(Cosine);
+        matches_pairwise(PositivePartCosine);
+        matches_pairwise(DotProduct);
+        matches_pairwise(Euclidean);
+        matches_pairwise(Manhattan);
+        matches_pairwise(Angular);
+    }
+
+    #[test]
+    fn test_similar_pairs() {
+        let vectors = vectors();
+
+        // Only the first and fourth rows point the same way
+        for chunk_rows in [1, 2, 10] {
+            let pairs = chunked_similar_pairs(vectors.view(), &Cosine, 0.9, chunk_rows);
+            assert_eq!(pairs.len(), 1);
+            assert_eq!((pairs[0].0, pairs[0].1), (0, 3));
+            assert!((pairs[0].2 - 1.0).abs() < 1e-6);
+        }
+
+        // Every pair of the upper triangle is kept when the threshold allows it, in order
+        let pairs = similar_pairs(vectors.view(), Euclidean, f32::NEG_INFINITY);
+        assert_eq!(pairs.len(), 10);
+        for chunk_rows in [1, 2, 3] {
+            assert_eq!(
+                chunked_similar_pairs(vectors.view(), &Euclidean, f32::NEG_INFINITY, chunk_rows),
+                pairs
+            );
+        }
+        assert!(pairs
+            .windows(2)
+            .all(|w| (w[0].0, w[0].1) < (w[1].0, w[1].1)));
+    }
+}
@@ -0,0 +1,408 @@
+use ndarray::Array2;
+use serde_json::Value;
+
+use crate::Result;
+
+mod bm25;
+mod flat;
+mod hnsw;
+mod hybrid;
+mod matrix;
+mod quantization;
+mod storage;
+mod top_k;
+
+pub use bm25::{Analyzer, Bm25Index, Bm25Params, BM25_FORMAT_VERSION};
+pub use flat::FlatIndex;
+pub use hnsw::{HnswIndex, HnswParams};
+pub use hybrid::{fuse, Fusion, FusionParams, HybridRetriever};
+pub use matrix::{similar_pairs, similarity_matrix};
+pub use quantization::{
+    quantization_report, recall, PqParams, ProductQuantizer, QuantizationReport, QuantizedIndex,
+    Quantizer, ScalarQuantizer,
+};
+pub use storage::{sidecar_path, FORMAT_VERSION};
+
+/// A stored item matched by a search, with the score it was ranked by.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchResult {
+    pub id: String,
+    /// Higher means closer to the query
+    pub score: f32,
+    pub payload: Option<Value>,
+}
+
+/// The operations shared by the vector indexes, so retrievers can be built over any of them.
+pub trait VectorIndex {
+    /// The length of the stored vectors
+    fn dimension(&self) -> usize;
+
+    /// It appends a vector to the index
+    fn add(&mut self, id: String, vector: &[f32], payload: Option<Value>) -> Result<()>;
+
+    /// It finds the `k` stored vectors closest to `query`, the closest first, or a
+    /// `Dimension` error when the query has another length than the stored vectors
+    fn search(&self, query: &[f32], k: usize) -> Result<Vec<SearchResult>>;
+}
+
+impl<M: Metric + Sync> VectorIndex for FlatIndex<M> {
+    fn dimension(&self) -> usize {
+        FlatIndex::dimension(self)
+    }
+
+    fn add(&mut self, id: String, vector: &[f32], payload: Option<Value>) -> Result<()> {
+        FlatIndex::add(self, id, vector, payload)
+    }
+
+    fn search(&self, query: &[f32], k: usize) -> Result<Vec<SearchResult>> {
+        FlatIndex::search(self, query, k)
+    }
+}
+
+impl<M: Metric + Sync> VectorIndex for HnswIndex<M> {
+    fn dimension(&self) -> usize {
+        self.store().dimension()
+    }
+
+    fn add(&mut self, id: String, vector: &[f32], payload: Option<Value>) -> Result<()> {
+        HnswIndex::add(self, id, vector, payload)
+    }
+
+    fn search(&self, query: &[f32], k: usize) -> Result<Vec<SearchResult>> {
+        HnswIndex::search(self, query, k)
+    }
+}
+
+/// A way of comparing two embedding vectors of the same length.
+///
+/// Every metric is a distance, lower meaning closer, and a score used to rank search
+/// results, higher meaning closer. Similarity metrics override `score` with the
+/// similarity itself, distances rank by the negated distance.
+pub trait Metric {
+    /// The name stored alongside persisted indexes
+    const NAME: &'static str;
+
+    /// How `similarity_matrix` scores many pairs at once
+    const KERNEL: Kernel = Kernel::Pairwise;
+
+    /// It returns how far apart `v1` and `v2` are, lower meaning closer
+    fn distance(&self, v1: &[f32], v2: &[f32]) -> f32;
+
+    /// It returns how close `v1` and `v2` are, higher meaning closer
+    fn score(&self, v1: &[f32], v2: &[f32]) -> f32 {
+        -self.distance(v1, v2)
+    }
+}
+
+/// How a metric's scores are computed over whole matrices of vectors.
+///
+/// Every kernel but `Pairwise` is a matrix product over vectors prepared once, normalized
+/// for the cosines, and gives the same scores as `Metric::score` up to rounding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kernel {
+    /// Every pair is scored with `Metric::score`
+    Pairwise,
+    /// The dot product
+    Dot,
+    /// The dot product of unit vectors
+    Cosine,
+    /// The dot product of the unit positive parts of the vectors
+    PositivePartCosine,
+    /// The negated distance, from the dot product and the squared norms
+    Euclidean,
+    /// One minus the angle over pi, from the dot product of unit vectors
+    Angular,
+}
+
+/// Cosine similarity, scored in `[-1, 1]` with a distance of `1 - cosine`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Cosine;
+
+/// The original cosine of this crate, which only counts the components that are positive.
+/// Kept so scores computed with it can be reproduced, use `Cosine` otherwise.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PositivePartCosine;
+
+/// The dot product, for embeddings that are already normalized or trained for it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DotProduct;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Euclidean;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Manhattan;
+
+/// The angle between two vectors, divided by pi so it falls in `[0, 1]`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Angular;
+
+impl Metric for Cosine {
+    const NAME: &'static str = "cosine";
+    const KERNEL: Kernel = Kernel::Cosine;
+
+    fn distance(&self, v1: &[f32], v2: &[f32]) -> f32 {
+        1.0 - self.score(v1, v2)
+    }
+
+    fn score(&self, v1: &[f32], v2: &[f32]) -> f32 {
+        cosine(v1, v2)
+    }
+}
+
+impl Metric for PositivePartCosine {
+    const NAME: &'static str = "positive_part_cosine";
+    const KERNEL: Kernel = Kernel::PositivePartCosine;
+
+    fn distance(&self, v1: &[f32], v2: &[f32]) -> f32 {
+        1.0 - self.score(v1, v2)
+    }
+
+    fn score(&self, v1: &[f32], v2: &[f32]) -> f32 {
+        positive_part_cosine(v1, v2)
+    }
+}
+
+impl Metric for DotProduct {
+    const NAME: &'static str = "dot";
+    const KERNEL: Kernel = Kernel::Dot;
+
+    fn distance(&self, v1: &[f32], v2: &[f32]) -> f32 {
+        -self.score(v1, v2)
+    }
+
+    fn score(&self, v1: &[f32], v2: &[f32]) -> f32 {
+        dot(v1, v2)
+    }
+}
+
+impl Metric for Euclidean {
+    const NAME: &'static str = "euclidean";
+    const KERNEL: Kernel = Kernel::Euclidean;
+
+    fn distance(&self, v1: &[f32], v2: &[f32]) -> f32 {
+        v1.iter()
+            .zip(v2)
+            .map(|(x1, x2)| (x1 - x2).powi(2))
+            .sum::<f32>()
+            .sqrt()
+    }
+}
+
+impl Metric for Manhattan {
+    const NAME: &'static str = "manhattan";
+
+    fn distance(&self, v1: &[f32], v2: &[f32]) -> f32 {
+        v1.iter().zip(v2).map(|(x1, x2)| (x1 - x2).abs()).sum()
+    }
+}
+
+impl Metric for Angular {
+    const NAME: &'static str = "angular";
+    const KERNEL: Kernel = Kernel::Angular;
+
+    fn distance(&self, v1: &[f32], v2: &[f32]) -> f32 {
+        // Rounding can push the cosine of parallel vectors slightly past 1
+        cosine(v1, v2).clamp(-1.0, 1.0).acos() / std::f32::consts::PI
+    }
+
+    fn score(&self, v1: &[f32], v2: &[f32]) -> f32 {
+        1.0 - self.distance(v1, v2)
+    }
+}
+
+/// It takes two vectors of floats, and returns the cosine similarity between them
+///
+/// Arguments:
+///
+/// * `v1`: The first vector
+/// * `v2`: The vector to compare against
+///
+/// Returns:
+///
+/// The cosine of the angle between the vectors, in `[-1, 1]`, or `0.0` when either is a zero vector.
+/// ```
+/// use sandbox_rust::utilities::retrieval::cosine_similarity;
+/// let v1 = vec![0.0, 0.0, 0.0];
+/// let v2 = vec![1.0, 1.0, 1.0];
+/// let v3 = vec![-1.0, -1.0, -1.0];
+/// assert!(cosine_similarity(&v2, &v2) == 1.0);
+/// assert_eq!(cosine_similarity(&v1, &v2), 0.0);
+/// assert_eq!(cosine_similarity(&v2, &v3), -1.0);
+/// ```
+#[must_use]
+pub fn cosine_similarity(v1: &[f32], v2: &[f32]) -> f64 {
+    cosine(v1, v2).into()
+}
+
+/// It takes two vectors of floats, and returns the cosine similarity of their positive parts,
+/// skipping every component that is not positive in the dot product and in both norms
+///
+/// This was the behaviour of `cosine_similarity` before it returned the true cosine.
+///
+/// Arguments:
+///
+/// * `v1`: The first vector
+/// * `v2`: The vector to compare against
+///
+/// Returns:
+///
+/// A similarity in `[0, 1]`, or `0.0` when either vector has no positive component.
+#[must_use]
+pub fn positive_part_cosine_similarity(v1: &[f32], v2: &[f32]) -> f64 {
+    positive_part_cosine(v1, v2).into()
+}
+
+/// It scales every row to unit length, leaving zero rows untouched, so the dot product of
+/// two rows is their cosine
+pub fn l2_normalize(vectors: &mut Array2<f32>) {
+    for mut row in vectors.outer_iter_mut() {
+        let norm = row.dot(&row).sqrt();
+        if norm > 0.0 {
+            row /= norm;
+        }
+    }
+}
+
+/// It advances a SplitMix64 state and returns the next pseudo random number
+///
+/// Enough to pick graph layers or k-means seeds reproducibly without another dependency.
+pub(crate) fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Reproducible vectors in `[-1, 1)` for the tests of the indexes, drawn with `splitmix64`
+#[cfg(test)]
+pub(crate) fn random_vectors(rows: usize, dimension: usize, seed: u64) -> Array2<f32> {
+    let mut state = seed;
+    Array2::from_shape_simple_fn((rows, dimension), || {
+        (splitmix64(&mut state) >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+    })
+}
+
+fn dot(v1: &[f32], v2: &[f32]) -> f32 {
+    v1.iter().zip(v2).map(|(x1, x2)| x1 * x2).sum()
+}
+
+fn cosine(v1: &[f32], v2: &[f32]) -> f32 {
+    let mut dot = 0.0;
+    let mut norm1 = 0.0;
+    let mut norm2 = 0.0;
+
+    for (x1, x2) in v1.iter().zip(v2) {
+        dot += x1 * x2;
+        norm1 += x1.powi(2);
+        norm2 += x2.powi(2);
+    }
+
+    if norm1 * norm2 == 0.0 {
+        0.0
+    } else {
+        dot / (norm1.sqrt() * norm2.sqrt())
+    }
+}
+
+fn positive_part_cosine(v1: &[f32], v2: &[f32]) -> f32 {
+    let mut dot = 0.0;
+    let mut norm1 = 0.0;
+    let mut norm2 = 0.0;
+
+    for (&x1, &x2) in v1.iter().zip(v2) {
+        if x1 > 0.0 && x2 > 0.0 {
+            dot += x1 * x2;
+        }
+        if x1 > 0.0 {
+            norm1 += x1.powi(2);
+        }
+
+        if x2 > 0.0 {
+            norm2 += x2.powi(2);
+        }
+    }
+
+    if norm1 * norm2 == 0.0 {
+        0.0
+    } else {
+        dot / (norm1.sqrt() * norm2.sqrt())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::array;
+
+    use super::*;
+
+    #[test]
+    fn test_l2_normalize() {
+        let mut vectors = array![[3.0, 4.0], [0.0, 0.0]];
+        l2_normalize(&mut vectors);
+        assert_eq!(vectors, array![[0.6, 0.8], [0.0, 0.0]]);
+    }
+
+    #[test]
+    fn test_cosine_similarity_domain() {
+        let v1 = vec![0.0, 0.0, 0.0];
+        let v2 = vec![1.0, 1.0, 1.0];
+        let v3 = vec![2.0, 2.0, 2.0];
+
+        let v4 = vec![1.0, 2.0, 3.0];
+
+        // Dissimilarity with a Zero Vector
+        assert!(cosine_similarity(&v1, &v2) == 0.0);
+
+        // Equal vectors
+        assert!(cosine_similarity(&v2, &v2) == 1.0);
+
+        // Equal vectors v3 = v2 * 2
+        assert!(cosine_similarity(&v3, &v2) == 1.0);
+
+        // Dissimilar vectors
+        let t1 = cosine_similarity(&v4, &v2);
+        let t2 = cosine_similarity(&v4, &v3);
+        assert!(t1 < 1.0 && t1 > 0.0);
+        assert!(t2 < 1.0 && t2 > 0.0);
+
+        assert_eq!(t1, t2);
+    }
+
+    #[test]
+    fn test_signed_cosine() {
+        let v1 = vec![1.0, -2.0, 0.5];
+        let v2 = vec![-1.0, 2.0, -0.5];
+        let v3 = vec![2.0, 1.0, 0.0];
+
+        assert!((cosine_similarity(&v1, &v2) + 1.0).abs() < 1e-6);
+        assert!(cosine_similarity(&v1, &v3).abs() < 1e-6);
+
+        // Negative components are dropped from the dot product and from both norms
+        assert!((positive_part_cosine_similarity(&v1, &v3) - 0.8).abs() < 1e-6);
+        assert_eq!(positive_part_cosine_similarity(&v1, &v2), 0.0);
+    }
+
+    #[test]
+    fn test_metrics() {
+        let v1 = [1.0, 0.0];
+        let v2 = [0.0, 1.0];
+        let v3 = [3.0, 4.0];
+
+        assert!((Cosine.distance(&v1, &v2) - 1.0).abs() < 1e-6);
+        assert_eq!(DotProduct.score(&v1, &v3), 3.0);
+        assert_eq!(Euclidean.distance(&[0.0, 0.0], &v3), 5.0);
+        assert_eq!(Euclidean.score(&[0.0, 0.0], &v3), -5.0);
+        assert_eq!(Manhattan.distance(&[0.0, 0.0], &v3), 7.0);
+        assert!((Angular.distance(&v1, &v2) - 0.5).abs() < 1e-6);
+        assert_eq!(Angular.distance(&v3, &v3), 0.0);
+
+        // Every metric ranks a vector closer to itself than to an orthogonal one
+        fn closer<M: Metric>(metric: M) -> bool {
+            metric.score(&[1.0, 1.0], &[1.0, 1.0]) > metric.score(&[1.0, 1.0], &[1.0, -1.0])
+        }
+        assert!(closer(Cosine) && closer(PositivePartCosine) && closer(DotProduct));
+        assert!(closer(Euclidean) && closer(Manhattan) && closer(Angular));
+    }
+}
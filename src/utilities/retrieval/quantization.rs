@@ -0,0 +1,1103 @@
+use std::borrow::Cow;
+use std::f32::consts::PI;
+use std::fmt;
+use std::path::Path;
+
+use ndarray::{s, Array2, ArrayView1, ArrayView2, Axis};
+use rayon::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::utilities::retrieval::storage::{read_codes, write_index, Records};
+use crate::utilities::retrieval::top_k::TopK;
+use crate::utilities::retrieval::{splitmix64, Cosine, FlatIndex, Kernel, Metric, SearchResult};
+use crate::{Error, Result};
+
+/// A lossy encoding of vectors into bytes, which full precision queries are scored against
+/// without decoding, the asymmetric distance computation.
+pub trait Quantizer {
+    /// A query prepared once before it is scored against every code
+    type Query;
+
+    /// A short name for reports, such as `int8` or `pq-16x256`
+    fn name(&self) -> String;
+
+    fn dimension(&self) -> usize;
+
+    /// The number of bytes of every code
+    fn code_len(&self) -> usize;
+
+    fn encode(&self, vector: &[f32]) -> Vec<u8>;
+
+    fn decode(&self, code: &[u8]) -> Vec<f32>;
+
+    fn prepare(&self, query: &[f32]) -> Self::Query;
+
+    /// The dot product of a prepared query with the vector a code stands for
+    fn dot(&self, query: &Self::Query, code: &[u8]) -> f32;
+}
+
+/// A quantizer that maps every component to one of 256 evenly spaced levels between the
+/// lowest and highest value of its dimension in the training sample.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScalarQuantizer {
+    offsets: Vec<f32>,
+    scales: Vec<f32>,
+}
+
+impl ScalarQuantizer {
+    /// It learns the range of every dimension
+    ///
+    /// Arguments:
+    ///
+    /// * `sample`: Vectors like the ones to encode, one per row.
+    ///
+    /// Returns:
+    ///
+    /// A `ScalarQuantizer`, values outside the sample range are clamped to it, or a
+    /// `Quantizer` error when the sample is empty
+    pub fn train(sample: ArrayView2<f32>) -> Result<ScalarQuantizer> {
+        if sample.nrows() == 0 {
+            return Err(Error::Quantizer {
+                reason: "the training sample is empty".to_string(),
+            });
+        }
+
+        let (offsets, scales) = sample
+            .axis_iter(Axis(1))
+            .map(|column| {
+                let lowest = column.fold(f32::INFINITY, |lowest, &value| lowest.min(value));
+                let highest = column.fold(f32::NEG_INFINITY, |highest, &value| highest.max(value));
+                if highest > lowest {
+                    (lowest, (highest - lowest) / 255.0)
+                } else if lowest.is_finite() {
+                    (lowest, 0.0)
+                } else {
+                    (0.0, 0.0)
+                }
+            })
+            .unzip();
+
+        Ok(ScalarQuantizer { offsets, scales })
+    }
+}
+
+impl Quantizer for ScalarQuantizer {
+    /// The query scaled per dimension, and its dot product with the offsets
+    type Query = (Vec<f32>, f32);
+
+    fn name(&self) -> String {
+        "int8".to_string()
+    }
+
+    fn dimension(&self) -> usize {
+        self.offsets.len()
+    }
+
+    fn code_len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    fn encode(&self, vector: &[f32]) -> Vec<u8> {
+        vector
+            .iter()
+            .zip(self.offsets.iter().zip(&self.scales))
+            .map(|(value, (offset, scale))| {
+                if *scale > 0.0 {
+                    ((value - offset) / scale).round().clamp(0.0, 255.0) as u8
+                } else {
+                    0
+                }
+            })
+            .collect()
+    }
+
+    fn decode(&self, code: &[u8]) -> Vec<f32> {
+        code.iter()
+            .zip(self.offsets.iter().zip(&self.scales))
+            .map(|(&level, (offset, scale))| offset + scale * f32::from(level))
+            .collect()
+    }
+
+    fn prepare(&self, query: &[f32]) -> Self::Query {
+        let scaled = query
+            .iter()
+            .zip(&self.scales)
+            .map(|(value, scale)| value * scale)
+            .collect();
+        let offset = query
+            .iter()
+            .zip(&self.offsets)
+            .map(|(value, offset)| value * offset)
+            .sum();
+        (scaled, offset)
+    }
+
+    fn dot(&self, (scaled, offset): &Self::Query, code: &[u8]) -> f32 {
+        offset
+            + scaled
+                .iter()
+                .zip(code)
+                .map(|(value, &level)| value * f32::from(level))
+                .sum::<f32>()
+    }
+}
+
+/// The parameters of `ProductQuantizer::train`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PqParams {
+    /// The number of slices every vector is split into, each encoded in one byte
+    pub subspaces: usize,
+    /// The centroids learned per subspace, at most 256
+    pub centroids: usize,
+    /// The most k-means iterations per subspace
+    pub iterations: usize,
+    /// The seed of the initial centroids
+    pub seed: u64,
+}
+
+impl Default for PqParams {
+    fn default() -> Self {
+        PqParams {
+            subspaces: 8,
+            centroids: 256,
+            iterations: 20,
+            seed: 42,
+        }
+    }
+}
+
+/// A quantizer that splits vectors into subspaces and encodes every slice as the index of
+/// its nearest centroid, learned with k-means.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(into = "SavedCodebooks", try_from = "SavedCodebooks")]
+pub struct ProductQuantizer {
+    dimension: usize,
+    /// The centroids of every subspace, shaped `(centroids, dimension / subspaces)`
+    codebooks: Vec<Array2<f32>>,
+}
+
+/// A `ProductQuantizer` as saved in the sidecar of a `QuantizedIndex`, every centroid
+/// being a list of values.
+#[derive(Serialize, Deserialize)]
+struct SavedCodebooks {
+    dimension: usize,
+    codebooks: Vec<Vec<Vec<f32>>>,
+}
+
+impl From<ProductQuantizer> for SavedCodebooks {
+    fn from(quantizer: ProductQuantizer) -> Self {
+        SavedCodebooks {
+            dimension: quantizer.dimension,
+            codebooks: quantizer
+                .codebooks
+                .iter()
+                .map(|codebook| codebook.outer_iter().map(|row| row.to_vec()).collect())
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<SavedCodebooks> for ProductQuantizer {
+    type Error = String;
+
+    fn try_from(saved: SavedCodebooks) -> std::result::Result<Self, Self::Error> {
+        let subspaces = saved.codebooks.len();
+        if subspaces == 0 || !saved.dimension.is_multiple_of(subspaces) {
+            return Err(format!(
+                "{} dimensions cannot be split into {subspaces} subspaces",
+                saved.dimension
+            ));
+        }
+
+        let width = saved.dimension / subspaces;
+        let codebooks = saved
+            .codebooks
+            .into_iter()
+            .map(|centroids| {
+                if !(1..=256).contains(&centroids.len()) {
+                    return Err(format!(
+                        "a byte holds between 1 and 256 centroids, not {}",
+                        centroids.len()
+                    ));
+                }
+                let rows = centroids.len();
+                Array2::from_shape_vec((rows, width), centroids.concat())
+                    .map_err(|_| format!("every centroid has {width} values"))
+            })
+            .collect::<std::result::Result<_, _>>()?;
+
+        Ok(ProductQuantizer {
+            dimension: saved.dimension,
+            codebooks,
+        })
+    }
+}
+
+impl ProductQuantizer {
+    /// It learns the centroids of every subspace from a sample, in parallel
+    ///
+    /// Arguments:
+    ///
+    /// * `sample`: Vectors like the ones to encode, one per row.
+    /// * `params`: The number of subspaces and centroids, and how long k-means runs.
+    ///
+    /// Returns:
+    ///
+    /// A `ProductQuantizer`, or an error when the parameters do not fit the sample
+    pub fn train(sample: ArrayView2<f32>, params: PqParams) -> Result<ProductQuantizer> {
+        let dimension = sample.ncols();
+        if params.subspaces == 0 || !dimension.is_multiple_of(params.subspaces) {
+            return Err(Error::Quantizer {
+                reason: format!(
+                    "{dimension} dimensions cannot be split into {} subspaces",
+                    params.subspaces
+                ),
+            });
+        }
+        if !(1..=256).contains(&params.centroids) {
+            return Err(Error::Quantizer {
+                reason: format!(
+                    "a byte holds between 1 and 256 centroids, not {}",
+                    params.centroids
+                ),
+            });
+        }
+        if sample.nrows() == 0 {
+            return Err(Error::Quantizer {
+                reason: "the training sample is empty".to_string(),
+            });
+        }
+
+        let width = dimension / params.subspaces;
+        let codebooks = (0..params.subspaces)
+            .into_par_iter()
+            .map(|subspace| {
+                k_means(
+                    sample.slice(s![.., subspace * width..(subspace + 1) * width]),
+                    params.centroids,
+                    params.iterations,
+                    params.seed.wrapping_add(subspace as u64),
+                )
+            })
+            .collect();
+
+        Ok(ProductQuantizer {
+            dimension,
+            codebooks,
+        })
+    }
+
+    fn width(&self) -> usize {
+        self.dimension / self.codebooks.len()
+    }
+}
+
+impl Quantizer for ProductQuantizer {
+    /// The dot product of every query slice with every centroid of its subspace
+    type Query = Vec<Vec<f32>>;
+
+    fn name(&self) -> String {
+        format!("pq-{}x{}", self.codebooks.len(), self.codebooks[0].nrows())
+    }
+
+    fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    fn code_len(&self) -> usize {
+        self.codebooks.len()
+    }
+
+    fn encode(&self, vector: &[f32]) -> Vec<u8> {
+        vector
+            .chunks(self.width())
+            .zip(&self.codebooks)
+            .map(|(slice, codebook)| nearest(codebook, ArrayView1::from(slice)) as u8)
+            .collect()
+    }
+
+    fn decode(&self, code: &[u8]) -> Vec<f32> {
+        code.iter()
+            .zip(&self.codebooks)
+            .flat_map(|(&centroid, codebook)| codebook.row(usize::from(centroid)).to_vec())
+            .collect()
+    }
+
+    fn prepare(&self, query: &[f32]) -> Self::Query {
+        query
+            .chunks(self.width())
+            .zip(&self.codebooks)
+            .map(|(slice, codebook)| codebook.dot(&ArrayView1::from(slice)).to_vec())
+            .collect()
+    }
+
+    fn dot(&self, tables: &Self::Query, code: &[u8]) -> f32 {
+        tables
+            .iter()
+            .zip(code)
+            .map(|(table, &centroid)| table[usize::from(centroid)])
+            .sum()
+    }
+}
+
+/// It clusters vectors with Lloyd's algorithm, starting from distinct random rows
+///
+/// Returns:
+///
+/// At most `k` centroids, one per row
+fn k_means(vectors: ArrayView2<f32>, k: usize, iterations: usize, seed: u64) -> Array2<f32> {
+    let k = k.min(vectors.nrows());
+
+    // A partial Fisher-Yates shuffle picks the first centroids
+    let mut rows: Vec<usize> = (0..vectors.nrows()).collect();
+    let mut state = seed;
+    for i in 0..k {
+        let j = i + (splitmix64(&mut state) % (rows.len() - i) as u64) as usize;
+        rows.swap(i, j);
+    }
+    let mut centroids = vectors.select(Axis(0), &rows[..k]);
+
+    let mut assignments = vec![usize::MAX; vectors.nrows()];
+    for _ in 0..iterations {
+        let mut changed = false;
+        for (row, assignment) in vectors.outer_iter().zip(&mut assignments) {
+            let centroid = nearest(&centroids, row);
+            changed |= centroid != *assignment;
+            *assignment = centroid;
+        }
+        if !changed {
+            break;
+        }
+
+        let mut sums = Array2::<f32>::zeros(centroids.raw_dim());
+        let mut counts = vec![0usize; k];
+        for (row, &assignment) in vectors.outer_iter().zip(&assignments) {
+            let mut sum = sums.row_mut(assignment);
+            sum += &row;
+            counts[assignment] += 1;
+        }
+        // A centroid left without vectors stays where it was
+        for ((mut centroid, sum), count) in centroids
+            .outer_iter_mut()
+            .zip(sums.outer_iter())
+            .zip(counts)
+        {
+            if count > 0 {
+                centroid.assign(&(&sum / count as f32));
+            }
+        }
+    }
+
+    centroids
+}
+
+/// The row of `centroids` closest to `vector` in Euclidean distance
+fn nearest(centroids: &Array2<f32>, vector: ArrayView1<f32>) -> usize {
+    let mut best = (0, f32::INFINITY);
+    for (index, centroid) in centroids.outer_iter().enumerate() {
+        let distance: f32 = centroid
+            .iter()
+            .zip(&vector)
+            .map(|(x1, x2)| (x1 - x2).powi(2))
+            .sum();
+        if distance < best.1 {
+            best = (index, distance);
+        }
+    }
+    best.0
+}
+
+/// The sidecar of a saved `QuantizedIndex`, the quantizer and the norms of the decoded
+/// vectors along with the ids and payloads.
+#[derive(Serialize, Deserialize)]
+struct Codes<'a, Q> {
+    records: Records<'a>,
+    squared_norms: Cow<'a, [f32]>,
+    quantizer: Q,
+}
+
+/// A vector index that keeps quantized codes instead of `f32` vectors.
+///
+/// Queries stay in full precision and are scored against the codes directly. With
+/// `with_rescoring`, the best candidates are scored again against the exact vectors, which
+/// may live in a memory mapped `FlatIndex` rather than in memory.
+///
+/// The codes are saved in the format of `FlatIndex::save`, so a process can open them
+/// without the `f32` vectors they were encoded from.
+pub struct QuantizedIndex<Q, M = Cosine> {
+    quantizer: Q,
+    metric: M,
+    ids: Vec<String>,
+    payloads: Vec<Option<Value>>,
+    codes: Vec<u8>,
+    /// The squared norm of every decoded vector, so cosines and distances can be scored
+    /// from dot products
+    squared_norms: Vec<f32>,
+    exact: Option<(FlatIndex<M>, usize)>,
+}
+
+impl<Q, M> QuantizedIndex<Q, M>
+where
+    Q: Quantizer + Sync,
+    M: Metric + Sync,
+{
+    /// It builds an empty index of vectors encoded with `quantizer` and compared with `metric`
+    #[must_use]
+    pub fn new(quantizer: Q, metric: M) -> QuantizedIndex<Q, M> {
+        QuantizedIndex {
+            quantizer,
+            metric,
+            ids: Vec::new(),
+            payloads: Vec::new(),
+            codes: Vec::new(),
+            squared_norms: Vec::new(),
+            exact: None,
+        }
+    }
+
+    /// It encodes every vector of an exact index
+    ///
+    /// Arguments:
+    ///
+    /// * `index`: The vectors, ids and payloads to encode.
+    /// * `quantizer`: A quantizer trained on vectors like those of `index`.
+    ///
+    /// Returns:
+    ///
+    /// A `QuantizedIndex` with the metric of `index`
+    pub fn from_index(index: &FlatIndex<M>, quantizer: Q) -> Result<QuantizedIndex<Q, M>>
+    where
+        M: Clone,
+    {
+        let mut quantized = QuantizedIndex::new(quantizer, index.metric().clone());
+        for ((id, vector), payload) in index
+            .ids()
+            .iter()
+            .zip(index.vectors().outer_iter())
+            .zip(index.payloads())
+        {
+            quantized.add(id.clone(), &vector.to_vec(), payload.clone())?;
+        }
+        Ok(quantized)
+    }
+
+    /// It re-scores the best `candidates` of every search against exact vectors
+    ///
+    /// Arguments:
+    ///
+    /// * `exact`: The full precision vectors, in the order of this index.
+    /// * `candidates`: The number of approximate results scored again, at least `k`.
+    ///
+    /// Returns:
+    ///
+    /// The index, or a `Dimension` error when `exact` does not hold one vector per code
+    pub fn with_rescoring(
+        mut self,
+        exact: FlatIndex<M>,
+        candidates: usize,
+    ) -> Result<QuantizedIndex<Q, M>> {
+        if exact.len() != self.len() || exact.dimension() != self.quantizer.dimension() {
+            return Err(Error::Dimension {
+                expected: self.len() * self.quantizer.dimension(),
+                found: exact.len() * exact.dimension(),
+            });
+        }
+
+        self.exact = Some((exact, candidates));
+        Ok(self)
+    }
+
+    /// It encodes a vector and appends it to the index, and to the exact vectors if any
+    ///
+    /// Arguments:
+    ///
+    /// * `id`: The id returned in search results.
+    /// * `vector`: A vector of the quantizer dimension.
+    /// * `payload`: Optional data returned along with the id.
+    pub fn add<S: Into<String>>(
+        &mut self,
+        id: S,
+        vector: &[f32],
+        payload: Option<Value>,
+    ) -> Result<()> {
+        self.check_dimension(vector)?;
+
+        let id = id.into();
+        if let Some((exact, _)) = &mut self.exact {
+            exact.add(id.clone(), vector, payload.clone())?;
+        }
+
+        let code = self.quantizer.encode(vector);
+        let decoded = self.quantizer.decode(&code);
+        self.squared_norms
+            .push(decoded.iter().map(|value| value * value).sum());
+        self.codes.extend(code);
+        self.ids.push(id);
+        self.payloads.push(payload);
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    #[must_use]
+    pub fn quantizer(&self) -> &Q {
+        &self.quantizer
+    }
+
+    /// The bytes kept in memory per vector, its code and the squared norm of its decoding
+    #[must_use]
+    pub fn bytes_per_vector(&self) -> usize {
+        self.quantizer.code_len() + std::mem::size_of::<f32>()
+    }
+
+    /// It finds approximately the `k` stored vectors closest to `query`
+    ///
+    /// Arguments:
+    ///
+    /// * `query`: A full precision vector of the quantizer dimension.
+    /// * `k`: The number of results.
+    ///
+    /// Returns:
+    ///
+    /// At most `k` results, the closest first, or a `Dimension` error when the query has
+    /// another length than the quantizer. Scores are exact when rescoring is set.
+    pub fn search(&self, query: &[f32], k: usize) -> Result<Vec<SearchResult>> {
+        self.check_dimension(query)?;
+
+        let wanted = match &self.exact {
+            Some((_, candidates)) => k.max(*candidates),
+            None => k,
+        };
+
+        let prepared = self.quantizer.prepare(query);
+        let query_norm: f32 = query.iter().map(|value| value * value).sum();
+        let mut top = TopK::new(wanted);
+        for index in 0..self.len() {
+            top.push(index, self.approximate(query, &prepared, query_norm, index));
+        }
+        let mut candidates = top.into_sorted_vec();
+
+        if let Some((exact, _)) = &self.exact {
+            let vectors = exact.vectors();
+            let mut top = TopK::new(k);
+            for candidate in candidates {
+                let row = vectors.row(candidate.index);
+                let row = row.as_slice().expect("index rows are contiguous");
+                top.push(candidate.index, exact.metric().score(query, row));
+            }
+            candidates = top.into_sorted_vec();
+        }
+
+        Ok(candidates
+            .into_iter()
+            .map(|scored| SearchResult {
+                id: self.ids[scored.index].clone(),
+                score: scored.score,
+                payload: self.payloads[scored.index].clone(),
+            })
+            .collect())
+    }
+
+    /// It runs `search` for every row of `queries` in parallel
+    pub fn search_batch(
+        &self,
+        queries: ArrayView2<f32>,
+        k: usize,
+    ) -> Result<Vec<Vec<SearchResult>>> {
+        (0..queries.nrows())
+            .into_par_iter()
+            .map(|row| self.search(&queries.index_axis(Axis(0), row).to_vec(), k))
+            .collect()
+    }
+
+    /// It writes the codes to `path`, and the ids, payloads, norms and quantizer to the
+    /// sidecar next to it
+    ///
+    /// The exact vectors of `with_rescoring` are not part of it, they are saved with their
+    /// own `FlatIndex`.
+    ///
+    /// Arguments:
+    ///
+    /// * `path`: The index file, see `sidecar_path` for the sidecar.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()>
+    where
+        Q: Serialize,
+    {
+        let codes = ArrayView2::from_shape((self.len(), self.quantizer.code_len()), &self.codes)
+            .expect("every id has one code");
+        let sidecar = Codes {
+            records: Records {
+                ids: Cow::Borrowed(&self.ids),
+                payloads: Cow::Borrowed(&self.payloads),
+            },
+            squared_norms: Cow::Borrowed(&self.squared_norms),
+            quantizer: &self.quantizer,
+        };
+        write_index(path.as_ref(), M::NAME, codes, &sidecar)
+    }
+
+    /// It opens an index written by `save`, reading its codes into memory
+    ///
+    /// Arguments:
+    ///
+    /// * `path`: The index file.
+    /// * `metric`: The metric the index was saved with.
+    ///
+    /// Returns:
+    ///
+    /// A `QuantizedIndex` without rescoring, or an `InvalidIndex` error when the file is
+    /// truncated, damaged, was saved with another metric or another kind of quantizer
+    pub fn open<P: AsRef<Path>>(path: P, metric: M) -> Result<QuantizedIndex<Q, M>>
+    where
+        Q: DeserializeOwned,
+    {
+        let path = path.as_ref();
+        let (codes, sidecar): (_, Codes<Q>) = read_codes(path, M::NAME)?;
+
+        let count = codes.nrows();
+        let consistent = sidecar.records.ids.len() == count
+            && sidecar.records.payloads.len() == count
+            && sidecar.squared_norms.len() == count
+            && sidecar.quantizer.code_len() == codes.ncols();
+        if !consistent {
+            return Err(Error::invalid_index(
+                path,
+                "the sidecar does not hold one id, payload and norm per code",
+            ));
+        }
+
+        Ok(QuantizedIndex {
+            quantizer: sidecar.quantizer,
+            metric,
+            ids: sidecar.records.ids.into_owned(),
+            payloads: sidecar.records.payloads.into_owned(),
+            codes: codes.into_raw_vec(),
+            squared_norms: sidecar.squared_norms.into_owned(),
+            exact: None,
+        })
+    }
+
+    fn check_dimension(&self, vector: &[f32]) -> Result<()> {
+        if vector.len() == self.quantizer.dimension() {
+            Ok(())
+        } else {
+            Err(Error::Dimension {
+                expected: self.quantizer.dimension(),
+                found: vector.len(),
+            })
+        }
+    }
+
+    /// The score of the vector stored at `index`, computed from its code
+    fn approximate(
+        &self,
+        query: &[f32],
+        prepared: &Q::Query,
+        query_norm: f32,
+        index: usize,
+    ) -> f32 {
+        let length = self.quantizer.code_len();
+        let code = &self.codes[index * length..(index + 1) * length];
+        let norm = self.squared_norms[index];
+
+        let cosine = |dot: f32| {
+            let norms = (query_norm * norm).sqrt();
+            if norms == 0.0 {
+                0.0
+            } else {
+                dot / norms
+            }
+        };
+
+        match M::KERNEL {
+            Kernel::Dot => self.quantizer.dot(prepared, code),
+            Kernel::Cosine => cosine(self.quantizer.dot(prepared, code)),
+            Kernel::Angular => {
+                let cosine = cosine(self.quantizer.dot(prepared, code));
+                1.0 - cosine.clamp(-1.0, 1.0).acos() / PI
+            }
+            Kernel::Euclidean => {
+                let dot = self.quantizer.dot(prepared, code);
+                -(query_norm + norm - 2.0 * dot).max(0.0).sqrt()
+            }
+            Kernel::PositivePartCosine | Kernel::Pairwise => {
+                self.metric.score(query, &self.quantizer.decode(code))
+            }
+        }
+    }
+}
+
+/// How one way of storing vectors compares with exact search over `f32` vectors.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuantizationReport {
+    pub scheme: String,
+    pub bytes_per_vector: usize,
+    /// How many times smaller than the `f32` vectors
+    pub compression: f32,
+    /// The share of the exact top `k` found by the scheme
+    pub recall: f32,
+    pub k: usize,
+}
+
+impl fmt::Display for QuantizationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<20} {:>6} bytes/vector {:>7.1}x smaller  recall@{} {:.3}",
+            self.scheme, self.bytes_per_vector, self.compression, self.k, self.recall
+        )
+    }
+}
+
+/// It measures the recall and size of int8 and product quantization against exact search
+///
+/// Arguments:
+///
+/// * `index`: The exact index, the baseline of the report.
+/// * `sample`: The vectors the quantizers are trained on.
+/// * `queries`: The queries, one per row.
+/// * `k`: The number of results compared per query.
+/// * `pq`: The parameters of product quantization.
+/// * `rescore`: The candidates re-scored exactly, for additional rows with rescoring.
+///
+/// Returns:
+///
+/// One row for the `f32` baseline and one per scheme
+pub fn quantization_report<M>(
+    index: &FlatIndex<M>,
+    sample: ArrayView2<f32>,
+    queries: ArrayView2<f32>,
+    k: usize,
+    pq: PqParams,
+    rescore: Option<usize>,
+) -> Result<Vec<QuantizationReport>>
+where
+    M: Metric + Sync + Clone,
+{
+    let expected = index.search_batch(queries, k)?;
+    let baseline = index.dimension() * std::mem::size_of::<f32>();
+    let row = |scheme: String, bytes_per_vector: usize, results: &[Vec<SearchResult>]| {
+        QuantizationReport {
+            scheme,
+            bytes_per_vector,
+            compression: baseline as f32 / bytes_per_vector as f32,
+            recall: recall(results, &expected),
+            k,
+        }
+    };
+
+    let mut report = vec![row("f32".to_string(), baseline, &expected)];
+    report.extend(compare(
+        index,
+        ScalarQuantizer::train(sample)?,
+        queries,
+        k,
+        rescore,
+        &row,
+    )?);
+    report.extend(compare(
+        index,
+        ProductQuantizer::train(sample, pq)?,
+        queries,
+        k,
+        rescore,
+        &row,
+    )?);
+    Ok(report)
+}
+
+/// The report rows of one quantizer, without and with rescoring
+fn compare<Q, M, R>(
+    index: &FlatIndex<M>,
+    quantizer: Q,
+    queries: ArrayView2<f32>,
+    k: usize,
+    rescore: Option<usize>,
+    row: &R,
+) -> Result<Vec<QuantizationReport>>
+where
+    Q: Quantizer + Sync,
+    M: Metric + Sync + Clone,
+    R: Fn(String, usize, &[Vec<SearchResult>]) -> QuantizationReport,
+{
+    let name = quantizer.name();
+    let quantized = QuantizedIndex::from_index(index, quantizer)?;
+    let mut rows = vec![row(
+        name.clone(),
+        quantized.bytes_per_vector(),
+        &quantized.search_batch(queries, k)?,
+    )];
+
+    if let Some(candidates) = rescore {
+        let bytes_per_vector = quantized.bytes_per_vector();
+        let rescored = quantized.with_rescoring(index.clone(), candidates)?;
+        rows.push(row(
+            format!("{name} + rescore {candidates}"),
+            bytes_per_vector,
+            &rescored.search_batch(queries, k)?,
+        ));
+    }
+    Ok(rows)
+}
+
+/// The share of the expected results that were found, over every query
+///
+/// Arguments:
+///
+/// * `results`: The results of an approximate search, per query.
+/// * `expected`: The results of an exact search, per query.
+///
+/// Returns:
+///
+/// A recall in `[0, 1]`, `1.0` when nothing was expected
+#[must_use]
+pub fn recall(results: &[Vec<SearchResult>], expected: &[Vec<SearchResult>]) -> f32 {
+    let total: usize = expected.iter().map(Vec::len).sum();
+    if total == 0 {
+        return 1.0;
+    }
+
+    let found: usize = results
+        .iter()
+        .zip(expected)
+        .map(|(results, expected)| {
+            results
+                .iter()
+                .filter(|result| expected.iter().any(|wanted| wanted.id == result.id))
+                .count()
+        })
+        .sum();
+    found as f32 / total as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::array;
+
+    use super::*;
+    use crate::utilities::retrieval::{random_vectors as generate, DotProduct, Euclidean};
+
+    fn flat<M: Metric + Sync>(vectors: &Array2<f32>, metric: M) -> FlatIndex<M> {
+        let ids = (0..vectors.nrows()).map(|row| row.to_string()).collect();
+        FlatIndex::from_vectors(ids, vectors.clone(), metric).unwrap()
+    }
+
+    #[test]
+    fn test_scalar_quantizer() {
+        let sample = array![[0.0, -1.0, 5.0], [1.0, 1.0, 5.0]];
+        let quantizer = ScalarQuantizer::train(sample.view()).unwrap();
+
+        let code = quantizer.encode(&[0.6, 1.0, 5.0]);
+        assert_eq!(code, [153, 255, 0]);
+        let decoded = quantizer.decode(&code);
+        assert!((decoded[0] - 0.6).abs() < 0.01 && decoded[1] == 1.0 && decoded[2] == 5.0);
+
+        // Values out of the sample range are clamped
+        assert_eq!(quantizer.encode(&[2.0, -3.0, 7.0]), [255, 0, 0]);
+
+        assert!(matches!(
+            ScalarQuantizer::train(Array2::zeros((0, 3)).view()),
+            Err(Error::Quantizer { .. })
+        ));
+
+        let query = [1.0, 2.0, 3.0];
+        let dot = quantizer.dot(&quantizer.prepare(&query), &code);
+        let expected: f32 = query.iter().zip(&decoded).map(|(x1, x2)| x1 * x2).sum();
+        assert!((dot - expected).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_product_quantizer() {
+        // Two well separated clusters per subspace are learned exactly
+        let sample = array![
+            [0.0, 0.0, 10.0, 10.0],
+            [0.1, 0.0, 10.0, 10.1],
+            [5.0, 5.0, -3.0, -3.0],
+            [5.0, 5.1, -3.1, -3.0]
+        ];
+        let params = PqParams {
+            subspaces: 2,
+            centroids: 2,
+            ..PqParams::default()
+        };
+        let quantizer = ProductQuantizer::train(sample.view(), params).unwrap();
+        assert_eq!(quantizer.name(), "pq-2x2");
+
+        let code = quantizer.encode(&[5.0, 5.0, 10.0, 10.0]);
+        let decoded = quantizer.decode(&code);
+        assert!((decoded[0] - 5.0).abs() < 0.1 && (decoded[3] - 10.0).abs() < 0.1);
+
+        let query = [1.0, -1.0, 0.5, 2.0];
+        let dot = quantizer.dot(&quantizer.prepare(&query), &code);
+        let expected: f32 = query.iter().zip(&decoded).map(|(x1, x2)| x1 * x2).sum();
+        assert!((dot - expected).abs() < 1e-4);
+
+        let uneven = PqParams {
+            subspaces: 3,
+            ..params
+        };
+        assert!(matches!(
+            ProductQuantizer::train(sample.view(), uneven),
+            Err(Error::Quantizer { .. })
+        ));
+    }
+
+    #[test]
+    fn test_asymmetric_scores_match_decoded_vectors() {
+        let vectors = generate(50, 8, 1);
+        let quantizer = ScalarQuantizer::train(vectors.view()).unwrap();
+        let query = generate(1, 8, 2).row(0).to_vec();
+
+        fn check<M: Metric + Sync + Clone>(
+            vectors: &Array2<f32>,
+            quantizer: &ScalarQuantizer,
+            query: &[f32],
+            metric: M,
+        ) {
+            let index =
+                QuantizedIndex::from_index(&flat(vectors, metric), quantizer.clone()).unwrap();
+            for result in index.search(query, 5).unwrap() {
+                let row: usize = result.id.parse().unwrap();
+                let decoded = quantizer.decode(&quantizer.encode(&vectors.row(row).to_vec()));
+                let expected = index.metric.score(query, &decoded);
+                assert!((result.score - expected).abs() < 1e-3, "{}", M::NAME);
+            }
+        }
+        check(&vectors, &quantizer, &query, Cosine);
+        check(&vectors, &quantizer, &query, DotProduct);
+        check(&vectors, &quantizer, &query, Euclidean);
+    }
+
+    #[test]
+    fn test_query_dimension_is_checked() {
+        let vectors = generate(20, 8, 5);
+        let index = flat(&vectors, Cosine);
+        let params = PqParams {
+            subspaces: 4,
+            centroids: 4,
+            ..PqParams::default()
+        };
+
+        let scalar =
+            QuantizedIndex::from_index(&index, ScalarQuantizer::train(vectors.view()).unwrap())
+                .unwrap();
+        let product = QuantizedIndex::from_index(
+            &index,
+            ProductQuantizer::train(vectors.view(), params).unwrap(),
+        )
+        .unwrap();
+
+        for found in [3, 9] {
+            let query = vec![1.0; found];
+            let dimension = |result| matches!(result, Err(Error::Dimension { expected: 8, found: f }) if f == found);
+            assert!(dimension(scalar.search(&query, 3).map(|_| ())));
+            assert!(dimension(product.search(&query, 3).map(|_| ())));
+        }
+        assert!(product
+            .search_batch(Array2::zeros((2, 5)).view(), 3)
+            .is_err());
+    }
+
+    #[test]
+    fn test_save_and_open() {
+        let vectors = generate(100, 8, 6);
+        let queries = generate(5, 8, 7);
+        let index = flat(&vectors, Euclidean);
+        let params = PqParams {
+            subspaces: 4,
+            centroids: 16,
+            ..PqParams::default()
+        };
+
+        let path = std::env::temp_dir().join("sandbox-rust-int8.index");
+        let scalar =
+            QuantizedIndex::from_index(&index, ScalarQuantizer::train(vectors.view()).unwrap())
+                .unwrap();
+        scalar.save(&path).unwrap();
+        let opened: QuantizedIndex<ScalarQuantizer, _> =
+            QuantizedIndex::open(&path, Euclidean).unwrap();
+        assert_eq!(opened.quantizer(), scalar.quantizer());
+        assert_eq!(
+            opened.search_batch(queries.view(), 5).unwrap(),
+            scalar.search_batch(queries.view(), 5).unwrap()
+        );
+        // The codes of another quantizer or metric are rejected
+        assert!(matches!(
+            QuantizedIndex::<ProductQuantizer, _>::open(&path, Euclidean),
+            Err(Error::InvalidIndex { .. })
+        ));
+        assert!(matches!(
+            QuantizedIndex::<ScalarQuantizer, _>::open(&path, Cosine),
+            Err(Error::InvalidIndex { .. })
+        ));
+
+        let path = std::env::temp_dir().join("sandbox-rust-pq.index");
+        let product = QuantizedIndex::from_index(
+            &index,
+            ProductQuantizer::train(vectors.view(), params).unwrap(),
+        )
+        .unwrap();
+        product.save(&path).unwrap();
+        let opened: QuantizedIndex<ProductQuantizer, _> =
+            QuantizedIndex::open(&path, Euclidean).unwrap();
+        assert_eq!(opened.quantizer(), product.quantizer());
+        assert_eq!(
+            opened.search_batch(queries.view(), 5).unwrap(),
+            product.search_batch(queries.view(), 5).unwrap()
+        );
+
+        // Rescoring works over the reopened codes
+        let rescored = opened.with_rescoring(index.clone(), 20).unwrap();
+        assert_eq!(
+            rescored
+                .search(queries.row(0).as_slice().unwrap(), 1)
+                .unwrap()[0]
+                .id,
+            index.search(queries.row(0).as_slice().unwrap(), 1).unwrap()[0].id
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let vectors = generate(1000, 16, 3);
+        let queries = generate(20, 16, 4);
+        let index = flat(&vectors, Euclidean);
+        let pq = PqParams {
+            subspaces: 4,
+            centroids: 64,
+            ..PqParams::default()
+        };
+
+        let report =
+            quantization_report(&index, vectors.view(), queries.view(), 10, pq, Some(50)).unwrap();
+        let schemes: Vec<&str> = report.iter().map(|row| row.scheme.as_str()).collect();
+        assert_eq!(
+            schemes,
+            [
+                "f32",
+                "int8",
+                "int8 + rescore 50",
+                "pq-4x64",
+                "pq-4x64 + rescore 50"
+            ]
+        );
+
+        assert_eq!((report[0].bytes_per_vector, report[0].recall), (64, 1.0));
+        assert_eq!(report[1].bytes_per_vector, 20);
+        assert!(report[1].recall >= 0.9);
+        assert!(report[2].recall >= report[1].recall);
+        assert_eq!(report[3].bytes_per_vector, 8);
+        assert!(report[3].compression == 8.0);
+        assert!(report[4].recall > report[3].recall && report[4].recall >= 0.9);
+    }
+}
@@ -0,0 +1,516 @@
+use std::borrow::Cow;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::mem::align_of;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use memmap2::Mmap;
+use ndarray::{Array2, ArrayView1, ArrayView2};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{Error, Result};
+
+/// The version of the vector index files written by this crate
+pub const FORMAT_VERSION: u32 = 1;
+
+const MAGIC: &[u8; 8] = b"SBXINDEX";
+/// The dtype tag of little endian `f32` vectors
+const DTYPE_F32: u32 = 1;
+/// The dtype tag of byte codes, such as those of a `Quantizer`
+const DTYPE_U8: u32 = 2;
+const METRIC_LEN: usize = 24;
+/// The header is padded so the vector block after it stays aligned for `f32`
+const HEADER_LEN: usize = 64;
+
+/// It names the sidecar holding the ids and metadata of the index file at `path`
+///
+/// Arguments:
+///
+/// * `path`: The index file.
+///
+/// Returns:
+///
+/// `path` with `.json` appended, `corpus.index` being described by `corpus.index.json`
+#[must_use]
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".json");
+    PathBuf::from(name)
+}
+
+/// The element types of the block of an index file.
+pub(crate) trait Element: Copy {
+    const DTYPE: u32;
+    const SIZE: usize;
+
+    /// It appends the little endian bytes of the element
+    fn write_le(self, bytes: &mut Vec<u8>);
+}
+
+impl Element for f32 {
+    const DTYPE: u32 = DTYPE_F32;
+    const SIZE: usize = 4;
+
+    fn write_le(self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Element for u8 {
+    const DTYPE: u32 = DTYPE_U8;
+    const SIZE: usize = 1;
+
+    fn write_le(self, bytes: &mut Vec<u8>) {
+        bytes.push(self);
+    }
+}
+
+/// The ids and payloads of the stored vectors, one per row.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Records<'a> {
+    pub ids: Cow<'a, [String]>,
+    pub payloads: Cow<'a, [Option<Value>]>,
+}
+
+/// The vectors of an index, either owned or mapped from an index file.
+#[derive(Debug, Clone)]
+pub(crate) enum Vectors {
+    Owned(Array2<f32>),
+    Mapped(MappedVectors),
+}
+
+impl Vectors {
+    pub fn view(&self) -> ArrayView2<'_, f32> {
+        match self {
+            Vectors::Owned(vectors) => vectors.view(),
+            Vectors::Mapped(vectors) => vectors.view(),
+        }
+    }
+
+    /// The vectors to modify, copying a mapped block out of its file the first time
+    pub fn to_mut(&mut self) -> &mut Array2<f32> {
+        if let Vectors::Mapped(vectors) = self {
+            *self = Vectors::Owned(vectors.view().to_owned());
+        }
+        match self {
+            Vectors::Owned(vectors) => vectors,
+            Vectors::Mapped(_) => unreachable!("mapped vectors were just copied"),
+        }
+    }
+
+    /// It checks mapped vectors against the checksum of their file, owned vectors always pass
+    pub fn verify(&self) -> Result<()> {
+        match self {
+            Vectors::Owned(_) => Ok(()),
+            Vectors::Mapped(vectors) => vectors.verify(),
+        }
+    }
+}
+
+/// The vector block of an index file, read in place from the memory map.
+#[derive(Debug, Clone)]
+pub(crate) struct MappedVectors {
+    map: Arc<Mmap>,
+    path: PathBuf,
+    rows: usize,
+    columns: usize,
+    checksum: u32,
+}
+
+impl MappedVectors {
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    fn view(&self) -> ArrayView2<'_, f32> {
+        let length = self.rows * self.columns;
+        let block = &self.map[HEADER_LEN..];
+        // SAFETY: `read_index` checked that the block holds `length` little endian `f32`,
+        // is aligned for them and that the host is little endian too
+        let values = unsafe { std::slice::from_raw_parts(block.as_ptr().cast::<f32>(), length) };
+        ArrayView2::from_shape((self.rows, self.columns), values)
+            .expect("the header gives the shape of the block")
+    }
+
+    /// It reads the whole vector block to compare it with the checksum of the header
+    fn verify(&self) -> Result<()> {
+        if crc32fast::hash(&self.map[HEADER_LEN..]) == self.checksum {
+            Ok(())
+        } else {
+            Err(Error::invalid_index(
+                &self.path,
+                "the vectors do not match their checksum",
+            ))
+        }
+    }
+}
+
+/// The fixed size header at the start of an index file, all integers little endian.
+///
+/// | bytes  | field                               |
+/// |--------|-------------------------------------|
+/// | 0..8   | magic `SBXINDEX`                    |
+/// | 8..12  | format version                      |
+/// | 12..16 | dtype, 1 for `f32`, 2 for `u8`      |
+/// | 16..24 | dimension, the elements of a row    |
+/// | 24..32 | count, the number of rows           |
+/// | 32..36 | CRC-32 of the vector block          |
+/// | 36..40 | CRC-32 of the sidecar               |
+/// | 40..64 | metric name, zero padded            |
+#[derive(Debug)]
+struct Header {
+    version: u32,
+    dtype: u32,
+    dimension: usize,
+    count: usize,
+    vectors_checksum: u32,
+    sidecar_checksum: u32,
+    metric: String,
+}
+
+impl Header {
+    fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0; HEADER_LEN];
+        bytes[0..8].copy_from_slice(MAGIC);
+        bytes[8..12].copy_from_slice(&self.version.to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.dtype.to_le_bytes());
+        bytes[16..24].copy_from_slice(&(self.dimension as u64).to_le_bytes());
+        bytes[24..32].copy_from_slice(&(self.count as u64).to_le_bytes());
+        bytes[32..36].copy_from_slice(&self.vectors_checksum.to_le_bytes());
+        bytes[36..40].copy_from_slice(&self.sidecar_checksum.to_le_bytes());
+        bytes[40..40 + self.metric.len()].copy_from_slice(self.metric.as_bytes());
+        bytes
+    }
+
+    fn parse(path: &Path, bytes: &[u8]) -> Result<Header> {
+        if bytes.len() < HEADER_LEN || &bytes[0..8] != MAGIC {
+            return Err(Error::invalid_index(
+                path,
+                "it does not start with an index header",
+            ));
+        }
+
+        let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        let u64_at = |at: usize| u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap());
+        let usize_at = |at: usize| {
+            usize::try_from(u64_at(at))
+                .map_err(|_| Error::invalid_index(path, "it is too large for this platform"))
+        };
+
+        let metric = &bytes[40..HEADER_LEN];
+        let metric_len = metric
+            .iter()
+            .position(|&byte| byte == 0)
+            .unwrap_or(METRIC_LEN);
+        let metric = std::str::from_utf8(&metric[..metric_len])
+            .map_err(|_| Error::invalid_index(path, "the metric name is not UTF-8"))?;
+
+        Ok(Header {
+            version: u32_at(8),
+            dtype: u32_at(12),
+            dimension: usize_at(16)?,
+            count: usize_at(24)?,
+            vectors_checksum: u32_at(32),
+            sidecar_checksum: u32_at(36),
+            metric: metric.to_string(),
+        })
+    }
+}
+
+/// It writes vectors to an index file, and their ids and metadata to its sidecar
+///
+/// Both files are written next to their destination first and renamed into place, so an
+/// index mapped from `path` keeps reading the previous file, even when it is the one saved.
+///
+/// Arguments:
+///
+/// * `path`: The index file, its sidecar is written next to it.
+/// * `metric`: The name of the metric the vectors are compared with.
+/// * `vectors`: The vectors, or the codes standing for them, one per row.
+/// * `sidecar`: The ids, metadata and anything else the index type needs to be rebuilt.
+pub(crate) fn write_index<A: Element, S: Serialize>(
+    path: &Path,
+    metric: &str,
+    vectors: ArrayView2<A>,
+    sidecar: &S,
+) -> Result<()> {
+    assert!(metric.len() <= METRIC_LEN, "metric names fit the header");
+
+    let sidecar = serde_json::to_vec(sidecar).expect("the sidecar serializes to JSON");
+
+    let row_bytes = |row: ArrayView1<A>| -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(row.len() * A::SIZE);
+        for &value in row {
+            value.write_le(&mut bytes);
+        }
+        bytes
+    };
+    let mut checksum = crc32fast::Hasher::new();
+    for row in vectors.outer_iter() {
+        checksum.update(&row_bytes(row));
+    }
+
+    let header = Header {
+        version: FORMAT_VERSION,
+        dtype: A::DTYPE,
+        dimension: vectors.ncols(),
+        count: vectors.nrows(),
+        vectors_checksum: checksum.finalize(),
+        sidecar_checksum: crc32fast::hash(&sidecar),
+        metric: metric.to_string(),
+    };
+
+    let sidecar_path = sidecar_path(path);
+    replace_file(&sidecar_path, |writer| writer.write_all(&sidecar))?;
+    replace_file(path, |writer| {
+        writer.write_all(&header.to_bytes())?;
+        for row in vectors.outer_iter() {
+            writer.write_all(&row_bytes(row))?;
+        }
+        Ok(())
+    })
+}
+
+/// It writes a file under a temporary name in its directory and renames it over `path`
+///
+/// A reader that already opened or mapped `path` keeps the old file, which stays alive
+/// until its last handle is dropped.
+pub(crate) fn replace_file<F>(path: &Path, write: F) -> Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> std::io::Result<()>,
+{
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(".{}.tmp", std::process::id()));
+    let temporary = PathBuf::from(name);
+
+    let written = File::create(&temporary).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        write(&mut writer)?;
+        writer
+            .into_inner()
+            .map_err(|error| error.into_error())?
+            .sync_all()
+    });
+    if let Err(source) = written {
+        let _ = fs::remove_file(&temporary);
+        return Err(Error::io(&temporary)(source));
+    }
+    fs::rename(&temporary, path).map_err(|source| {
+        let _ = fs::remove_file(&temporary);
+        Error::io(path)(source)
+    })
+}
+
+/// It maps the vectors of an index file and reads its sidecar
+///
+/// The vectors are neither read nor copied, they stay in the file until a search touches
+/// them. Their length is checked against the header, their checksum only by `verify`, which
+/// reads them all.
+///
+/// Arguments:
+///
+/// * `path`: The index file.
+/// * `metric`: The name of the metric the caller compares vectors with.
+///
+/// Returns:
+///
+/// The mapped vectors and the decoded sidecar
+pub(crate) fn read_index<S: DeserializeOwned>(
+    path: &Path,
+    metric: &str,
+) -> Result<(MappedVectors, S)> {
+    let file = File::open(path).map_err(Error::io(path))?;
+    // SAFETY: the map is only read, and `write_index` renames new files over index files
+    // instead of modifying them in place, so the mapped file never changes
+    let map = unsafe { Mmap::map(&file) }.map_err(Error::io(path))?;
+    let header = check_header::<f32>(path, &map, metric)?;
+
+    if cfg!(target_endian = "big")
+        || map[HEADER_LEN..].as_ptr().align_offset(align_of::<f32>()) != 0
+    {
+        return Err(Error::invalid_index(
+            path,
+            "the vectors cannot be mapped on this platform",
+        ));
+    }
+
+    let sidecar = read_sidecar(path, &header)?;
+    let vectors = MappedVectors {
+        map: Arc::new(map),
+        path: path.to_path_buf(),
+        rows: header.count,
+        columns: header.dimension,
+        checksum: header.vectors_checksum,
+    };
+    Ok((vectors, sidecar))
+}
+
+/// It reads the byte codes of an index file into memory, along with its sidecar
+///
+/// Unlike `read_index`, the codes are copied out of the file, so their checksum is
+/// checked on the way.
+///
+/// Arguments:
+///
+/// * `path`: The index file.
+/// * `metric`: The name of the metric the caller compares vectors with.
+///
+/// Returns:
+///
+/// The codes, one row per stored vector, and the decoded sidecar
+pub(crate) fn read_codes<S: DeserializeOwned>(
+    path: &Path,
+    metric: &str,
+) -> Result<(Array2<u8>, S)> {
+    let bytes = fs::read(path).map_err(Error::io(path))?;
+    let header = check_header::<u8>(path, &bytes, metric)?;
+
+    if crc32fast::hash(&bytes[HEADER_LEN..]) != header.vectors_checksum {
+        return Err(Error::invalid_index(
+            path,
+            "the codes do not match their checksum",
+        ));
+    }
+
+    let sidecar = read_sidecar(path, &header)?;
+    let codes = Array2::from_shape_vec(
+        (header.count, header.dimension),
+        bytes[HEADER_LEN..].to_vec(),
+    )
+    .expect("the length of the file was checked against the header");
+    Ok((codes, sidecar))
+}
+
+/// It parses the header of an index file and checks it describes the rest of the file
+fn check_header<A: Element>(path: &Path, bytes: &[u8], metric: &str) -> Result<Header> {
+    let header = Header::parse(path, bytes)?;
+
+    if header.version != FORMAT_VERSION {
+        return Err(Error::invalid_index(
+            path,
+            &format!(
+                "format version {} is not supported, expected {FORMAT_VERSION}",
+                header.version
+            ),
+        ));
+    }
+    if header.dtype != A::DTYPE {
+        return Err(Error::invalid_index(
+            path,
+            &format!(
+                "dtype {} is not supported, expected {}",
+                header.dtype,
+                A::DTYPE
+            ),
+        ));
+    }
+    if header.metric != metric {
+        return Err(Error::invalid_index(
+            path,
+            &format!(
+                "it was built for the `{}` metric, not `{metric}`",
+                header.metric
+            ),
+        ));
+    }
+
+    let length = header
+        .count
+        .checked_mul(header.dimension)
+        .and_then(|values| values.checked_mul(A::SIZE))
+        .and_then(|bytes| bytes.checked_add(HEADER_LEN));
+    if length != Some(bytes.len()) {
+        return Err(Error::invalid_index(
+            path,
+            &format!(
+                "it is {} bytes long, the header describes {} vectors of dimension {}",
+                bytes.len(),
+                header.count,
+                header.dimension
+            ),
+        ));
+    }
+
+    Ok(header)
+}
+
+/// It reads the sidecar of an index file, checked against the checksum of its header
+fn read_sidecar<S: DeserializeOwned>(path: &Path, header: &Header) -> Result<S> {
+    let sidecar_path = sidecar_path(path);
+    let sidecar = fs::read(&sidecar_path).map_err(Error::io(&sidecar_path))?;
+    if crc32fast::hash(&sidecar) != header.sidecar_checksum {
+        return Err(Error::invalid_index(
+            &sidecar_path,
+            "the ids and metadata do not match their checksum",
+        ));
+    }
+    serde_json::from_slice(&sidecar)
+        .map_err(|source| Error::invalid_index(&sidecar_path, &source.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::array;
+
+    use super::*;
+
+    fn records() -> Records<'static> {
+        Records {
+            ids: Cow::Owned(vec!["a".into(), "b".into()]),
+            payloads: Cow::Owned(vec![None, Some(Value::from(1))]),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let path = std::env::temp_dir().join("sandbox-rust-storage-round-trip.index");
+        // A transposed view is written row by row like any other
+        let vectors = array![[1.0, 3.0], [2.0, 4.0]].reversed_axes();
+        write_index(&path, "cosine", vectors.view(), &records()).unwrap();
+
+        let (mapped, read): (MappedVectors, Records) = read_index(&path, "cosine").unwrap();
+        assert_eq!(mapped.view(), array![[1.0, 2.0], [3.0, 4.0]]);
+        assert!(mapped.verify().is_ok());
+        assert_eq!(read.ids, records().ids);
+        assert_eq!(read.payloads, records().payloads);
+
+        assert!(matches!(
+            read_index::<Records>(&path, "dot"),
+            Err(Error::InvalidIndex { .. })
+        ));
+    }
+
+    #[test]
+    fn test_damaged_files_are_detected() {
+        let path = std::env::temp_dir().join("sandbox-rust-storage-damaged.index");
+        let vectors = array![[1.0, 2.0, 3.0], [4.0, 5.0, 6.0]];
+        write_index(&path, "euclidean", vectors.view(), &records()).unwrap();
+        let bytes = fs::read(&path).unwrap();
+
+        let damaged = |bytes: &[u8]| {
+            fs::write(&path, bytes).unwrap();
+            read_index::<Records>(&path, "euclidean")
+                .unwrap_err()
+                .to_string()
+        };
+
+        assert!(damaged(&bytes[..bytes.len() - 4]).contains("bytes long"));
+
+        // A damaged vector block opens, the checksum is only read on request
+        let mut flipped = bytes.clone();
+        flipped[HEADER_LEN] ^= 1;
+        fs::write(&path, &flipped).unwrap();
+        let (mapped, _): (MappedVectors, Records) = read_index(&path, "euclidean").unwrap();
+        let error = Vectors::Mapped(mapped).verify().unwrap_err();
+        assert!(error.to_string().contains("checksum"));
+
+        assert!(damaged(&bytes[..10]).contains("index header"));
+
+        fs::write(sidecar_path(&path), b"{\"ids\": []}").unwrap();
+        assert!(damaged(&bytes).contains("checksum"));
+    }
+}
@@ -0,0 +1,98 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+/// A candidate kept by `TopK`, ordered by score.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Scored {
+    pub score: f32,
+    pub index: usize,
+}
+
+impl PartialEq for Scored {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Scored {}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Lower indices win ties, so results are stable across runs
+        self.score
+            .total_cmp(&other.score)
+            .then_with(|| other.index.cmp(&self.index))
+    }
+}
+
+/// The `k` highest scores seen so far, kept in a min-heap of at most `k` entries.
+pub(crate) struct TopK {
+    k: usize,
+    heap: BinaryHeap<Reverse<Scored>>,
+}
+
+impl TopK {
+    pub fn new(k: usize) -> TopK {
+        TopK {
+            k,
+            heap: BinaryHeap::with_capacity(k + 1),
+        }
+    }
+
+    /// It offers a candidate, dropping the lowest score once more than `k` are kept.
+    /// NaN scores are never kept.
+    pub fn push(&mut self, index: usize, score: f32) {
+        if self.k == 0 || score.is_nan() {
+            return;
+        }
+        if self.heap.len() == self.k {
+            match self.heap.peek() {
+                Some(Reverse(lowest)) if (Scored { score, index }) <= *lowest => return,
+                _ => {
+                    self.heap.pop();
+                }
+            }
+        }
+        self.heap.push(Reverse(Scored { score, index }));
+    }
+
+    /// The kept candidates, highest score first
+    pub fn into_sorted_vec(self) -> Vec<Scored> {
+        // Sorting `Reverse` ascending puts the highest score first
+        self.heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(scored)| scored)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_top_k() {
+        let mut top = TopK::new(3);
+        for (index, score) in [0.1, 0.9, f32::NAN, 0.5, 0.9, -1.0, 0.7]
+            .into_iter()
+            .enumerate()
+        {
+            top.push(index, score);
+        }
+
+        let kept: Vec<(usize, f32)> = top
+            .into_sorted_vec()
+            .iter()
+            .map(|scored| (scored.index, scored.score))
+            .collect();
+        assert_eq!(kept, [(1, 0.9), (4, 0.9), (6, 0.7)]);
+        assert!(TopK::new(0).into_sorted_vec().is_empty());
+    }
+}
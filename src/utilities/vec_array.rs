@@ -1,78 +1,698 @@
-use ndarray::{ArrayBase, Dim, IxDynImpl, OwnedRepr};
+use std::borrow::Cow;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 
-/// It takes a 2D array and returns a vector of vectors of floats
+use ndarray::{
+    ArrayBase, ArrayD, ArrayView, ArrayView1, ArrayView2, ArrayView3, Axis, Data, Dimension, Ix2,
+    Ix3, IxDyn, ShapeBuilder,
+};
+
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::{Error, Result};
+
+/// It borrows an array as a view of a fixed rank, typically an `ArrayD` returned by a model
 ///
 /// Arguments:
 ///
-/// * `arr`: &ArrayBase<OwnedRepr<f32>, Dim<IxDynImpl>>
+/// * `arr`: An array of any element type, rank and memory layout.
 ///
 /// Returns:
 ///
-/// A vector of vectors of f32s.
-pub fn array2_to_vec(arr: &ArrayBase<OwnedRepr<f32>, Dim<IxDynImpl>>) -> Vec<Vec<f32>> {
-    let rows = arr
-        .to_owned()
-        .into_raw_vec()
-        .chunks(arr.shape()[1])
-        .map(<[f32]>::to_vec)
-        .collect::<Vec<_>>();
-    rows
+/// A view with the rank of `E`, or a `Rank` error naming the actual shape
+pub fn view_as<A, S, D, E>(arr: &ArrayBase<S, D>) -> Result<ArrayView<'_, A, E>>
+where
+    S: Data<Elem = A>,
+    D: Dimension,
+    E: Dimension,
+{
+    arr.view()
+        .into_dimensionality::<E>()
+        .map_err(|_| Error::Rank {
+            expected: E::NDIM.unwrap_or(arr.ndim()),
+            found: arr.shape().to_vec(),
+        })
+}
+
+/// It borrows an array as a 2D view, shaped `(rows, columns)`
+pub fn view2<A, S, D>(arr: &ArrayBase<S, D>) -> Result<ArrayView2<'_, A>>
+where
+    S: Data<Elem = A>,
+    D: Dimension,
+{
+    view_as::<A, S, D, Ix2>(arr)
 }
 
-/// It takes a 3D array and returns a vector of vectors of vectors of floats
+/// It borrows an array as a 3D view, shaped `(sequences, tokens, values)`
+pub fn view3<A, S, D>(arr: &ArrayBase<S, D>) -> Result<ArrayView3<'_, A>>
+where
+    S: Data<Elem = A>,
+    D: Dimension,
+{
+    view_as::<A, S, D, Ix3>(arr)
+}
+
+/// It iterates over the rows of a 2D view as slices
+///
+/// Rows that are contiguous in memory are borrowed, only rows of transposed or strided
+/// views are copied.
 ///
 /// Arguments:
 ///
-/// * `arr`: &ArrayBase<OwnedRepr<f32>, Dim<IxDynImpl>>
+/// * `view`: The rows, of any memory layout.
 ///
 /// Returns:
 ///
-/// A vector of vectors of f32s.
+/// One slice per row, in order
+pub fn rows<'a, A: Clone>(view: ArrayView2<'a, A>) -> impl ExactSizeIterator<Item = Cow<'a, [A]>> {
+    (0..view.nrows()).map(move |row| row_slice(view.index_axis_move(Axis(0), row)))
+}
+
+/// It iterates over the tokens of every sequence of a 3D view, as slices
+///
+/// Arguments:
+///
+/// * `view`: The values of every token, shaped `(sequences, tokens, values)`.
+///
+/// Returns:
+///
+/// One iterator per sequence, over one slice per token
+pub fn tokens<'a, A: Clone>(
+    view: ArrayView3<'a, A>,
+) -> impl ExactSizeIterator<Item = impl ExactSizeIterator<Item = Cow<'a, [A]>>> {
+    (0..view.len_of(Axis(0))).map(move |sequence| rows(view.index_axis_move(Axis(0), sequence)))
+}
+
+fn row_slice<A: Clone>(row: ArrayView1<'_, A>) -> Cow<'_, [A]> {
+    match row.to_slice() {
+        Some(slice) => Cow::Borrowed(slice),
+        None => Cow::Owned(row.to_vec()),
+    }
+}
+
+/// It takes a 2D array and returns a vector of its rows
+///
+/// Arguments:
+///
+/// * `arr`: A 2D array of any memory layout, `view2` checks the rank of dynamic arrays.
+///
+/// Returns:
+///
+/// A vector of vectors, one per row in logical order.
+pub fn array2_to_vec<A, S>(arr: &ArrayBase<S, Ix2>) -> Vec<Vec<A>>
+where
+    A: Clone,
+    S: Data<Elem = A>,
+{
+    rows(arr.view()).map(Cow::into_owned).collect()
+}
+
+/// It takes a 3D array and returns a vector of the token rows of every sequence
+///
+/// Arguments:
+///
+/// * `arr`: A 3D array of any memory layout, `view3` checks the rank of dynamic arrays.
+///
+/// Returns:
+///
+/// A vector of vectors of vectors, in logical order.
 #[must_use]
-pub fn array3_to_vec(arr: &ArrayBase<OwnedRepr<f32>, Dim<IxDynImpl>>) -> Vec<Vec<Vec<f32>>> {
-    dbg!(arr);
-
-    let rows = arr
-        .to_owned()
-        .into_raw_vec()
-        .chunks(arr.shape()[1] * arr.shape()[2])
-        .map(|chunk1| {
-            chunk1
-                .chunks(arr.shape()[2])
-                .map(<[f32]>::to_vec)
+pub fn array3_to_vec<A, S>(arr: &ArrayBase<S, Ix3>) -> Vec<Vec<Vec<A>>>
+where
+    A: Clone,
+    S: Data<Elem = A>,
+{
+    tokens(arr.view())
+        .map(|sequence| sequence.map(Cow::into_owned).collect())
+        .collect()
+}
+
+/// The magic string every `.npy` file starts with
+const NPY_MAGIC: &[u8] = b"\x93NUMPY";
+
+/// An element type that can be stored in `.npy` files.
+pub trait NpyElement: Copy {
+    /// The type code in the `descr` of a `.npy` header, without its byte order
+    const TYPE_CODE: &'static str;
+    const SIZE: usize;
+
+    fn from_bytes(bytes: &[u8], little_endian: bool) -> Self;
+
+    fn write_le(self, out: &mut Vec<u8>);
+}
+
+impl NpyElement for f32 {
+    const TYPE_CODE: &'static str = "f4";
+    const SIZE: usize = 4;
+
+    fn from_bytes(bytes: &[u8], little_endian: bool) -> Self {
+        let bytes = bytes.try_into().expect("an f32 is 4 bytes");
+        if little_endian {
+            f32::from_le_bytes(bytes)
+        } else {
+            f32::from_be_bytes(bytes)
+        }
+    }
+
+    fn write_le(self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl NpyElement for i64 {
+    const TYPE_CODE: &'static str = "i8";
+    const SIZE: usize = 8;
+
+    fn from_bytes(bytes: &[u8], little_endian: bool) -> Self {
+        let bytes = bytes.try_into().expect("an i64 is 8 bytes");
+        if little_endian {
+            i64::from_le_bytes(bytes)
+        } else {
+            i64::from_be_bytes(bytes)
+        }
+    }
+
+    fn write_le(self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+/// It writes an array to a `.npy` file, as `numpy.save` does
+///
+/// Arguments:
+///
+/// * `path`: The file to write.
+/// * `arr`: An array of any rank and memory layout, written in logical order.
+pub fn write_npy<P, A, S, D>(path: P, arr: &ArrayBase<S, D>) -> Result<()>
+where
+    P: AsRef<Path>,
+    A: NpyElement,
+    S: Data<Elem = A>,
+    D: Dimension,
+{
+    let path = path.as_ref();
+    fs::write(path, npy_bytes(arr)).map_err(Error::io(path))
+}
+
+/// It reads an array written by `numpy.save` or `write_npy`
+///
+/// Arguments:
+///
+/// * `path`: The `.npy` file.
+///
+/// Returns:
+///
+/// The array, or an `InvalidNpy` error when the file holds another element type
+pub fn read_npy<P, A>(path: P) -> Result<ArrayD<A>>
+where
+    P: AsRef<Path>,
+    A: NpyElement,
+{
+    let path = path.as_ref();
+    let bytes = fs::read(path).map_err(Error::io(path))?;
+    parse_npy(&bytes).map_err(|reason| Error::invalid_npy(path, &reason))
+}
+
+/// A writer of `.npz` archives, as `numpy.savez` and `numpy.savez_compressed` produce.
+pub struct NpzWriter {
+    path: PathBuf,
+    zip: ZipWriter<File>,
+    options: FileOptions,
+}
+
+impl NpzWriter {
+    /// It creates an archive whose arrays are stored uncompressed
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<NpzWriter> {
+        NpzWriter::with_method(path.as_ref(), CompressionMethod::Stored)
+    }
+
+    /// It creates an archive whose arrays are deflated
+    pub fn create_compressed<P: AsRef<Path>>(path: P) -> Result<NpzWriter> {
+        NpzWriter::with_method(path.as_ref(), CompressionMethod::Deflated)
+    }
+
+    fn with_method(path: &Path, method: CompressionMethod) -> Result<NpzWriter> {
+        let file = File::create(path).map_err(Error::io(path))?;
+        Ok(NpzWriter {
+            path: path.to_path_buf(),
+            zip: ZipWriter::new(file),
+            options: FileOptions::default().compression_method(method),
+        })
+    }
+
+    /// It adds an array to the archive
+    ///
+    /// Arguments:
+    ///
+    /// * `name`: The key of the array in numpy, stored as `{name}.npy`.
+    /// * `arr`: An array of any rank and memory layout.
+    pub fn add<A, S, D>(&mut self, name: &str, arr: &ArrayBase<S, D>) -> Result<()>
+    where
+        A: NpyElement,
+        S: Data<Elem = A>,
+        D: Dimension,
+    {
+        self.zip
+            .start_file(format!("{name}.npy"), self.options)
+            .map_err(|source| Error::invalid_npy(&self.path, &source.to_string()))?;
+        self.zip
+            .write_all(&npy_bytes(arr))
+            .map_err(Error::io(&self.path))
+    }
+
+    /// It writes the directory of the archive, which is unreadable until then
+    pub fn finish(mut self) -> Result<()> {
+        self.zip
+            .finish()
+            .map_err(|source| Error::invalid_npy(&self.path, &source.to_string()))?;
+        Ok(())
+    }
+}
+
+/// A reader of `.npz` archives.
+pub struct NpzReader {
+    path: PathBuf,
+    zip: ZipArchive<File>,
+}
+
+impl NpzReader {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<NpzReader> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(Error::io(path))?;
+        let zip = ZipArchive::new(file)
+            .map_err(|source| Error::invalid_npy(path, &source.to_string()))?;
+        Ok(NpzReader {
+            path: path.to_path_buf(),
+            zip,
+        })
+    }
+
+    /// The keys of the arrays in the archive, in no particular order
+    #[must_use]
+    pub fn names(&self) -> Vec<String> {
+        self.zip
+            .file_names()
+            .map(|name| name.strip_suffix(".npy").unwrap_or(name).to_string())
+            .collect()
+    }
+
+    /// It reads one array of the archive
+    ///
+    /// Arguments:
+    ///
+    /// * `name`: The key of the array, with or without its `.npy` suffix.
+    ///
+    /// Returns:
+    ///
+    /// The array, or an `InvalidNpy` error when it is missing or of another element type
+    pub fn by_name<A: NpyElement>(&mut self, name: &str) -> Result<ArrayD<A>> {
+        let entry = if name.ends_with(".npy") {
+            name.to_string()
+        } else {
+            format!("{name}.npy")
+        };
+        let mut file = self
+            .zip
+            .by_name(&entry)
+            .map_err(|source| Error::invalid_npy(&self.path, &format!("`{name}`: {source}")))?;
+
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)
+            .map_err(Error::io(&self.path))?;
+        parse_npy(&bytes)
+            .map_err(|reason| Error::invalid_npy(&self.path, &format!("`{name}`: {reason}")))
+    }
+}
+
+/// How far the logits of two implementations may drift apart, as in numpy's `allclose`.
+///
+/// A logit passes when `|candidate - reference| <= absolute + relative * |reference|`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParityTolerance {
+    pub absolute: f32,
+    pub relative: f32,
+}
+
+impl ParityTolerance {
+    /// Whether a logit is close enough to its reference, never when either is NaN
+    #[must_use]
+    pub fn accepts(&self, expected: f32, found: f32) -> bool {
+        (found - expected).abs() <= self.absolute + self.relative * expected.abs()
+    }
+}
+
+impl Default for ParityTolerance {
+    fn default() -> Self {
+        ParityTolerance {
+            absolute: 1e-3,
+            relative: 1e-3,
+        }
+    }
+}
+
+/// It compares an array with the golden tensor a reference implementation saved with
+/// `numpy.save`, panicking with the first differing element
+///
+/// Arguments:
+///
+/// * `actual`: The output under test.
+/// * `golden`: The `.npy` file of the expected `f32` output.
+/// * `tolerance`: How far the output may drift from the golden tensor.
+#[cfg(test)]
+#[track_caller]
+pub(crate) fn assert_golden<P, S, D>(
+    actual: &ArrayBase<S, D>,
+    golden: P,
+    tolerance: ParityTolerance,
+) where
+    P: AsRef<Path>,
+    S: Data<Elem = f32>,
+    D: Dimension,
+{
+    let golden = golden.as_ref();
+    let expected: ArrayD<f32> = read_npy(golden).unwrap_or_else(|error| panic!("{error}"));
+    assert_eq!(
+        actual.shape(),
+        expected.shape(),
+        "the shape differs from `{}`",
+        golden.display()
+    );
+
+    let mismatch = actual
+        .iter()
+        .zip(expected.indexed_iter())
+        .find(|(&found, (_, &expected))| !tolerance.accepts(expected, found));
+    if let Some((found, (index, expected))) = mismatch {
+        panic!(
+            "`{}` differs at {:?}: expected {expected}, found {found}",
+            golden.display(),
+            index.slice()
+        );
+    }
+}
+
+/// It encodes an array as the bytes of a version 1.0 `.npy` file
+fn npy_bytes<A, S, D>(arr: &ArrayBase<S, D>) -> Vec<u8>
+where
+    A: NpyElement,
+    S: Data<Elem = A>,
+    D: Dimension,
+{
+    let shape = match arr.shape() {
+        [length] => format!("({length},)"),
+        shape => format!(
+            "({})",
+            shape
+                .iter()
+                .map(usize::to_string)
                 .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    let mut header = format!(
+        "{{'descr': '<{}', 'fortran_order': False, 'shape': {shape}, }}",
+        A::TYPE_CODE
+    );
+    // The header is padded with spaces and a newline so the data is 64 byte aligned
+    let unpadded = NPY_MAGIC.len() + 4 + header.len() + 1;
+    header.push_str(&" ".repeat((64 - unpadded % 64) % 64));
+    header.push('\n');
+
+    let mut bytes = Vec::with_capacity(NPY_MAGIC.len() + 4 + header.len() + arr.len() * A::SIZE);
+    bytes.extend_from_slice(NPY_MAGIC);
+    bytes.extend_from_slice(&[1, 0]);
+    bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(header.as_bytes());
+    // `iter` visits the elements in logical order, whatever the strides
+    for &value in arr {
+        value.write_le(&mut bytes);
+    }
+    bytes
+}
+
+/// It decodes the bytes of a `.npy` file, of any version
+fn parse_npy<A: NpyElement>(bytes: &[u8]) -> std::result::Result<ArrayD<A>, String> {
+    let Some(rest) = bytes.strip_prefix(NPY_MAGIC) else {
+        return Err("the file does not start with the npy magic string".to_string());
+    };
+    let (header_len, rest) = match rest {
+        [1, _, a, b, rest @ ..] => (usize::from(u16::from_le_bytes([*a, *b])), rest),
+        [2 | 3, _, a, b, c, d, rest @ ..] => (u32::from_le_bytes([*a, *b, *c, *d]) as usize, rest),
+        _ => return Err("the npy version is not supported".to_string()),
+    };
+    if rest.len() < header_len {
+        return Err("the header is truncated".to_string());
+    }
+    let header = std::str::from_utf8(&rest[..header_len])
+        .map_err(|_| "the header is not text".to_string())?;
+    let data = &rest[header_len..];
+
+    let descr = header_value(header, "descr")?.trim_matches(|c| c == '\'' || c == '"');
+    let little_endian = match descr.split_at(descr.len().min(1)) {
+        ("<", code) if code == A::TYPE_CODE => true,
+        (">", code) if code == A::TYPE_CODE => false,
+        ("=", code) if code == A::TYPE_CODE => cfg!(target_endian = "little"),
+        _ => {
+            return Err(format!(
+                "the data type is `{descr}`, expected `<{}`",
+                A::TYPE_CODE
+            ))
+        }
+    };
+    let fortran_order = header_value(header, "fortran_order")? == "True";
+    let shape = header_value(header, "shape")?
+        .trim_matches(|c| c == '(' || c == ')')
+        .split(',')
+        .map(str::trim)
+        .filter(|dimension| !dimension.is_empty())
+        .map(|dimension| {
+            dimension
+                .parse()
+                .map_err(|_| format!("invalid dimension `{dimension}`"))
         })
-        .collect::<Vec<_>>();
-    rows
+        .collect::<std::result::Result<Vec<usize>, String>>()?;
+
+    let length: usize = shape.iter().product();
+    if data.len() != length * A::SIZE {
+        return Err(format!(
+            "the shape {shape:?} needs {} bytes of data, found {}",
+            length * A::SIZE,
+            data.len()
+        ));
+    }
+    let values = data
+        .chunks_exact(A::SIZE)
+        .map(|bytes| A::from_bytes(bytes, little_endian))
+        .collect();
+
+    let shape = IxDyn(&shape);
+    let arr = if fortran_order {
+        ArrayD::from_shape_vec(shape.f(), values)
+    } else {
+        ArrayD::from_shape_vec(shape, values)
+    };
+    Ok(arr.expect("the data length was just checked"))
+}
+
+/// The raw value of a key of the Python dict literal of a `.npy` header
+fn header_value<'a>(header: &'a str, key: &str) -> std::result::Result<&'a str, String> {
+    let missing = || format!("the header has no `{key}`");
+    let start = header.find(&format!("'{key}':")).ok_or_else(missing)? + key.len() + 3;
+    let value = header[start..].trim_start();
+
+    // A tuple holds commas, so it ends at its closing parenthesis
+    let end = if value.starts_with('(') {
+        value.find(')').map(|end| end + 1)
+    } else {
+        value.find([',', '}'])
+    };
+    Ok(value[..end.ok_or_else(missing)?].trim())
 }
 
 #[cfg(test)]
 mod tests {
-    use ndarray::array;
+    use ndarray::{array, s, Array2};
 
     use super::*;
     #[test]
     fn test_array2() {
-        let arr2: ArrayBase<OwnedRepr<f32>, Dim<[usize; 2]>> =
-            array![[1.1, 2.1, 3.], [3.1, 2.1, 1.], [1.1, 2.1, 3.]];
+        let arr2: Array2<f32> = array![[1.1, 2.1, 3.], [3.1, 2.1, 1.], [1.1, 2.1, 3.]];
         assert_eq!(
-            array2_to_vec(&arr2.into_dyn()),
+            array2_to_vec(&view2(&arr2.into_dyn()).unwrap()),
             vec![vec![1.1, 2.1, 3.], vec![3.1, 2.1, 1.], vec![1.1, 2.1, 3.]]
         )
     }
 
     #[test]
     fn test_array3() {
-        let arr3: ArrayBase<OwnedRepr<f32>, Dim<[usize; 3]>> = array![
+        let arr3 = array![
             [[3.1, 2.1, 1.], [3.1, 2.1, 1.], [3.1, 2.1, 1.]],
             [[1.1, 2.1, 3.], [1.1, 2.1, 3.], [1.1, 2.1, 3.]]
         ];
         assert_eq!(
-            array3_to_vec(&arr3.into_dyn()),
+            array3_to_vec(&view3(&arr3.into_dyn()).unwrap()),
             vec![
                 vec![vec![3.1, 2.1, 1.], vec![3.1, 2.1, 1.], vec![3.1, 2.1, 1.]],
                 vec![vec![1.1, 2.1, 3.], vec![1.1, 2.1, 3.], vec![1.1, 2.1, 3.]]
             ]
         )
     }
+
+    #[test]
+    fn test_non_standard_layouts() {
+        let arr2: Array2<i64> = array![[1, 2, 3], [4, 5, 6]];
+        assert_eq!(
+            array2_to_vec(&arr2.t()),
+            vec![vec![1, 4], vec![2, 5], vec![3, 6]]
+        );
+        assert_eq!(
+            array2_to_vec(&arr2.slice(s![.., ..;2])),
+            vec![vec![1, 3], vec![4, 6]]
+        );
+
+        let arr3 = ArrayD::from_shape_vec(IxDyn(&[2, 2, 2]), (0..8).collect()).unwrap();
+        let arr3 = arr3.permuted_axes(IxDyn(&[1, 0, 2]));
+        assert_eq!(
+            array3_to_vec(&view3(&arr3).unwrap()),
+            vec![vec![vec![0, 1], vec![4, 5]], vec![vec![2, 3], vec![6, 7]]]
+        );
+    }
+
+    #[test]
+    fn test_rows_borrow_contiguous_data() {
+        let arr2: Array2<f32> = array![[1., 2.], [3., 4.]];
+        assert!(rows(arr2.view()).all(|row| matches!(row, Cow::Borrowed(_))));
+        assert!(rows(arr2.t()).all(|row| matches!(row, Cow::Owned(_))));
+
+        let arr3 = arr2.clone().into_shape((1, 2, 2)).unwrap();
+        let sequences: Vec<Vec<Cow<[f32]>>> = tokens(arr3.view()).map(Iterator::collect).collect();
+        assert_eq!(*sequences[0][1], [3., 4.]);
+        assert!(matches!(sequences[0][0], Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_rank_is_checked() {
+        let arr = ArrayD::<f32>::zeros(IxDyn(&[2, 3, 4]));
+        assert!(view3(&arr).is_ok());
+        assert_eq!(
+            view2(&arr).unwrap_err().to_string(),
+            "expected a tensor of rank 2, found shape [2, 3, 4]"
+        );
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("sandbox-rust-{name}"))
+    }
+
+    /// The bytes `numpy.save` writes for a header, with its version 1.0 preamble
+    fn npy_header(header: &str) -> Vec<u8> {
+        let mut bytes = NPY_MAGIC.to_vec();
+        bytes.extend_from_slice(&[1, 0]);
+        bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(header.as_bytes());
+        bytes
+    }
+
+    #[test]
+    fn test_npy_round_trip() {
+        let path = temp_path("logits.npy");
+        let logits: Array2<f32> = array![[1.5, -2.0, 3.25], [0.0, f32::MAX, -0.5]];
+
+        // Transposed views are written in logical order
+        write_npy(&path, &logits.t()).unwrap();
+        let read: ArrayD<f32> = read_npy(&path).unwrap();
+        assert_eq!(read, logits.t().into_dyn());
+
+        let bytes = fs::read(&path).unwrap();
+        assert_eq!((bytes.len() - 6 * 4) % 64, 0);
+        assert!(std::str::from_utf8(&bytes[10..128])
+            .unwrap()
+            .starts_with("{'descr': '<f4', 'fortran_order': False, 'shape': (3, 2), }"));
+
+        let scalar = ArrayD::from_elem(IxDyn(&[]), 7i64);
+        write_npy(&path, &scalar).unwrap();
+        assert_eq!(read_npy::<_, i64>(&path).unwrap(), scalar);
+
+        let error = read_npy::<_, i64>(temp_path("missing.npy")).unwrap_err();
+        assert!(matches!(error, Error::Io { .. }));
+    }
+
+    #[test]
+    fn test_parse_numpy_layouts() {
+        // numpy.asfortranarray(numpy.arange(6).reshape(2, 3)).astype('>i8')
+        let mut bytes = npy_header("{'descr': '>i8', 'fortran_order': True, 'shape': (2, 3), }\n");
+        for value in [0i64, 3, 1, 4, 2, 5] {
+            bytes.extend_from_slice(&value.to_be_bytes());
+        }
+        let arr: ArrayD<i64> = parse_npy(&bytes).unwrap();
+        assert_eq!(arr, array![[0, 1, 2], [3, 4, 5]].into_dyn());
+
+        let mut bytes = npy_header("{'descr': '<f4', 'fortran_order': False, 'shape': (2,), }\n");
+        bytes.extend_from_slice(&1.0f32.to_le_bytes());
+        bytes.extend_from_slice(&2.0f32.to_le_bytes());
+        assert_eq!(
+            parse_npy::<f32>(&bytes).unwrap(),
+            array![1.0, 2.0].into_dyn()
+        );
+
+        assert_eq!(
+            parse_npy::<i64>(&bytes).unwrap_err(),
+            "the data type is `<f4`, expected `<i8`"
+        );
+        assert!(parse_npy::<f32>(&bytes[..bytes.len() - 1])
+            .unwrap_err()
+            .contains("needs 8 bytes of data"));
+    }
+
+    #[test]
+    fn test_npz_round_trip() {
+        let input_ids: Array2<i64> = array![[0, 581, 2], [0, 47, 2]];
+        let logits = Array2::from_shape_fn((2, 3), |(i, j)| (i * 3 + j) as f32 / 10.0);
+
+        for (name, compressed) in [("outputs.npz", false), ("outputs-compressed.npz", true)] {
+            let path = temp_path(name);
+            let mut writer = if compressed {
+                NpzWriter::create_compressed(&path).unwrap()
+            } else {
+                NpzWriter::create(&path).unwrap()
+            };
+            writer.add("input_ids", &input_ids).unwrap();
+            writer.add("logits", &logits.slice(s![.., 1..])).unwrap();
+            writer.finish().unwrap();
+
+            let mut reader = NpzReader::open(&path).unwrap();
+            let mut names = reader.names();
+            names.sort();
+            assert_eq!(names, ["input_ids", "logits"]);
+            assert_eq!(
+                reader.by_name::<i64>("input_ids").unwrap(),
+                input_ids.clone().into_dyn()
+            );
+            assert_eq!(
+                reader.by_name::<f32>("logits.npy").unwrap(),
+                logits.slice(s![.., 1..]).into_dyn()
+            );
+            assert!(reader.by_name::<f32>("input_ids").is_err());
+            assert!(reader.by_name::<f32>("hidden").is_err());
+        }
+    }
+
+    #[test]
+    fn test_assert_golden() {
+        let path = temp_path("golden.npy");
+        let golden = array![[0.25, -1.0], [3.0, 100.0]];
+        write_npy(&path, &golden).unwrap();
+
+        assert_golden(&(&golden + 5e-4), &path, ParityTolerance::default());
+        let drifted = std::panic::catch_unwind(|| {
+            assert_golden(
+                &array![[0.25, -1.0], [3.0, 100.5]],
+                &path,
+                ParityTolerance::default(),
+            )
+        });
+        assert!(drifted.is_err());
+    }
 }